@@ -0,0 +1,285 @@
+//! JSON bodies of the HTTP API under `/api`, for dashboards and tools that want game data
+//! without holding a WebSocket open.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::TimeControl;
+use crate::game::{Outcome, STARTING_FEN};
+use crate::protocol::{GameSummary, MoveRecord};
+use crate::rating::{Category, Rating, RatingChange};
+use crate::storage::{GameFilter, GameRecord, PlayerRecord, ResultFilter, StorageError};
+
+/// Most games a page of `GET /api/games` holds
+pub const MAX_PER_PAGE: usize = 100;
+/// Rating changes `GET /api/players/{name}` shows, newest first
+pub const RATING_HISTORY_LENGTH: usize = 50;
+
+/// A game in a listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GameListing {
+    pub game_code: String,
+    pub white: PlayerRecord,
+    pub black: PlayerRecord,
+    pub time_control: Option<TimeControl>,
+    /// moves played by both sides together
+    pub move_count: usize,
+    /// None while the game is in progress
+    pub outcome: Option<Outcome>,
+    /// milliseconds since the Unix epoch
+    pub started_at: u64,
+    pub updated_at: u64,
+    pub ended_at: Option<u64>,
+}
+
+impl From<&GameRecord> for GameListing {
+    fn from(record: &GameRecord) -> Self {
+        GameListing {
+            game_code: record.game_code.clone(),
+            white: record.white.clone(),
+            black: record.black.clone(),
+            time_control: record.time_control,
+            move_count: record.moves.len(),
+            outcome: record.outcome,
+            started_at: record.started_at,
+            updated_at: record.updated_at,
+            ended_at: record.ended_at,
+        }
+    }
+}
+
+/// Body of `GET /api/games`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GamesPage {
+    pub games: Vec<GameListing>,
+    /// counted from 1
+    pub page: usize,
+    pub per_page: usize,
+    /// games matching the filters, on all pages
+    pub total: u64,
+}
+
+/// Body of `GET /api/games/live`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LiveGames {
+    pub games: Vec<GameSummary>,
+}
+
+/// One move of a game in detail, with the position it leads to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlyRecord {
+    /// counted from 1
+    pub ply: usize,
+    #[serde(flatten)]
+    pub mv: MoveRecord,
+    pub fen: String,
+}
+
+/// Body of `GET /api/games/{code}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GameDetail {
+    #[serde(flatten)]
+    pub game: GameListing,
+    /// the game is being played in a room now
+    pub live: bool,
+    pub initial_fen: String,
+    pub moves: Vec<PlyRecord>,
+}
+
+impl GameDetail {
+    /// Replays the record's moves to describe each one and the position after it
+    pub fn new(record: &GameRecord, live: bool) -> Result<Self, StorageError> {
+        let final_state = record.replay()?;
+        let mut replay = final_state.initial_position();
+        let moves = final_state.history().iter().enumerate().map(|(i, mv)| {
+            let described = MoveRecord::new(&replay, mv);
+            replay.replay_move(mv);
+            PlyRecord { ply: i + 1, mv: described, fen: replay.to_fen() }
+        }).collect();
+        Ok(GameDetail {
+            game: GameListing::from(record),
+            live,
+            initial_fen: record.start_fen.clone().unwrap_or_else(|| STARTING_FEN.to_string()),
+            moves,
+        })
+    }
+}
+
+/// A player's rating in one category, as of the request
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CategoryRating {
+    pub category: Category,
+    #[serde(flatten)]
+    pub rating: Rating,
+    pub provisional: bool,
+}
+
+/// Body of `GET /api/players/{name}`: the categories the player has rated games in, and
+/// their latest rating changes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlayerRatings {
+    pub name: String,
+    pub ratings: Vec<CategoryRating>,
+    pub history: Vec<RatingChange>,
+}
+
+impl PlayerRatings {
+    /// Shows each rating as of `now`, its deviation grown since the player last played
+    pub fn new(name: String, ratings: Vec<(Category, Rating)>, history: Vec<RatingChange>, now: u64) -> Self {
+        let ratings = ratings.into_iter()
+            .map(|(category, rating)| {
+                let rating = rating.decayed(now);
+                CategoryRating { category, rating, provisional: rating.provisional() }
+            })
+            .collect();
+        PlayerRatings { name, ratings, history }
+    }
+}
+
+/// Query string of `GET /api/players/{name}`: `?category=blitz` limits the history to one
+/// category
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct PlayerQuery {
+    pub category: Option<String>,
+}
+
+impl PlayerQuery {
+    pub fn category(&self) -> Result<Option<Category>, ApiError> {
+        self.category.as_deref()
+            .map(|text| {
+                serde_json::from_value::<Category>(serde_json::Value::String(text.to_string()))
+                    .map_err(|_| ApiError::new("bad_request", format!("unknown category {:?}", text)))
+            })
+            .transpose()
+    }
+}
+
+/// Decodes a percent-encoded path segment, such as a player's name; None if it is not
+/// valid UTF-8 once decoded
+pub fn decode_path_segment(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| segment.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Body of every failed API request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ApiError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError { code, message: message.into() }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Query string of `GET /api/games`, e.g. `?player=alice&result=draw&from=2025-01-01&page=2`.
+/// Every field is taken as text so that bad values get a JSON error like everything else.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct GamesQuery {
+    pub player: Option<String>,
+    /// white_wins, black_wins, draw or ongoing
+    pub result: Option<String>,
+    /// first day (UTC) of games to include, as YYYY-MM-DD
+    pub from: Option<String>,
+    /// last day (UTC) of games to include
+    pub to: Option<String>,
+    pub page: Option<String>,
+    pub per_page: Option<String>,
+}
+
+impl GamesQuery {
+    /// The storage filter for the query, and the page number and size it asks for.
+    /// Private games still being played are never listed.
+    pub fn filter(&self) -> Result<(GameFilter, usize, usize), ApiError> {
+        let result = self.result.as_deref()
+            .map(|text| {
+                serde_json::from_value::<ResultFilter>(serde_json::Value::String(text.to_string()))
+                    .map_err(|_| ApiError::new("bad_request", format!("unknown result {:?}", text)))
+            })
+            .transpose()?;
+        let bad_day = |text: &str| ApiError::new("bad_request", format!("dates are YYYY-MM-DD, not {:?}", text));
+        let day = |text: &Option<String>| text.as_deref().map(|text| parse_day(text).ok_or_else(|| bad_day(text))).transpose();
+        let number = |text: &Option<String>, name: &str, default: usize| match text.as_deref() {
+            None => Ok(default),
+            Some(text) => text.parse::<usize>().ok().filter(|&n| n > 0)
+                .ok_or_else(|| ApiError::new("bad_request", format!("{} must be a positive number", name))),
+        };
+        let page = number(&self.page, "page", 1)?;
+        let per_page = number(&self.per_page, "per_page", 20)?.min(MAX_PER_PAGE);
+        let filter = GameFilter {
+            player: self.player.clone().filter(|name| !name.is_empty()),
+            result,
+            started_from: day(&self.from)?,
+            // the whole of the last day counts
+            started_before: self.to.as_deref()
+                .map(|text| parse_day(text).and_then(|start| start.checked_add(DAY_MS)).ok_or_else(|| bad_day(text)))
+                .transpose()?,
+            hide_private_ongoing: true,
+            offset: (page - 1).saturating_mul(per_page),
+            limit: per_page,
+        };
+        Ok((filter, page, per_page))
+    }
+}
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Milliseconds since the Unix epoch at the start (UTC) of a YYYY-MM-DD day
+pub fn parse_day(text: &str) -> Option<u64> {
+    let mut parts = text.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month_length = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=month_length).contains(&day) {
+        return None;
+    }
+    u64::try_from(days_from_civil(year, month, day)?).ok()?.checked_mul(DAY_MS)
+}
+
+/// Converts a Gregorian date into days since 1970-01-01, or `None` if that
+/// many days don't fit
+fn days_from_civil(year: i64, month: u32, day: u32) -> Option<i64> {
+    let year = if month <= 2 { year.checked_sub(1)? } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era.checked_mul(146_097)?.checked_add(doe - 719_468)
+}
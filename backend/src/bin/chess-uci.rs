@@ -0,0 +1,161 @@
+//! Universal Chess Interface front end for the built-in engine, for use with chess GUIs
+//! and tournament managers such as cutechess-cli. Reads commands on stdin, answers on stdout.
+
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use chess::engine::{self, Limits, SearchResult};
+use chess::game::{Color, GameState, MoveError};
+
+/// Time kept in hand when budgeting from the clock, for communication lag
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+/// Writes one line to stdout and flushes it, as GUIs read line by line
+fn send(line: &str) {
+    let mut out = io::stdout().lock();
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+/// A search running on its own thread, which prints `bestmove` when it ends
+struct RunningSearch {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl RunningSearch {
+    /// Stops the search early and waits for its `bestmove`
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
+
+/// Handles `position [startpos | fen <fen>] [moves <uci>...]`
+fn parse_position(args: &[&str]) -> Result<GameState, String> {
+    let moves_at = args.iter().position(|&arg| arg == "moves").unwrap_or(args.len());
+    let mut state = match args.first() {
+        Some(&"startpos") => GameState::new(),
+        Some(&"fen") => GameState::from_fen(&args[1..moves_at].join(" ")).map_err(|err| err.to_string())?,
+        _ => return Err("expected startpos or fen".to_string()),
+    };
+    // the GUI adjudicates the game, so moves are played on past a fivefold repetition or
+    // the 75-move rule, which `Move::from_uci` would refuse as the game being over
+    for uci in args.iter().skip(moves_at + 1) {
+        let mv = state.legal_moves().into_iter().find(|mv| mv.to_uci() == *uci)
+            .ok_or_else(|| format!("{}: {}", uci, MoveError::IllegalMove))?;
+        state.replay_move(&mv);
+    }
+    Ok(state)
+}
+
+/// Turns the arguments of `go` into search limits. A fixed `movetime` wins; otherwise the
+/// time is budgeted from the side to move's clock, spread over `movestogo` moves (or an
+/// assumed 30) plus most of the increment.
+fn parse_go(args: &[&str], turn: Color) -> Limits {
+    let mut limits = Limits::default();
+    let mut clock = None;
+    let mut increment = 0;
+    let mut moves_to_go = 30;
+    let mut infinite = false;
+    for (i, &arg) in args.iter().enumerate() {
+        let value = args.get(i + 1).and_then(|v| v.parse::<u64>().ok());
+        match (arg, turn) {
+            ("depth", _) => limits.depth = value.map(|d| d as u32),
+            ("movetime", _) => limits.time = value.map(Duration::from_millis),
+            ("wtime", Color::White) | ("btime", Color::Black) => clock = value,
+            ("winc", Color::White) | ("binc", Color::Black) => increment = value.unwrap_or(0),
+            ("movestogo", _) => moves_to_go = value.unwrap_or(30).max(1),
+            ("infinite", _) => infinite = true,
+            _ => {}
+        }
+    }
+    if limits.time.is_none() && !infinite && let Some(clock) = clock {
+        let left = Duration::from_millis(clock).saturating_sub(MOVE_OVERHEAD);
+        let budget = Duration::from_millis(clock / moves_to_go + increment * 3 / 4);
+        limits.time = Some(budget.min(left).max(Duration::from_millis(1)));
+    }
+    limits
+}
+
+/// One `info` line for a finished iteration
+fn info_line(result: &SearchResult, started: Instant) -> String {
+    let score = match engine::mate_in(result.score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", result.score),
+    };
+    let millis = started.elapsed().as_millis().max(1);
+    let pv: Vec<String> = result.pv.iter().map(|mv| mv.to_uci()).collect();
+    format!(
+        "info depth {} score {} nodes {} nps {} time {} pv {}",
+        result.depth, score, result.nodes, result.nodes as u128 * 1000 / millis, millis, pv.join(" ")
+    )
+}
+
+fn start_search(state: &GameState, limits: Limits) -> RunningSearch {
+    let stop = Arc::new(AtomicBool::new(false));
+    let state = state.clone();
+    let flag = stop.clone();
+    let handle = thread::spawn(move || {
+        let started = Instant::now();
+        let result = engine::search_with(&state, &limits, &flag, |result| send(&info_line(result, started)));
+        match result.best_move {
+            Some(mv) => send(&format!("bestmove {}", mv.to_uci())),
+            // no legal move: the GUI should not have asked, but it still needs an answer
+            None => send("bestmove 0000"),
+        }
+    });
+    RunningSearch { stop, handle }
+}
+
+fn main() {
+    let mut state = GameState::new();
+    let mut search: Option<RunningSearch> = None;
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else { continue };
+        // a finished search has already printed its bestmove; reap its thread
+        if search.as_ref().is_some_and(|running| running.handle.is_finished()) {
+            search.take().unwrap().stop();
+        }
+        match command {
+            "uci" => {
+                send(&format!("id name Rusty Chess {}", env!("CARGO_PKG_VERSION")));
+                send("id author Rusty Chess contributors");
+                send("uciok");
+            }
+            "isready" => send("readyok"),
+            "ucinewgame" => {
+                if let Some(running) = search.take() {
+                    running.stop();
+                }
+                state = GameState::new();
+            }
+            "position" => match parse_position(args) {
+                Ok(position) => state = position,
+                Err(err) => send(&format!("info string bad position: {}", err)),
+            },
+            "go" => {
+                if let Some(running) = search.take() {
+                    running.stop();
+                }
+                search = Some(start_search(&state, parse_go(args, state.turn())));
+            }
+            "stop" => {
+                if let Some(running) = search.take() {
+                    running.stop();
+                }
+            }
+            "quit" => break,
+            // debug, setoption, register and ponderhit need no action
+            _ => {}
+        }
+    }
+    if let Some(running) = search.take() {
+        running.stop();
+    }
+}
@@ -0,0 +1,283 @@
+use crate::game::{Color, PieceType};
+use std::sync::LazyLock;
+
+/// One bit per square, bit 0 = a1 ... bit 63 = h8 (same indexing as the board vector)
+pub type Bitboard = u64;
+
+pub const fn bit(sq: u8) -> Bitboard {
+    1u64 << sq
+}
+
+/// Iterates the set squares of a bitboard, lowest index first
+pub struct Squares(Bitboard);
+
+impl Iterator for Squares {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.0 == 0 {
+            return None;
+        }
+        let sq = self.0.trailing_zeros() as u8;
+        self.0 &= self.0 - 1;
+        Some(sq)
+    }
+}
+
+pub fn squares(bb: Bitboard) -> Squares {
+    Squares(bb)
+}
+
+// ---------- Leaper tables ----------
+
+const KNIGHT_DELTAS: [(i8, i8); 8] = [(1, 2), (2, 1), (-1, 2), (-2, 1), (1, -2), (2, -1), (-1, -2), (-2, -1)];
+const KING_DELTAS: [(i8, i8); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (-1, 1), (1, -1), (-1, -1)];
+const WHITE_PAWN_DELTAS: [(i8, i8); 2] = [(-1, 1), (1, 1)];
+const BLACK_PAWN_DELTAS: [(i8, i8); 2] = [(-1, -1), (1, -1)];
+
+const fn leaper_table(deltas: &[(i8, i8)]) -> [Bitboard; 64] {
+    let mut table = [0u64; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let x = (sq % 8) as i8;
+        let y = (sq / 8) as i8;
+        let mut i = 0;
+        while i < deltas.len() {
+            let nx = x + deltas[i].0;
+            let ny = y + deltas[i].1;
+            if nx >= 0 && nx < 8 && ny >= 0 && ny < 8 {
+                table[sq] |= 1u64 << (ny * 8 + nx);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    table
+}
+
+pub static KNIGHT_ATTACKS: [Bitboard; 64] = leaper_table(&KNIGHT_DELTAS);
+pub static KING_ATTACKS: [Bitboard; 64] = leaper_table(&KING_DELTAS);
+static PAWN_ATTACKS: [[Bitboard; 64]; 2] = [leaper_table(&WHITE_PAWN_DELTAS), leaper_table(&BLACK_PAWN_DELTAS)];
+
+/// Squares a pawn of `color` standing on `sq` attacks
+pub fn pawn_attacks(color: Color, sq: u8) -> Bitboard {
+    PAWN_ATTACKS[color_index(color)][sq as usize]
+}
+
+// ---------- Sliding pieces (magic bitboards) ----------
+
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (-1, 1), (1, -1), (-1, -1)];
+
+/// Attacks from `sq` along `directions`, stopping at (and including) the first blocker
+fn ray_attacks(sq: u8, occupied: Bitboard, directions: &[(i8, i8)]) -> Bitboard {
+    let mut attacks = 0;
+    for &(dx, dy) in directions {
+        let mut x = (sq % 8) as i8 + dx;
+        let mut y = (sq / 8) as i8 + dy;
+        while (0..8).contains(&x) && (0..8).contains(&y) {
+            let b = bit((y * 8 + x) as u8);
+            attacks |= b;
+            if occupied & b != 0 {
+                break;
+            }
+            x += dx;
+            y += dy;
+        }
+    }
+    attacks
+}
+
+/// Squares whose occupancy matters for a slider on `sq`: its rays minus the board edge they run into
+fn relevant_mask(sq: u8, directions: &[(i8, i8)]) -> Bitboard {
+    let mut mask = 0;
+    for &(dx, dy) in directions {
+        let mut x = (sq % 8) as i8 + dx;
+        let mut y = (sq / 8) as i8 + dy;
+        while (0..8).contains(&(x + dx)) && (0..8).contains(&(y + dy)) {
+            mask |= bit((y * 8 + x) as u8);
+            x += dx;
+            y += dy;
+        }
+    }
+    mask
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Magic {
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupied: Bitboard) -> usize {
+        self.offset + (((occupied & self.mask).wrapping_mul(self.magic)) >> self.shift) as usize
+    }
+}
+
+struct SliderTables {
+    rook: Vec<Magic>,
+    bishop: Vec<Magic>,
+    attacks: Vec<Bitboard>,
+}
+
+// Magic multipliers, found once offline with a fixed-seed xorshift search over sparse
+// random numbers. Each maps every blocker subset of a square's mask to a distinct
+// (or attack-equivalent) slot of a 2^bits table.
+const ROOK_MAGICS: [u64; 64] = [
+    0x1080004008801020, 0x0840092002C03000, 0x1900200010400900, 0x0880100008000480,
+    0x4200100420080200, 0x8100020100080400, 0x0200040110886200, 0x0200008040220411,
+    0x0404800084400220, 0x0000401000402000, 0x0086001081220440, 0x0408800800100280,
+    0x000A001201040820, 0x8848800200840080, 0x4001000100040200, 0x0442000102105084,
+    0x9080010020804100, 0x0040404000201009, 0x0000808010002009, 0x2200090021D00100,
+    0x0008008008040080, 0x0004004002010040, 0x0011040008015042, 0x00000A0001768104,
+    0x0000800080204009, 0x2010004140002001, 0x9800200280100080, 0x1000100080080080,
+    0x0442000A00049020, 0x2100040080020080, 0x0800120400900148, 0x0010040A00128541,
+    0x2800804000800030, 0x1010002000400041, 0x4000200011004100, 0x0610008410800800,
+    0x0400802402800800, 0xC100020080800400, 0x0002000802000401, 0x0182085882000401,
+    0x0220204000808000, 0x2860100040024022, 0x0001002004110040, 0x99101042000A0020,
+    0x0004080004008080, 0x0010040002008080, 0x2012004881020004, 0x8300842444820011,
+    0x0088403882010200, 0x0820400080210100, 0x0110910040A00300, 0x0801100280080480,
+    0x0242009008200600, 0x1002000489500200, 0x0040800200010080, 0x0091800041000080,
+    0x0000209300488001, 0x04C1002414824001, 0x020020000B001041, 0x7000100004200901,
+    0x8002002004100802, 0x30010002084C0007, 0x0888221800813004, 0x4000002840840112,
+];
+
+const BISHOP_MAGICS: [u64; 64] = [
+    0xA010041108003100, 0x006082020A002900, 0x6810010619200000, 0x08281A0520000408,
+    0x0001104001000400, 0x0018901008048400, 0x00040A0210245280, 0x000200210808A402,
+    0x9140048410821200, 0x0800091010820041, 0x20504804832202C0, 0x0100091401081000,
+    0x8021011140000012, 0x0810020804450400, 0x208B0542109008A2, 0x0080084A08040204,
+    0x0040E2A80811244C, 0x2505022008008108, 0x0430220100420040, 0x010A040420220040,
+    0x1105000290400000, 0x0093001200822120, 0x4000A62048043004, 0x280120048A015004,
+    0x006090002A020814, 0x44042000240800D0, 0x01102800040A4400, 0x1004080080220040,
+    0x0001001011004024, 0x0010044000805040, 0x0914041200820100, 0x0004821012821480,
+    0x0024040500C05021, 0x0088611002080200, 0x0116080A00040020, 0x4000020080080080,
+    0x2450450140840040, 0x0000880201484100, 0x0222020404020092, 0x8081110600002E00,
+    0x2842101105000801, 0x1100809008001025, 0x00020202221C0400, 0x0422014022009020,
+    0x0210046102100C00, 0xC004008082029102, 0x00AA461801101200, 0x0404080080201108,
+    0x020542108C205002, 0x0410544804100100, 0x0040910841100000, 0x0400200042021100,
+    0x00004204850400C0, 0x0200100410A42102, 0x1040020801210102, 0x0805040410420000,
+    0x2884804130100200, 0x800C262201242000, 0x1058000194108800, 0x0014221054420204,
+    0x0104000012A02200, 0x0200881003300100, 0x0140400202840100, 0x0402020801010201,
+];
+
+/// Builds one square's magic entry and appends its attack table to `attacks`
+fn init_magic(sq: u8, magic: u64, directions: &[(i8, i8)], attacks: &mut Vec<Bitboard>) -> Magic {
+    let mask = relevant_mask(sq, directions);
+    let bits = mask.count_ones();
+    let entry = Magic { mask, magic, shift: 64 - bits, offset: attacks.len() };
+    attacks.resize(attacks.len() + (1usize << bits), 0);
+    // enumerate every subset of the mask (carry-rippler)
+    let mut subset: Bitboard = 0;
+    loop {
+        let idx = entry.index(subset);
+        let reference = ray_attacks(sq, subset, directions);
+        debug_assert!(attacks[idx] == 0 || attacks[idx] == reference, "bad magic for square {}", sq);
+        attacks[idx] = reference;
+        subset = subset.wrapping_sub(mask) & mask;
+        if subset == 0 {
+            break;
+        }
+    }
+    entry
+}
+
+impl SliderTables {
+    fn new() -> Self {
+        let mut attacks = Vec::new();
+        let rook = (0..64).map(|sq| init_magic(sq, ROOK_MAGICS[sq as usize], &ROOK_DIRECTIONS, &mut attacks)).collect();
+        let bishop = (0..64).map(|sq| init_magic(sq, BISHOP_MAGICS[sq as usize], &BISHOP_DIRECTIONS, &mut attacks)).collect();
+        SliderTables { rook, bishop, attacks }
+    }
+}
+
+static SLIDERS: LazyLock<SliderTables> = LazyLock::new(SliderTables::new);
+
+pub fn rook_attacks(sq: u8, occupied: Bitboard) -> Bitboard {
+    let tables = &*SLIDERS;
+    tables.attacks[tables.rook[sq as usize].index(occupied)]
+}
+
+pub fn bishop_attacks(sq: u8, occupied: Bitboard) -> Bitboard {
+    let tables = &*SLIDERS;
+    tables.attacks[tables.bishop[sq as usize].index(occupied)]
+}
+
+pub fn queen_attacks(sq: u8, occupied: Bitboard) -> Bitboard {
+    rook_attacks(sq, occupied) | bishop_attacks(sq, occupied)
+}
+
+// ---------- Position ----------
+
+pub(crate) fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+pub(crate) fn piece_index(piece_type: PieceType) -> usize {
+    match piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+    }
+}
+
+/// Piece placement as one bitboard per color and piece type, kept alongside the
+/// square-list board for fast attack and legality tests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bitboards {
+    pieces: [[Bitboard; 6]; 2],
+    by_color: [Bitboard; 2],
+}
+
+impl Bitboards {
+    pub fn put(&mut self, sq: u8, color: Color, piece_type: PieceType) {
+        self.pieces[color_index(color)][piece_index(piece_type)] |= bit(sq);
+        self.by_color[color_index(color)] |= bit(sq);
+    }
+
+    pub fn remove(&mut self, sq: u8, color: Color, piece_type: PieceType) {
+        self.pieces[color_index(color)][piece_index(piece_type)] &= !bit(sq);
+        self.by_color[color_index(color)] &= !bit(sq);
+    }
+
+    pub fn pieces(&self, color: Color, piece_type: PieceType) -> Bitboard {
+        self.pieces[color_index(color)][piece_index(piece_type)]
+    }
+
+    pub fn occupied_by(&self, color: Color) -> Bitboard {
+        self.by_color[color_index(color)]
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.by_color[0] | self.by_color[1]
+    }
+
+    pub fn king_square(&self, color: Color) -> Option<u8> {
+        squares(self.pieces(color, PieceType::King)).next()
+    }
+
+    /// true if any piece of `attacker` attacks `sq` with the current occupancy
+    pub fn is_attacked(&self, sq: u8, attacker: Color) -> bool {
+        let occupied = self.occupied();
+        let defender = match attacker {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        };
+        let queens = self.pieces(attacker, PieceType::Queen);
+        // a pawn of the defending color on `sq` would attack exactly the squares attacking pawns stand on
+        pawn_attacks(defender, sq) & self.pieces(attacker, PieceType::Pawn) != 0
+            || KNIGHT_ATTACKS[sq as usize] & self.pieces(attacker, PieceType::Knight) != 0
+            || KING_ATTACKS[sq as usize] & self.pieces(attacker, PieceType::King) != 0
+            || bishop_attacks(sq, occupied) & (self.pieces(attacker, PieceType::Bishop) | queens) != 0
+            || rook_attacks(sq, occupied) & (self.pieces(attacker, PieceType::Rook) | queens) != 0
+    }
+}
@@ -0,0 +1,162 @@
+//! Chess clocks for timed games. The server keeps the time; clients are told how much
+//! each side has left.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{opposite_color, Color};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The most time each side may start with in a Fischer or Bronstein game
+pub const MAX_BASE_MS: u64 = 24 * 60 * 60 * 1000;
+/// The largest increment or delay a move may earn
+pub const MAX_EXTRA_MS: u64 = 60 * 60 * 1000;
+/// The longest a correspondence move may take
+pub const MAX_DAYS_PER_MOVE: u32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimeControl {
+    /// `base_ms` each, and `increment_ms` added after every move
+    Fischer { base_ms: u64, increment_ms: u64 },
+    /// `base_ms` each; after every move the time it took, up to `delay_ms`, is given back
+    Bronstein { base_ms: u64, delay_ms: u64 },
+    /// `days_per_move` for each move; unused time does not carry over
+    Correspondence { days_per_move: u32 },
+}
+
+impl TimeControl {
+    /// Whether both sides get some time to move, and no more than the limits above
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            TimeControl::Fischer { base_ms, increment_ms: extra_ms } | TimeControl::Bronstein { base_ms, delay_ms: extra_ms } => {
+                (1..=MAX_BASE_MS).contains(&base_ms) && extra_ms <= MAX_EXTRA_MS
+            }
+            TimeControl::Correspondence { days_per_move } => (1..=MAX_DAYS_PER_MOVE).contains(&days_per_move),
+        }
+    }
+
+    /// Time on each clock at the start of the game
+    fn initial(&self) -> Duration {
+        match *self {
+            TimeControl::Fischer { base_ms, .. } | TimeControl::Bronstein { base_ms, .. } => Duration::from_millis(base_ms),
+            TimeControl::Correspondence { days_per_move } => DAY * days_per_move,
+        }
+    }
+}
+
+fn slot(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+/// A two-sided clock. Neither side's time runs until the first move has been made.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    control: TimeControl,
+    /// time left for white and black, as of the last press
+    remaining: [Duration; 2],
+    /// the side whose time is running, and since when
+    running: Option<(Color, Instant)>,
+    /// the side that ran out of time, once one has
+    flagged: Option<Color>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Clock { control, remaining: [control.initial(); 2], running: None, flagged: None }
+    }
+
+    /// A clock with the given time left on each side, as saved from an earlier one, with
+    /// `running`'s time running from `now`
+    pub fn restore(control: TimeControl, white: Duration, black: Duration, running: Option<Color>, now: Instant) -> Self {
+        Clock { control, remaining: [white, black], running: running.map(|side| (side, now)), flagged: None }
+    }
+
+    pub fn control(&self) -> TimeControl {
+        self.control
+    }
+
+    /// Time `color` has left at `now`
+    pub fn remaining(&self, color: Color, now: Instant) -> Duration {
+        let left = self.remaining[slot(color)];
+        match self.running {
+            Some((side, since)) if side == color => left.saturating_sub(now.saturating_duration_since(since)),
+            _ => left,
+        }
+    }
+
+    /// The side whose time is running
+    pub fn running(&self) -> Option<Color> {
+        self.running.map(|(side, _)| side)
+    }
+
+    pub fn flagged(&self) -> Option<Color> {
+        self.flagged
+    }
+
+    /// When the running side's flag falls
+    pub fn deadline(&self) -> Option<Instant> {
+        self.running.map(|(side, since)| since + self.remaining[slot(side)])
+    }
+
+    /// Records that `mover` completed a move at `now` and starts the opponent's time,
+    /// applying the increment or delay. The first press starts the clock. Fails with the
+    /// flagged side if `mover` had already run out, in which case the move does not count.
+    pub fn press(&mut self, mover: Color, now: Instant) -> Result<(), Color> {
+        if let Some(flagged) = self.flagged.or_else(|| self.check_flag(now)) {
+            return Err(flagged);
+        }
+        if let Some((side, since)) = self.running
+            && side == mover
+        {
+            let used = now.saturating_duration_since(since);
+            let left = &mut self.remaining[slot(mover)];
+            *left = left.saturating_sub(used);
+            match self.control {
+                TimeControl::Fischer { increment_ms, .. } => *left += Duration::from_millis(increment_ms),
+                TimeControl::Bronstein { delay_ms, .. } => *left += used.min(Duration::from_millis(delay_ms)),
+                TimeControl::Correspondence { .. } => *left = self.control.initial(),
+            }
+        }
+        self.running = Some((opposite_color(mover), now));
+        Ok(())
+    }
+
+    /// Flags the running side if its time is up at `now`, returning it
+    pub fn check_flag(&mut self, now: Instant) -> Option<Color> {
+        if self.flagged.is_none()
+            && let Some(side) = self.running()
+            && self.remaining(side, now).is_zero()
+        {
+            self.remaining[slot(side)] = Duration::ZERO;
+            self.running = None;
+            self.flagged = Some(side);
+        }
+        self.flagged
+    }
+
+    /// Gives the move to `side` without increment or delay, as after a takeback. The
+    /// time used so far still counts against the side whose clock was running.
+    pub fn hand_to(&mut self, side: Color, now: Instant) {
+        if self.flagged.is_some() {
+            return;
+        }
+        if let Some((running, _)) = self.running {
+            self.remaining[slot(running)] = self.remaining(running, now);
+            self.running = Some((side, now));
+        }
+    }
+
+    /// Stops the clock for good, as when the game ends
+    pub fn stop(&mut self, now: Instant) {
+        if let Some(side) = self.running() {
+            self.remaining[slot(side)] = self.remaining(side, now);
+            self.running = None;
+        }
+    }
+}
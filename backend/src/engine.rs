@@ -0,0 +1,404 @@
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::bitboard::{piece_index, squares};
+use crate::game::{Color, GameState, Move, PieceType};
+
+/// Score of being mated right now; mate in n plies scores MATE - n
+pub const MATE: i32 = 30_000;
+/// Scores beyond this are mates rather than material
+pub const MATE_BOUND: i32 = MATE - 1_000;
+
+const MAX_PLY: usize = 128;
+/// Slots in the best-move table used for move ordering (a power of two)
+const TABLE_SIZE: usize = 1 << 16;
+/// How many nodes to search between clock checks
+const CHECK_INTERVAL: u64 = 2048;
+
+/// When to stop searching. With neither limit set the search runs to `MAX_DEPTH`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub time: Option<Duration>,
+}
+
+impl Limits {
+    pub const MAX_DEPTH: u32 = 64;
+
+    /// Search exactly `depth` plies (plus quiescence)
+    pub fn depth(depth: u32) -> Self {
+        Limits { depth: Some(depth), time: None }
+    }
+
+    /// Search as deep as possible within `time`
+    pub fn time(time: Duration) -> Self {
+        Limits { depth: None, time: Some(time) }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    /// None only when the side to move has no legal move
+    pub best_move: Option<Move>,
+    /// centipawns from the side to move's point of view, or a mate score
+    pub score: i32,
+    /// deepest iteration that completed
+    pub depth: u32,
+    pub nodes: u64,
+    /// expected line of play, starting with `best_move`
+    pub pv: Vec<Move>,
+}
+
+/// Finds the best move for the side to move within `limits`
+pub fn search(state: &GameState, limits: &Limits) -> SearchResult {
+    search_with(state, limits, &AtomicBool::new(false), |_| {})
+}
+
+/// Like `search`, but also gives up as soon as `stop` is set, and hands the result of
+/// every finished iteration to `report`
+pub fn search_with(state: &GameState, limits: &Limits, stop: &AtomicBool, report: impl FnMut(&SearchResult)) -> SearchResult {
+    Searcher::new(limits, stop).run(state, report)
+}
+
+/// Full moves until mate for a mate score (negative when the side to move is getting
+/// mated), or None for an ordinary score
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score >= MATE_BOUND {
+        Some((MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        Some(-(MATE + score) / 2)
+    } else {
+        None
+    }
+}
+
+// ----------- Evaluation -----------
+
+const PIECE_VALUES: [i32; 6] = [100, 320, 330, 500, 900, 0];
+
+// Piece-square tables from white's point of view, written with rank 8 first so they
+// read like a board diagram. Index with `sq ^ 56` for white and `sq` for black.
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+const KING_MIDDLEGAME_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+const KING_ENDGAME_TABLE: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+const PIECE_TYPES: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King,
+];
+
+/// Game phase weights: 24 with all pieces on the board, 0 with only kings and pawns
+const PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
+const MAX_PHASE: i32 = 24;
+
+fn piece_value(piece_type: PieceType) -> i32 {
+    PIECE_VALUES[piece_index(piece_type)]
+}
+
+/// Static evaluation in centipawns from the side to move's point of view: material plus
+/// piece-square bonuses, with the king table blended from middlegame to endgame
+pub fn evaluate(state: &GameState) -> i32 {
+    let bitboards = state.bitboards();
+    let mut phase = 0;
+    for piece_type in PIECE_TYPES {
+        let count = bitboards.pieces(Color::White, piece_type).count_ones()
+            + bitboards.pieces(Color::Black, piece_type).count_ones();
+        phase += PHASE_WEIGHTS[piece_index(piece_type)] * count as i32;
+    }
+    let phase = phase.min(MAX_PHASE);
+
+    let mut score = 0;
+    for color in [Color::White, Color::Black] {
+        let sign = if color == Color::White { 1 } else { -1 };
+        let flip = if color == Color::White { 56 } else { 0 };
+        for piece_type in PIECE_TYPES {
+            for sq in squares(bitboards.pieces(color, piece_type)) {
+                let idx = (sq ^ flip) as usize;
+                let positional = match piece_type {
+                    PieceType::Pawn => PAWN_TABLE[idx],
+                    PieceType::Knight => KNIGHT_TABLE[idx],
+                    PieceType::Bishop => BISHOP_TABLE[idx],
+                    PieceType::Rook => ROOK_TABLE[idx],
+                    PieceType::Queen => QUEEN_TABLE[idx],
+                    PieceType::King => {
+                        (KING_MIDDLEGAME_TABLE[idx] * phase + KING_ENDGAME_TABLE[idx] * (MAX_PHASE - phase)) / MAX_PHASE
+                    }
+                };
+                score += sign * (piece_value(piece_type) + positional);
+            }
+        }
+    }
+    if state.turn() == Color::White { score } else { -score }
+}
+
+// ----------- Search -----------
+
+struct Searcher<'a> {
+    max_depth: u32,
+    deadline: Option<Instant>,
+    /// set by another thread to end the search early
+    stop: &'a AtomicBool,
+    nodes: u64,
+    /// iteration in progress; the first one is never cut short
+    iteration: u32,
+    /// set once the deadline passes; the iteration in progress is then thrown away
+    stopped: bool,
+    /// two quiet moves per ply that caused a beta cutoff
+    killers: [[Option<Move>; 2]; MAX_PLY],
+    /// best move found in each position, keyed by the low bits of its hash
+    best_moves: Vec<Option<(u64, Move)>>,
+}
+
+impl<'a> Searcher<'a> {
+    fn new(limits: &Limits, stop: &'a AtomicBool) -> Self {
+        Searcher {
+            max_depth: limits.depth.unwrap_or(Limits::MAX_DEPTH).clamp(1, Limits::MAX_DEPTH),
+            deadline: limits.time.map(|time| Instant::now() + time),
+            stop,
+            nodes: 0,
+            iteration: 0,
+            stopped: false,
+            killers: [[None; 2]; MAX_PLY],
+            best_moves: vec![None; TABLE_SIZE],
+        }
+    }
+
+    /// Iterative deepening: search depth 1, 2, ... keeping the result of the last
+    /// iteration that finished. Depth 1 always finishes so there is always a move.
+    fn run(&mut self, state: &GameState, mut report: impl FnMut(&SearchResult)) -> SearchResult {
+        let mut state = state.clone();
+        let mut result = SearchResult { best_move: None, score: 0, depth: 0, nodes: 0, pv: Vec::new() };
+        for depth in 1..=self.max_depth {
+            self.iteration = depth;
+            let score = self.negamax(&mut state, depth, 0, -MATE, MATE);
+            if self.stopped {
+                break;
+            }
+            result.pv = self.principal_variation(&mut state, depth);
+            result.best_move = result.pv.first().copied();
+            result.score = score;
+            result.depth = depth;
+            result.nodes = self.nodes;
+            report(&result);
+            // no point searching deeper once a forced mate is found
+            if score.abs() >= MATE_BOUND {
+                break;
+            }
+        }
+        result.nodes = self.nodes;
+        result
+    }
+
+    /// Polls the clock and the stop flag every `CHECK_INTERVAL` nodes, never during the
+    /// first iteration
+    fn out_of_time(&mut self) -> bool {
+        self.nodes += 1;
+        if !self.stopped && self.iteration > 1 && self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            let past_deadline = self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            self.stopped = past_deadline || self.stop.load(Ordering::Relaxed);
+        }
+        self.stopped
+    }
+
+    /// Follows the best-move table from the root for up to `depth` plies
+    fn principal_variation(&self, state: &mut GameState, depth: u32) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut undos = Vec::new();
+        while pv.len() < depth as usize {
+            // a table slot may have been overwritten by another position's move
+            let Some(mv) = self.table_move(state).filter(|mv| state.legal_moves().contains(mv)) else { break };
+            undos.push(state.make_move(&mv));
+            pv.push(mv);
+        }
+        for (mv, undo) in pv.iter().zip(undos).rev() {
+            state.unmake_move(mv, undo);
+        }
+        pv
+    }
+
+    fn negamax(&mut self, state: &mut GameState, depth: u32, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+        if ply > 0 && (state.repetition_count() >= 2 || state.is_fifty_move_draw() || state.is_insufficient_material()) {
+            return 0;
+        }
+        if depth == 0 || ply >= MAX_PLY - 1 {
+            return self.quiescence(state, ply, alpha, beta);
+        }
+        let mut moves = state.legal_moves();
+        if moves.is_empty() {
+            return if state.is_in_check() { -MATE + ply as i32 } else { 0 };
+        }
+        self.order_moves(state, &mut moves, ply);
+
+        let mut best_score = -MATE;
+        let mut best_move = moves[0];
+        for mv in moves {
+            let quiet = !is_capture(state, &mv) && mv.promotion().is_none();
+            let undo = state.make_move(&mv);
+            let score = -self.negamax(state, depth - 1, ply + 1, -beta, -alpha);
+            state.unmake_move(&mv, undo);
+            if self.stopped {
+                return 0;
+            }
+            if score > best_score {
+                best_score = score;
+                best_move = mv;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+            if alpha >= beta {
+                if quiet && self.killers[ply][0] != Some(mv) {
+                    self.killers[ply][1] = self.killers[ply][0];
+                    self.killers[ply][0] = Some(mv);
+                }
+                break;
+            }
+        }
+        self.best_moves[state.hash() as usize % TABLE_SIZE] = Some((state.hash(), best_move));
+        best_score
+    }
+
+    /// Searches captures and promotions until the position is quiet, so the static
+    /// evaluation is never taken in the middle of an exchange
+    fn quiescence(&mut self, state: &mut GameState, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+        let stand_pat = evaluate(state);
+        if stand_pat >= beta || ply >= MAX_PLY - 1 {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+        let mut moves: Vec<Move> = state.legal_moves().into_iter()
+            .filter(|mv| is_capture(state, mv) || mv.promotion() == Some(PieceType::Queen))
+            .collect();
+        moves.sort_by_cached_key(|mv| Reverse(capture_order(state, mv)));
+        for mv in moves {
+            let undo = state.make_move(&mv);
+            let score = -self.quiescence(state, ply + 1, -beta, -alpha);
+            state.unmake_move(&mv, undo);
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    fn table_move(&self, state: &GameState) -> Option<Move> {
+        match self.best_moves[state.hash() as usize % TABLE_SIZE] {
+            Some((hash, mv)) if hash == state.hash() => Some(mv),
+            _ => None,
+        }
+    }
+
+    /// Best move from an earlier iteration first, then captures (most valuable victim,
+    /// least valuable attacker), promotions, killer moves, and the remaining quiet moves
+    fn order_moves(&self, state: &GameState, moves: &mut [Move], ply: usize) {
+        let table_move = self.table_move(state);
+        let killers = self.killers[ply];
+        moves.sort_by_cached_key(|mv| {
+            let score = if Some(*mv) == table_move {
+                1_000_000
+            } else if is_capture(state, mv) {
+                100_000 + capture_order(state, mv)
+            } else if let Some(promotion) = mv.promotion() {
+                90_000 + piece_value(promotion)
+            } else if killers.contains(&Some(*mv)) {
+                80_000
+            } else {
+                0
+            };
+            Reverse(score)
+        });
+    }
+}
+
+fn is_capture(state: &GameState, mv: &Move) -> bool {
+    mv.is_en_passant() || state.piece_type_at(mv.to() as usize).is_some()
+}
+
+/// MVV-LVA: prefer taking the most valuable piece with the least valuable one
+fn capture_order(state: &GameState, mv: &Move) -> i32 {
+    let victim = state.piece_type_at(mv.to() as usize).map_or(piece_value(PieceType::Pawn), piece_value);
+    let attacker = state.piece_type_at(mv.from() as usize).map_or(0, piece_value);
+    victim * 10 - attacker / 10
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::time::timeout;
+
+use crate::game::{GameState, Move};
+
+/// How long the engine gets to answer `uci` and `isready`
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Slack on top of the move budget before the engine is told to stop
+const MOVE_GRACE: Duration = Duration::from_millis(500);
+/// How long the engine gets to reply to `stop` or `quit`
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub enum EngineError {
+    /// the executable could not be started
+    Spawn(io::Error),
+    /// the engine exited or closed its output
+    Crashed,
+    /// no answer within the time allowed
+    Timeout,
+    /// the engine answered with a move that is not legal here
+    BadMove(String),
+}
+
+impl EngineError {
+    /// Machine-readable code for error replies
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::Spawn(_) => "engine_unavailable",
+            EngineError::Crashed => "engine_crashed",
+            EngineError::Timeout => "engine_timeout",
+            EngineError::BadMove(_) => "engine_illegal_move",
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Spawn(err) => write!(f, "could not start engine: {}", err),
+            EngineError::Crashed => f.write_str("engine process exited"),
+            EngineError::Timeout => f.write_str("engine did not answer in time"),
+            EngineError::BadMove(mv) => write!(f, "engine played illegal move {}", mv),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+struct EngineProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl EngineProcess {
+    async fn send(&mut self, command: &str) -> Result<(), EngineError> {
+        let line = format!("{}\n", command);
+        self.stdin.write_all(line.as_bytes()).await.map_err(|_| EngineError::Crashed)?;
+        self.stdin.flush().await.map_err(|_| EngineError::Crashed)
+    }
+
+    /// Reads lines until one starts with `prefix`, returning it
+    async fn wait_for(&mut self, prefix: &str) -> Result<String, EngineError> {
+        loop {
+            match self.stdout.next_line().await {
+                Ok(Some(line)) if line.starts_with(prefix) => return Ok(line),
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => return Err(EngineError::Crashed),
+            }
+        }
+    }
+
+    async fn wait_for_within(&mut self, prefix: &str, limit: Duration) -> Result<String, EngineError> {
+        timeout(limit, self.wait_for(prefix)).await.unwrap_or(Err(EngineError::Timeout))
+    }
+}
+
+/// A UCI engine executable used as an opponent. The process is started on first use,
+/// restarted if it crashes or hangs, and killed when this value is shut down or dropped.
+pub struct ExternalEngine {
+    path: PathBuf,
+    process: Option<EngineProcess>,
+    /// how many times the process has been (re)started
+    starts: u32,
+}
+
+impl ExternalEngine {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ExternalEngine { path: path.into(), process: None, starts: 0 }
+    }
+
+    /// How many times the engine process has been started, including restarts
+    pub fn starts(&self) -> u32 {
+        self.starts
+    }
+
+    /// Starts the engine and waits for it to finish the UCI handshake
+    async fn start(&mut self) -> Result<&mut EngineProcess, EngineError> {
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(EngineError::Spawn)?;
+        self.starts += 1;
+        let stdin = child.stdin.take().ok_or(EngineError::Crashed)?;
+        let stdout = BufReader::new(child.stdout.take().ok_or(EngineError::Crashed)?).lines();
+        let mut process = EngineProcess { child, stdin, stdout };
+        process.send("uci").await?;
+        process.wait_for_within("uciok", HANDSHAKE_TIMEOUT).await?;
+        process.send("isready").await?;
+        process.wait_for_within("readyok", HANDSHAKE_TIMEOUT).await?;
+        Ok(self.process.insert(process))
+    }
+
+    /// The running process, starting a new one if there is none or the old one exited
+    async fn running(&mut self) -> Result<&mut EngineProcess, EngineError> {
+        let alive = match self.process.as_mut() {
+            Some(process) => matches!(process.child.try_wait(), Ok(None)),
+            None => false,
+        };
+        if alive {
+            return Ok(self.process.as_mut().unwrap());
+        }
+        self.process = None;
+        self.start().await
+    }
+
+    /// Asks the engine for its move in `state`, allowing it `budget` to think. If the
+    /// process has died, or dies or hangs while thinking, it is restarted and asked once more.
+    pub async fn best_move(&mut self, state: &GameState, budget: Duration) -> Result<Move, EngineError> {
+        match self.try_best_move(state, budget).await {
+            Err(EngineError::Crashed | EngineError::Timeout) => {
+                self.kill().await;
+                self.try_best_move(state, budget).await
+            }
+            result => result,
+        }
+    }
+
+    async fn try_best_move(&mut self, state: &GameState, budget: Duration) -> Result<Move, EngineError> {
+        let position = position_command(state);
+        let process = self.running().await?;
+        process.send(&position).await?;
+        process.send(&format!("go movetime {}", budget.as_millis().max(1))).await?;
+        let reply = match process.wait_for_within("bestmove", budget + MOVE_GRACE).await {
+            // over budget: ask for the move now, and give up on the engine if it still stalls
+            Err(EngineError::Timeout) => {
+                process.send("stop").await?;
+                process.wait_for_within("bestmove", STOP_TIMEOUT).await?
+            }
+            reply => reply?,
+        };
+        let uci = reply.split_whitespace().nth(1).unwrap_or_default();
+        Move::from_uci(state, uci).map_err(|_| EngineError::BadMove(uci.to_string()))
+    }
+
+    async fn kill(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            let _ = process.child.kill().await;
+        }
+    }
+
+    /// Asks the engine to quit, killing it if it does not exit promptly
+    pub async fn shutdown(&mut self) {
+        if let Some(process) = self.process.as_mut() {
+            let _ = process.send("quit").await;
+            if timeout(STOP_TIMEOUT, process.child.wait()).await.is_ok() {
+                self.process = None;
+                return;
+            }
+        }
+        self.kill().await;
+    }
+}
+
+/// `position ... moves ...` for the game so far, from its starting position
+fn position_command(state: &GameState) -> String {
+    let mut command = match state.start_fen() {
+        Some(fen) => format!("position fen {}", fen),
+        None => "position startpos".to_string(),
+    };
+    if !state.history().is_empty() {
+        command.push_str(" moves");
+        for mv in state.history() {
+            command.push(' ');
+            command.push_str(&mv.to_uci());
+        }
+    }
+    command
+}
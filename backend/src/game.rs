@@ -1,5 +1,9 @@
 use rand::{thread_rng, Rng};
-use std::collections::HashMap;
+use std::fmt;
+
+use crate::bitboard::{bishop_attacks, bit, pawn_attacks, queen_attacks, rook_attacks, squares, Bitboards, KING_ATTACKS, KNIGHT_ATTACKS};
+use crate::clock::Clock;
+use crate::zobrist;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum PieceType { 
@@ -17,7 +21,7 @@ pub enum Color {
     Black 
 }
 
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Piece {
     pub piece_type: PieceType,
     color: Color,
@@ -26,7 +30,7 @@ pub struct Piece {
 pub type Square = Option<Piece>;
 pub type Board = Vec<Square>;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Move {
     from: u8,
     to: u8,
@@ -35,19 +39,369 @@ pub struct Move {
     is_en_passant: bool,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+impl Move {
+    pub fn from(&self) -> u8 {
+        self.from
+    }
+    pub fn to(&self) -> u8 {
+        self.to
+    }
+    pub fn promotion(&self) -> Option<PieceType> {
+        self.promotion
+    }
+    pub fn is_castle(&self) -> bool {
+        self.is_castle
+    }
+    pub fn is_en_passant(&self) -> bool {
+        self.is_en_passant
+    }
+
+    /// Renders the move in Standard Algebraic Notation. `state` must be the position
+    /// the move is played from.
+    pub fn to_san(&self, state: &GameState) -> String {
+        let Some(piece) = state.board[self.from as usize] else { return String::new() };
+        let mut san = String::new();
+        if self.is_castle {
+            san.push_str(if self.to > self.from { "O-O" } else { "O-O-O" });
+        } else {
+            let is_capture = state.board[self.to as usize].is_some() || self.is_en_passant;
+            if piece.piece_type == PieceType::Pawn {
+                if is_capture {
+                    san.push((b'a' + self.from % 8) as char);
+                }
+            } else {
+                san.push(piece_letter(piece.piece_type));
+                // other pieces of the same type that could also reach the destination
+                let rivals: Vec<u8> = state.legal_moves().into_iter()
+                    .filter(|m| m.to == self.to && m.from != self.from)
+                    .filter(|m| state.board[m.from as usize].map(|p| p.piece_type) == Some(piece.piece_type))
+                    .map(|m| m.from)
+                    .collect();
+                if !rivals.is_empty() {
+                    let same_file = rivals.iter().any(|&f| f % 8 == self.from % 8);
+                    let same_rank = rivals.iter().any(|&f| f / 8 == self.from / 8);
+                    if !same_file {
+                        san.push((b'a' + self.from % 8) as char);
+                    } else if !same_rank {
+                        san.push((b'1' + self.from / 8) as char);
+                    } else {
+                        san.push_str(&square_name(self.from));
+                    }
+                }
+            }
+            if is_capture {
+                san.push('x');
+            }
+            san.push_str(&square_name(self.to));
+            if let Some(promo) = self.promotion {
+                san.push('=');
+                san.push(piece_letter(promo));
+            }
+        }
+        // played on a scratch copy with `make_move`, which unlike `apply_move` still moves
+        // once the game has an outcome
+        let mut next = state.clone();
+        next.make_move(self);
+        if next.is_checkmate() {
+            san.push('#');
+        } else if next.is_in_check() {
+            san.push('+');
+        }
+        san
+    }
+}
+
+/// Reasons a SAN string cannot be turned into a move by `Move::from_san`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanError {
+    /// the text is not well-formed SAN
+    Syntax,
+    /// well-formed, but no legal move matches it
+    Illegal,
+    /// more than one legal move matches it
+    Ambiguous,
+}
+
+impl SanError {
+    /// Short machine-readable code sent to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            SanError::Syntax => "bad_notation",
+            SanError::Illegal => "illegal_move",
+            SanError::Ambiguous => "ambiguous_move",
+        }
+    }
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            SanError::Syntax => "malformed SAN",
+            SanError::Illegal => "no legal move matches",
+            SanError::Ambiguous => "more than one legal move matches",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for SanError {}
+
+fn piece_from_letter(c: char) -> Option<PieceType> {
+    match c {
+        'N' => Some(PieceType::Knight),
+        'B' => Some(PieceType::Bishop),
+        'R' => Some(PieceType::Rook),
+        'Q' => Some(PieceType::Queen),
+        'K' => Some(PieceType::King),
+        _ => None,
+    }
+}
+
+impl Move {
+    /// Renders the move in UCI long algebraic notation, e.g. "e2e4", "e7e8q", "e1g1"
+    pub fn to_uci(&self) -> String {
+        let mut uci = format!("{}{}", square_name(self.from), square_name(self.to));
+        if let Some(promo) = self.promotion {
+            uci.push(piece_letter(promo).to_ascii_lowercase());
+        }
+        uci
+    }
+
+    /// Parses a UCI move string and checks it against the legal moves of `state`
+    pub fn from_uci(state: &GameState, uci: &str) -> Result<Move, MoveError> {
+        if !uci.is_ascii() || !(4..=5).contains(&uci.len()) {
+            return Err(MoveError::BadNotation);
+        }
+        let from = parse_square(&uci[0..2]).ok_or(MoveError::BadNotation)?;
+        let to = parse_square(&uci[2..4]).ok_or(MoveError::BadNotation)?;
+        let promotion = match uci[4..].chars().next() {
+            None => None,
+            Some(c) => Some(piece_from_letter(c.to_ascii_uppercase()).ok_or(MoveError::BadNotation)?),
+        };
+        state.validate_move(from, to, promotion)
+    }
+
+    /// Parses a SAN move (e.g. "Nbd7", "exd6", "e8=Q+", "O-O") against the legal moves of
+    /// `state`. Check/mate markers and annotation glyphs such as "!?" are ignored, and
+    /// castling written with zeros is accepted.
+    pub fn from_san(state: &GameState, san: &str) -> Result<Move, SanError> {
+        let text = san.trim_end_matches(['+', '#', '!', '?']);
+        let legal = state.legal_moves();
+        let candidates: Vec<Move> = if matches!(text, "O-O" | "0-0" | "O-O-O" | "0-0-0") {
+            let kingside = text.len() == 3;
+            legal.into_iter()
+                .filter(|m| m.is_castle && (m.to > m.from) == kingside)
+                .collect()
+        } else {
+            let mut chars: Vec<char> = text.chars().collect();
+            let piece_type = match chars.first() {
+                Some(&c) if c.is_ascii_uppercase() => {
+                    chars.remove(0);
+                    piece_from_letter(c).ok_or(SanError::Syntax)?
+                }
+                Some(_) => PieceType::Pawn,
+                None => return Err(SanError::Syntax),
+            };
+            // trailing promotion piece, with or without '='
+            let mut promotion = None;
+            if let Some(&c) = chars.last()
+                && c.is_ascii_uppercase()
+            {
+                promotion = Some(piece_from_letter(c).filter(|p| *p != PieceType::King).ok_or(SanError::Syntax)?);
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+            if chars.len() < 2 {
+                return Err(SanError::Syntax);
+            }
+            let dest: String = chars.split_off(chars.len() - 2).into_iter().collect();
+            let to = parse_square(&dest).ok_or(SanError::Syntax)?;
+            // whatever is left is disambiguation and an optional capture marker
+            if chars.last() == Some(&'x') {
+                chars.pop();
+            }
+            let mut from_file = None;
+            let mut from_rank = None;
+            for c in chars {
+                match c {
+                    'a'..='h' if from_file.is_none() => from_file = Some(c as u8 - b'a'),
+                    '1'..='8' if from_rank.is_none() => from_rank = Some(c as u8 - b'1'),
+                    _ => return Err(SanError::Syntax),
+                }
+            }
+            legal.into_iter()
+                .filter(|m| m.to == to && m.promotion == promotion)
+                .filter(|m| state.board[m.from as usize].map(|p| p.piece_type) == Some(piece_type))
+                .filter(|m| !m.is_castle)
+                .filter(|m| from_file.is_none_or(|f| m.from % 8 == f))
+                .filter(|m| from_rank.is_none_or(|r| m.from / 8 == r))
+                .collect()
+        };
+        match candidates.len() {
+            0 => Err(SanError::Illegal),
+            1 => Ok(candidates[0]),
+            _ => Err(SanError::Ambiguous),
+        }
+    }
+}
+
+/// Uppercase SAN letter for a piece type (pawns have none in SAN, 'P' is returned for completeness)
+fn piece_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Pawn => 'P',
+        PieceType::Knight => 'N',
+        PieceType::Bishop => 'B',
+        PieceType::Rook => 'R',
+        PieceType::Queen => 'Q',
+        PieceType::King => 'K',
+    }
+}
+
+/// Reasons a requested move is refused by `GameState::validate_move`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    /// a square index outside 0..64
+    OffBoard,
+    /// no piece on the source square
+    EmptySquare,
+    /// the piece on the source square belongs to the side not on move
+    NotYourTurn,
+    /// the destination is not reachable, or the move leaves the king in check
+    IllegalMove,
+    /// promotion piece given for a non-promoting move, or not one of Q/R/B/N
+    InvalidPromotion,
+    /// a move string that is not well-formed UCI
+    BadNotation,
+    /// the game already has an outcome
+    GameOver,
+}
+
+impl MoveError {
+    /// Short machine-readable code sent to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            MoveError::OffBoard => "off_board",
+            MoveError::EmptySquare => "empty_square",
+            MoveError::NotYourTurn => "not_your_turn",
+            MoveError::IllegalMove => "illegal_move",
+            MoveError::InvalidPromotion => "invalid_promotion",
+            MoveError::BadNotation => "bad_notation",
+            MoveError::GameOver => "game_over",
+        }
+    }
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            MoveError::OffBoard => "square index is off the board",
+            MoveError::EmptySquare => "there is no piece on the source square",
+            MoveError::NotYourTurn => "it is not this side's turn to move",
+            MoveError::IllegalMove => "the move is not legal in the current position",
+            MoveError::InvalidPromotion => "invalid promotion piece for this move",
+            MoveError::BadNotation => "malformed UCI move",
+            MoveError::GameOver => "the game has ended",
+        };
+        f.write_str(msg)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    /// A win for `color`
+    pub fn win_for(color: Color) -> Self {
+        match color {
+            Color::White => GameResult::WhiteWins,
+            Color::Black => GameResult::BlackWins,
+        }
+    }
+}
+
+/// Why a game ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    /// the same position five times; ends the game without a claim
+    FivefoldRepetition,
+    /// 75 moves by each side without a capture or pawn move; ends the game without a claim
+    SeventyFiveMoveRule,
+    /// claimed by a player
+    ThreefoldRepetition,
+    /// claimed by a player
+    FiftyMoveRule,
+    Agreement,
+    Resignation,
+    Timeout,
+    /// the flag fell, but the opponent could not have checkmated
+    TimeoutVsInsufficientMaterial,
+    /// a player left and did not come back in time
+    Abandonment,
+}
+
+/// The result of a finished game and how it came about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Outcome {
+    pub result: GameResult,
+    pub termination: Termination,
+}
+
+impl Outcome {
+    pub fn win(winner: Color, termination: Termination) -> Self {
+        Outcome { result: GameResult::win_for(winner), termination }
+    }
+
+    pub fn draw(termination: Termination) -> Self {
+        Outcome { result: GameResult::Draw, termination }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct GameState {
     board: Board,
+    /// the same placement as `board`, used for move generation
+    #[serde(skip)]
+    bitboards: Bitboards,
     turn: Color,
     castling_rights: CastlingRights,
     en_passant_square: Option<u8>,
     halfmove_clock: u32,
     fullmove_clock: u32,
     game_code: String,
-    repetition_counts: HashMap<String, usize>,
+    /// Zobrist hash of the current position, updated incrementally
+    #[serde(skip)]
+    hash: u64,
+    /// hash of the position after every ply, the starting position first
+    #[serde(skip)]
+    position_hashes: Vec<u64>,
+    /// every move played so far, oldest first; clients get them described in the state's
+    /// `moves` instead
+    #[serde(skip)]
+    history: Vec<Move>,
+    /// undo records matching `history`, for takebacks
+    #[serde(skip)]
+    undo_stack: Vec<Undo>,
+    /// FEN the game started from, or None for the standard starting position
+    start_fen: Option<String>,
+    /// chess clock, for timed games
+    #[serde(skip)]
+    clock: Option<Clock>,
+    /// how the game ended, once it has
+    outcome: Option<Outcome>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct CastlingRights {
     white_kingside: bool,
     white_queenside: bool,
@@ -55,9 +409,50 @@ pub struct CastlingRights {
     black_queenside: bool
 }
 
+/// What `GameState::make_move` changed that cannot be recomputed from the move itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Undo {
+    /// the piece that stood on the from-square (a pawn, for promotions)
+    moved: Square,
+    /// the captured piece and the square it stood on (differs from `to` for en passant)
+    captured: Option<(u8, Piece)>,
+    castling_rights: CastlingRights,
+    en_passant_square: Option<u8>,
+    halfmove_clock: u32,
+    /// hash of the position the move reached
+    repetition_key: u64,
+}
+
+impl Undo {
+    /// The piece taken by the move, if any
+    pub fn captured(&self) -> Option<Piece> {
+        self.captured.map(|(_, piece)| piece)
+    }
+}
+
 // ----------- Checks -----------
 
-fn opposite_color(color: Color) -> Color {
+/// Rook (from, to) squares when a king of `color` castles from `from` to `to`, or None
+/// if the king move is not a castle
+fn castle_rook_squares(color: Color, from: usize, to: usize) -> Option<(usize, usize)> {
+    if to == from + 2 {
+        // kingside
+        Some(match color {
+            Color::White => (7, 5),
+            Color::Black => (63, 61),
+        })
+    } else if to + 2 == from {
+        // queenside
+        Some(match color {
+            Color::White => (0, 3),
+            Color::Black => (56, 59),
+        })
+    } else {
+        None
+    }
+}
+
+pub fn opposite_color(color: Color) -> Color {
     match color {
         Color::White => Color::Black,
         Color::Black => Color::White,
@@ -65,200 +460,93 @@ fn opposite_color(color: Color) -> Color {
 }
 
 fn is_square_attacked(state: &GameState, square: u8, attacker_color: Color) -> bool {
-    let target_x = square % 8;
-    let target_y = square / 8;
-    for i in 0..64 {
-        if let Some(attacker) = state.board[i] {
-            if attacker.color == attacker_color {
-                match attacker.piece_type {
-                    PieceType::King => {
-                        let x = i % 8;
-                        let y = i / 8;
-                        if (x as i8 - target_x as i8).abs() <= 1 && (y as i8 - target_y as i8).abs() <= 1 {
-                            return true;
-                        }
-                    }
-                    _ => {
-                        let pseudo_moves = legal_moves_for_piece(state, i as u8);
-                        if pseudo_moves.contains(&square) {
-                            return true;
-                        }
-                    }
-                }
-            }
-        }
-    }
-    false
+    state.bitboards.is_attacked(square, attacker_color)
 }
 
+/// Pseudo-legal destinations for the piece on `pos`: moves that may still leave the king in check
 pub fn legal_moves_for_piece(state: &GameState, pos: u8) -> Vec<u8> {
     let Some(piece) = state.board[pos as usize] else { return vec![] };
-    let mut moves = Vec::new();
-
-    let (x, y) = (pos % 8, pos / 8);
     let color = piece.color;
+    let bb = &state.bitboards;
+    let own = bb.occupied_by(color);
+    let enemy = bb.occupied_by(opposite_color(color));
+    let occupied = own | enemy;
 
-    let mut push = |dx: i8, dy: i8, repeat: bool, captures_only: bool| {
-        let mut step = 1;
-        loop {
-            let nx = x as i8 + dx * step;
-            let ny = y as i8 + dy * step;
-            if nx < 0 || ny < 0 || nx >= 8 || ny >= 8 {
-                break;
-            }
-            let to = (ny * 8 + nx) as u8;
-            match state.board[to as usize] {
-                Some(p) if p.color == color => break,
-                Some(p) => {
-                    if !captures_only {
-                        moves.push(to);
-                    } else {
-                        moves.push(to);
-                    }
-                    break;
-                }
-                None => {
-                    if !captures_only {
-                        moves.push(to);
-                    }
-                }
-            }
-            if !repeat { break; }
-            step += 1;
-        }
-    };
- 
-
-    match piece.piece_type {
+    let targets = match piece.piece_type {
         PieceType::Pawn => {
-            let dir = if color == Color::White { 1 } else { -1 };
-            let start_row = if color == Color::White { 1 } else { 6 };
-
-            let forward_one = pos as i8 + dir * 8;
-            if (0..64).contains(&forward_one) && state.board[forward_one as usize].is_none() {
-                moves.push(forward_one as u8);
-
-                let forward_two = pos as i8 + dir * 16;
-                if y == start_row && state.board[forward_two as usize].is_none() {
-                    moves.push(forward_two as u8);
+            let (dir, start_row): (i8, u8) = if color == Color::White { (8, 1) } else { (-8, 6) };
+            let mut targets = 0;
+            let forward_one = pos as i8 + dir;
+            if (0..64).contains(&forward_one) && occupied & bit(forward_one as u8) == 0 {
+                targets |= bit(forward_one as u8);
+                let forward_two = forward_one + dir;
+                if pos / 8 == start_row && occupied & bit(forward_two as u8) == 0 {
+                    targets |= bit(forward_two as u8);
                 }
             }
-
-            for dx in [-1, 1] {
-                let nx = x as i8 + dx;
-                let ny = y as i8 + dir;
-                if nx >= 0 && nx < 8 && ny >= 0 && ny < 8 {
-                    let to = (ny * 8 + nx) as u8;
-                    if let Some(p) = state.board[to as usize] {
-                        if p.color != color {
-                            moves.push(to);
-                        }
-                    } else if Some(to) == state.en_passant_square {
-                        moves.push(to);
-                    }
-                }
-            }
-        }
-
-        PieceType::Knight => {
-            let deltas = [
-                (1, 2), (2, 1), (-1, 2), (-2, 1),
-                (1, -2), (2, -1), (-1, -2), (-2, -1),
-            ];
-            for (dx, dy) in deltas {
-                let nx = x as i8 + dx;
-                let ny = y as i8 + dy;
-                if nx >= 0 && nx < 8 && ny >= 0 && ny < 8 {
-                    let to = (ny * 8 + nx) as u8;
-                    if let Some(p) = state.board[to as usize] {
-                        if p.color != color {
-                            moves.push(to);
-                        }
-                    } else {
-                        moves.push(to);
-                    }
-                }
-            }
-        }
-
-        PieceType::Bishop => {
-            for &(dx, dy) in &[(1, 1), (-1, 1), (1, -1), (-1, -1)] {
-                push(dx, dy, true, false);
-            }
-        }
-
-        PieceType::Rook => {
-            for &(dx, dy) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
-                push(dx, dy, true, false);
-            }
-        }
-
-        PieceType::Queen => {
-            for &(dx, dy) in &[
-                (1, 0), (-1, 0), (0, 1), (0, -1),
-                (1, 1), (-1, 1), (1, -1), (-1, -1)
-            ] {
-                push(dx, dy, true, false);
+            // en passant is only available to the side on move
+            let mut capturable = enemy;
+            if let Some(ep) = state.en_passant_square
+                && color == state.turn
+            {
+                capturable |= bit(ep);
             }
+            targets | (pawn_attacks(color, pos) & capturable)
         }
-
+        PieceType::Knight => KNIGHT_ATTACKS[pos as usize] & !own,
+        PieceType::Bishop => bishop_attacks(pos, occupied) & !own,
+        PieceType::Rook => rook_attacks(pos, occupied) & !own,
+        PieceType::Queen => queen_attacks(pos, occupied) & !own,
         PieceType::King => {
-            for &(dx, dy) in &[
-                (1, 0), (-1, 0), (0, 1), (0, -1),
-                (1, 1), (-1, 1), (1, -1), (-1, -1)
-            ] {
-                push(dx, dy, false, false);
-            }
-
-            // castling with check restrictions
+            let mut targets = KING_ATTACKS[pos as usize] & !own;
+            // castling with check restrictions: the king may not start on, pass or land on an attacked square
             let opponent = opposite_color(color);
-            if piece.color == Color::White && y == 0 && x == 4 {
-                if state.castling_rights.white_kingside
-                    && state.board[5].is_none()
-                    && state.board[6].is_none()
-                    && !is_square_attacked(state, 4, opponent)
-                    && !is_square_attacked(state, 5, opponent)
-                    && !is_square_attacked(state, 6, opponent)
+            let (home, rights_king, rights_queen) = match color {
+                Color::White => (4, state.castling_rights.white_kingside, state.castling_rights.white_queenside),
+                Color::Black => (60, state.castling_rights.black_kingside, state.castling_rights.black_queenside),
+            };
+            let rooks = bb.pieces(color, PieceType::Rook);
+            if pos == home && !is_square_attacked(state, home, opponent) {
+                if rights_king
+                    && rooks & bit(home + 3) != 0
+                    && occupied & (bit(home + 1) | bit(home + 2)) == 0
+                    && !is_square_attacked(state, home + 1, opponent)
+                    && !is_square_attacked(state, home + 2, opponent)
                 {
-                    moves.push(6);
+                    targets |= bit(home + 2);
                 }
-                if state.castling_rights.white_queenside
-                    && state.board[1].is_none()
-                    && state.board[2].is_none()
-                    && state.board[3].is_none()
-                    && !is_square_attacked(state, 4, opponent)
-                    && !is_square_attacked(state, 3, opponent)
-                    && !is_square_attacked(state, 2, opponent)
+                if rights_queen
+                    && rooks & bit(home - 4) != 0
+                    && occupied & (bit(home - 1) | bit(home - 2) | bit(home - 3)) == 0
+                    && !is_square_attacked(state, home - 1, opponent)
+                    && !is_square_attacked(state, home - 2, opponent)
                 {
-                    moves.push(2);
-                }
-            }
-
-            if piece.color == Color::Black && y == 7 && x == 4 {
-                if state.castling_rights.black_kingside
-                    && state.board[61].is_none()
-                    && state.board[62].is_none()
-                    && !is_square_attacked(state, 60, opponent)
-                    && !is_square_attacked(state, 61, opponent)
-                    && !is_square_attacked(state, 62, opponent)
-                {
-                    moves.push(62);
-                }
-                if state.castling_rights.black_queenside
-                    && state.board[57].is_none()
-                    && state.board[58].is_none()
-                    && state.board[59].is_none()
-                    && !is_square_attacked(state, 60, opponent)
-                    && !is_square_attacked(state, 59, opponent)
-                    && !is_square_attacked(state, 58, opponent)
-                {
-                    moves.push(58);
+                    targets |= bit(home - 2);
                 }
             }
+            targets
         }
-    }
+    };
+
+    squares(targets).collect()
+}
 
-   moves
+/// true if moving the piece on `from` to `to` does not leave its own king attacked.
+/// Works on a copy of the bitboards only, so no game state is cloned.
+fn keeps_king_safe(state: &GameState, piece: Piece, from: u8, to: u8) -> bool {
+    let mut bb = state.bitboards;
+    if let Some(captured) = state.board[to as usize] {
+        bb.remove(to, captured.color, captured.piece_type);
+    } else if piece.piece_type == PieceType::Pawn && state.en_passant_square == Some(to) {
+        let cap = if piece.color == Color::White { to - 8 } else { to + 8 };
+        bb.remove(cap, opposite_color(piece.color), PieceType::Pawn);
+    }
+    bb.remove(from, piece.color, piece.piece_type);
+    bb.put(to, piece.color, piece.piece_type);
+    match bb.king_square(piece.color) {
+        Some(king) => !bb.is_attacked(king, opposite_color(piece.color)),
+        None => false,
+    }
 }
 
 pub fn legal_moves_for_piece_strict(state: &GameState, pos: u8) -> Vec<u8> {
@@ -267,32 +555,9 @@ pub fn legal_moves_for_piece_strict(state: &GameState, pos: u8) -> Vec<u8> {
         return vec![];
     }
 
-    let candidate_moves = legal_moves_for_piece(state, pos); 
-    let mut legal_moves = Vec::new();
-
-    for to in candidate_moves {
-        let mut next_state = state.clone();
-
-        next_state.board[to as usize] = next_state.board[pos as usize];
-        next_state.board[pos as usize] = None;
-
-        if piece.piece_type == PieceType::Pawn && state.en_passant_square == Some(to) {
-            let offset = if piece.color == Color::White { -8 } else { 8 };
-            next_state.board[(to as i8 + offset) as usize] = None;
-        }
-
-        let king_pos = next_state.board.iter().position(|&p| {
-            matches!(p, Some(Piece { piece_type: PieceType::King, color }) if color == piece.color)
-        });
-
-        if let Some(king_idx) = king_pos {
-            if !is_square_attacked(&next_state, king_idx as u8, opposite_color(piece.color)) {
-                legal_moves.push(to);
-            }
-        }
-    }
-
-   legal_moves 
+    let mut legal_moves = legal_moves_for_piece(state, pos);
+    legal_moves.retain(|&to| keeps_king_safe(state, piece, pos, to));
+    legal_moves
 }
 
 // ------- Initialisation -------
@@ -324,96 +589,528 @@ pub fn starting_board() -> Board {
    board 
 }
 
+fn bitboards_for(board: &Board) -> Bitboards {
+    let mut bitboards = Bitboards::default();
+    for (idx, square) in board.iter().enumerate() {
+        if let Some(piece) = square {
+            bitboards.put(idx as u8, piece.color, piece.piece_type);
+        }
+    }
+    bitboards
+}
+
+fn generate_game_code() -> String {
+    let mut rng = thread_rng();
+    format!("{:06}", rng.gen_range(0..1_000_000))
+}
+
+// ------------ FEN -------------
+
+/// FEN of the standard starting position
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Error returned by `GameState::from_fen`, naming the malformed field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    /// the string does not have 4 to 6 space-separated fields
+    FieldCount(usize),
+    /// piece placement field
+    Board(String),
+    /// active color field
+    SideToMove(String),
+    /// castling availability field
+    CastlingRights(String),
+    /// en passant target square field
+    EnPassant(String),
+    /// halfmove clock field
+    HalfmoveClock(String),
+    /// fullmove number field
+    FullmoveClock(String),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::FieldCount(n) => write!(f, "FEN must have 4 to 6 fields, found {}", n),
+            FenError::Board(msg) => write!(f, "invalid piece placement: {}", msg),
+            FenError::SideToMove(msg) => write!(f, "invalid side to move: {}", msg),
+            FenError::CastlingRights(msg) => write!(f, "invalid castling rights: {}", msg),
+            FenError::EnPassant(msg) => write!(f, "invalid en passant square: {}", msg),
+            FenError::HalfmoveClock(msg) => write!(f, "invalid halfmove clock: {}", msg),
+            FenError::FullmoveClock(msg) => write!(f, "invalid fullmove number: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+fn piece_to_fen_char(piece: Piece) -> char {
+    let c = match piece.piece_type {
+        PieceType::Pawn => 'p',
+        PieceType::Knight => 'n',
+        PieceType::Bishop => 'b',
+        PieceType::Rook => 'r',
+        PieceType::Queen => 'q',
+        PieceType::King => 'k',
+    };
+    if piece.color == Color::White { c.to_ascii_uppercase() } else { c }
+}
+
+fn piece_from_fen_char(c: char) -> Option<Piece> {
+    let piece_type = match c.to_ascii_lowercase() {
+        'p' => PieceType::Pawn,
+        'n' => PieceType::Knight,
+        'b' => PieceType::Bishop,
+        'r' => PieceType::Rook,
+        'q' => PieceType::Queen,
+        'k' => PieceType::King,
+        _ => return None,
+    };
+    let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+    Some(Piece { piece_type, color })
+}
+
+/// Formats a board index as an algebraic square name, e.g. 12 -> "e2"
+pub fn square_name(idx: u8) -> String {
+    format!("{}{}", (b'a' + idx % 8) as char, idx / 8 + 1)
+}
+
+/// Parses an algebraic square name such as "e2" into a board index
+pub fn parse_square(name: &str) -> Option<u8> {
+    let bytes = name.as_bytes();
+    if bytes.len() != 2 || !(b'a'..=b'h').contains(&bytes[0]) || !(b'1'..=b'8').contains(&bytes[1]) {
+        return None;
+    }
+    Some((bytes[1] - b'1') * 8 + (bytes[0] - b'a'))
+}
+
+fn parse_fen_board(field: &str) -> Result<Board, FenError> {
+    let ranks: Vec<&str> = field.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(FenError::Board(format!("expected 8 ranks, found {}", ranks.len())));
+    }
+    let mut board: Board = vec![None; 64];
+    // FEN lists rank 8 first
+    for (i, rank_str) in ranks.iter().enumerate() {
+        let rank = 7 - i;
+        let mut file = 0usize;
+        for c in rank_str.chars() {
+            if let Some(skip) = c.to_digit(10) {
+                if !(1..=8).contains(&skip) {
+                    return Err(FenError::Board(format!("bad empty-square count '{}' on rank {}", c, rank + 1)));
+                }
+                file += skip as usize;
+            } else {
+                let Some(piece) = piece_from_fen_char(c) else {
+                    return Err(FenError::Board(format!("unknown piece '{}' on rank {}", c, rank + 1)));
+                };
+                if file < 8 {
+                    board[rank * 8 + file] = Some(piece);
+                }
+                file += 1;
+            }
+            if file > 8 {
+                return Err(FenError::Board(format!("rank {} has more than 8 squares", rank + 1)));
+            }
+        }
+        if file != 8 {
+            return Err(FenError::Board(format!("rank {} has {} squares", rank + 1, file)));
+        }
+    }
+    for color in [Color::White, Color::Black] {
+        let kings = board.iter().flatten().filter(|p| p.piece_type == PieceType::King && p.color == color).count();
+        if kings != 1 {
+            return Err(FenError::Board(format!("{:?} must have exactly one king, found {}", color, kings)));
+        }
+    }
+    if board.iter().enumerate().any(|(i, sq)| matches!(sq, Some(p) if p.piece_type == PieceType::Pawn && !(8..56).contains(&i))) {
+        return Err(FenError::Board("pawn on the first or last rank".to_string()));
+    }
+    Ok(board)
+}
+
+fn parse_fen_castling(field: &str) -> Result<CastlingRights, FenError> {
+    let mut rights = CastlingRights { white_kingside: false, white_queenside: false, black_kingside: false, black_queenside: false };
+    if field == "-" {
+        return Ok(rights);
+    }
+    for c in field.chars() {
+        let flag = match c {
+            'K' => &mut rights.white_kingside,
+            'Q' => &mut rights.white_queenside,
+            'k' => &mut rights.black_kingside,
+            'q' => &mut rights.black_queenside,
+            _ => return Err(FenError::CastlingRights(format!("unexpected character '{}'", c))),
+        };
+        if *flag {
+            return Err(FenError::CastlingRights(format!("'{}' given twice", c)));
+        }
+        *flag = true;
+    }
+    Ok(rights)
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GameState {
     pub fn new() -> Self {
+        let board = starting_board();
         let mut state = GameState {
-            board: starting_board(),
+            bitboards: bitboards_for(&board),
+            board,
             turn: Color::White,
             castling_rights: CastlingRights { white_kingside: true, white_queenside: true, black_kingside: true, black_queenside: true },
             en_passant_square: None,
             halfmove_clock: 0,
             fullmove_clock: 1,
-            game_code: { let mut rng = thread_rng(); format!("{:06}", rng.gen_range(0..1_000_000)) },
-            repetition_counts: HashMap::new(),
+            game_code: generate_game_code(),
+            hash: 0,
+            position_hashes: Vec::new(),
+            history: Vec::new(),
+            undo_stack: Vec::new(),
+            start_fen: None,
+            clock: None,
+            outcome: None,
         };
         // initial position counted
-        let key = state.position_key();
-        state.repetition_counts.insert(key, 1);
+        state.hash = state.compute_hash();
+        state.position_hashes.push(state.hash);
         state
     }
 
-    /// compute a key for the current position (board, turn, castling, en_passant)
-    fn position_key(&self) -> String {
-        let mut map = serde_json::Map::new();
-        map.insert("board".to_string(), serde_json::to_value(&self.board).unwrap());
-        map.insert("turn".to_string(), serde_json::to_value(&self.turn).unwrap());
-        map.insert("castling_rights".to_string(), serde_json::to_value(&self.castling_rights).unwrap());
-        map.insert("en_passant_square".to_string(), serde_json::to_value(&self.en_passant_square).unwrap());
-        serde_json::Value::Object(map).to_string()
+    /// Builds a game from a FEN string. The halfmove and fullmove fields may be omitted
+    /// (as in EPD), in which case they default to 0 and 1.
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if !(4..=6).contains(&fields.len()) {
+            return Err(FenError::FieldCount(fields.len()));
+        }
+        let board = parse_fen_board(fields[0])?;
+        let turn = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            other => return Err(FenError::SideToMove(format!("expected 'w' or 'b', found '{}'", other))),
+        };
+        let castling_rights = parse_fen_castling(fields[2])?;
+        let en_passant_square = match fields[3] {
+            "-" => None,
+            name => {
+                let Some(idx) = parse_square(name) else {
+                    return Err(FenError::EnPassant(format!("'{}' is not a square", name)));
+                };
+                // the target square lies behind a pawn that just double-stepped
+                let expected_rank = if turn == Color::White { 5 } else { 2 };
+                if idx / 8 != expected_rank {
+                    return Err(FenError::EnPassant(format!("{} is not on rank {}", name, expected_rank + 1)));
+                }
+                Some(idx)
+            }
+        };
+        let halfmove_clock = match fields.get(4) {
+            Some(s) => s.parse::<u32>().map_err(|_| FenError::HalfmoveClock(format!("'{}' is not a number", s)))?,
+            None => 0,
+        };
+        let fullmove_clock = match fields.get(5) {
+            Some(s) => match s.parse::<u32>() {
+                Ok(n) if n >= 1 => n,
+                _ => return Err(FenError::FullmoveClock(format!("'{}' is not a positive number", s))),
+            },
+            None => 1,
+        };
+        let mut state = GameState {
+            bitboards: bitboards_for(&board),
+            board,
+            turn,
+            castling_rights,
+            en_passant_square,
+            halfmove_clock,
+            fullmove_clock,
+            game_code: generate_game_code(),
+            hash: 0,
+            position_hashes: Vec::new(),
+            history: Vec::new(),
+            undo_stack: Vec::new(),
+            start_fen: None,
+            clock: None,
+            outcome: None,
+        };
+        state.hash = state.compute_hash();
+        state.position_hashes.push(state.hash);
+        // store the normalised form so exports are stable
+        state.start_fen = Some(state.to_fen());
+        Ok(state)
+    }
+
+    /// Describes the current position as a FEN string
+    pub fn to_fen(&self) -> String {
+        let mut placement = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.board[rank * 8 + file] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            placement.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        placement.push(piece_to_fen_char(piece));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                placement.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                placement.push('/');
+            }
+        }
+        let turn = if self.turn == Color::White { "w" } else { "b" };
+        let mut castling = String::new();
+        if self.castling_rights.white_kingside { castling.push('K'); }
+        if self.castling_rights.white_queenside { castling.push('Q'); }
+        if self.castling_rights.black_kingside { castling.push('k'); }
+        if self.castling_rights.black_queenside { castling.push('q'); }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        let en_passant = self.en_passant_square.map(square_name).unwrap_or_else(|| "-".to_string());
+        format!("{} {} {} {} {} {}", placement, turn, castling, en_passant, self.halfmove_clock, self.fullmove_clock)
+    }
+
+    /// Zobrist hash of the current position. Positions that are the same under the
+    /// repetition rules (placement, side to move, castling rights, en passant capture) hash alike.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Hashes the position from scratch
+    fn compute_hash(&self) -> u64 {
+        let mut hash = self.extras_hash();
+        for sq in squares(self.bitboards.occupied()) {
+            let piece = self.board[sq as usize].unwrap();
+            hash ^= zobrist::piece(piece.color, piece.piece_type, sq);
+        }
+        hash
     }
 
-    /// update repetition map after a move
-    fn update_repetition(&mut self) {
-        let key = self.position_key();
-        let count = self.repetition_counts.entry(key).or_insert(0);
-        *count += 1;
+    /// The part of the hash that is not piece placement: side to move, castling and en passant
+    fn extras_hash(&self) -> u64 {
+        let mut hash = if self.turn == Color::Black { zobrist::black_to_move() } else { 0 };
+        if self.castling_rights.white_kingside { hash ^= zobrist::castling(0); }
+        if self.castling_rights.white_queenside { hash ^= zobrist::castling(1); }
+        if self.castling_rights.black_kingside { hash ^= zobrist::castling(2); }
+        if self.castling_rights.black_queenside { hash ^= zobrist::castling(3); }
+        if let Some(sq) = self.en_passant_square
+            && self.can_capture_en_passant(sq)
+        {
+            hash ^= zobrist::en_passant(sq);
+        }
+        hash
     }
 
-    /// true if same position occurred three times
+    /// true if the side to move has a legal en passant capture onto `target`. A double step
+    /// with no pawn able to take it leaves the position the same for repetition purposes.
+    fn can_capture_en_passant(&self, target: u8) -> bool {
+        let pawns = self.bitboards.pieces(self.turn, PieceType::Pawn);
+        let pawn = Piece { piece_type: PieceType::Pawn, color: self.turn };
+        squares(pawn_attacks(opposite_color(self.turn), target) & pawns)
+            .any(|from| keeps_king_safe(self, pawn, from, target))
+    }
+
+    /// How many times the current position has occurred, counting this one. Only positions
+    /// since the last capture or pawn move can repeat, so the search stops there.
+    pub fn repetition_count(&self) -> usize {
+        let window = (self.halfmove_clock as usize + 1).min(self.position_hashes.len());
+        self.position_hashes[self.position_hashes.len() - window..]
+            .iter()
+            .rev()
+            .step_by(2)
+            .filter(|&&hash| hash == self.hash)
+            .count()
+    }
+
+    /// true if the current position has occurred three times
     pub fn is_threefold_repetition(&self) -> bool {
-        self.repetition_counts.values().any(|&c| c >= 3)
+        self.repetition_count() >= 3
     }
 
     /// Returns the game code identifier
     pub fn game_code(&self) -> &str {
         &self.game_code
     }
+
+    /// Gives the game a known code, as when it is restored from storage
+    pub fn set_game_code(&mut self, game_code: String) {
+        self.game_code = game_code;
+    }
     /// Returns the current player's turn color
     pub fn turn(&self) -> Color {
         self.turn
     }
+    /// Returns the fullmove number (starts at 1, incremented after Black moves)
+    pub fn fullmove_clock(&self) -> u32 {
+        self.fullmove_clock
+    }
     
-    /// Moves a piece from one square to another, without validation, then switches turn
-    pub fn move_piece(&mut self, from: u8, to: u8, promotion: Option<PieceType>) {
+    /// Checks a requested move against the game's outcome, turn order and the strict move
+    /// rules. A missing promotion on a promoting pawn move is accepted and defaults to a
+    /// queen, matching `move_piece`.
+    pub fn validate_move(&self, from: u8, to: u8, promotion: Option<PieceType>) -> Result<Move, MoveError> {
+        if self.outcome.is_some() {
+            return Err(MoveError::GameOver);
+        }
+        if from >= 64 || to >= 64 {
+            return Err(MoveError::OffBoard);
+        }
+        let Some(piece) = self.board[from as usize] else { return Err(MoveError::EmptySquare) };
+        if piece.color != self.turn {
+            return Err(MoveError::NotYourTurn);
+        }
+        if !legal_moves_for_piece_strict(self, from).contains(&to) {
+            return Err(MoveError::IllegalMove);
+        }
+        let is_promotion = self.is_promotion_square(from, to);
+        match promotion {
+            Some(PieceType::Queen | PieceType::Rook | PieceType::Bishop | PieceType::Knight) if is_promotion => {}
+            None => {}
+            Some(_) => return Err(MoveError::InvalidPromotion),
+        }
+        Ok(self.describe_move(from, to, promotion))
+    }
+
+    /// true if the piece on `from` is a pawn and `to` is on its last rank
+    fn is_promotion_square(&self, from: u8, to: u8) -> bool {
+        match self.board[from as usize] {
+            Some(Piece { piece_type: PieceType::Pawn, color }) => {
+                let last_rank = if color == Color::White { 7 } else { 0 };
+                to / 8 == last_rank
+            }
+            _ => false,
+        }
+    }
+
+    /// Builds the `Move` record for moving the piece on `from` to `to` in this position,
+    /// filling in the castling/en passant flags and the default queen promotion
+    fn describe_move(&self, from: u8, to: u8, promotion: Option<PieceType>) -> Move {
+        let piece_type = self.board[from as usize].map(|p| p.piece_type);
+        Move {
+            from,
+            to,
+            promotion: if self.is_promotion_square(from, to) { Some(promotion.unwrap_or(PieceType::Queen)) } else { None },
+            is_castle: piece_type == Some(PieceType::King) && (from as i8 - to as i8).abs() == 2,
+            is_en_passant: piece_type == Some(PieceType::Pawn) && self.en_passant_square == Some(to),
+        }
+    }
+
+    /// Every legal move for the side to move, with one entry per promotion choice
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for from in squares(self.bitboards.occupied_by(self.turn)) {
+            for to in legal_moves_for_piece_strict(self, from) {
+                if self.is_promotion_square(from, to) {
+                    for promo in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+                        moves.push(self.describe_move(from, to, Some(promo)));
+                    }
+                } else {
+                    moves.push(self.describe_move(from, to, None));
+                }
+            }
+        }
+        moves
+    }
+
+    /// Moves played so far, oldest first
+    pub fn history(&self) -> &[Move] {
+        &self.history
+    }
+
+    /// FEN the game started from, or None if it began from the standard position
+    pub fn start_fen(&self) -> Option<&str> {
+        self.start_fen.as_deref()
+    }
+
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    pub fn clock_mut(&mut self) -> Option<&mut Clock> {
+        self.clock.as_mut()
+    }
+
+    pub fn set_clock(&mut self, clock: Option<Clock>) {
+        self.clock = clock;
+    }
+
+    /// The side that lost on time, or ran out of time for a draw
+    pub fn flagged(&self) -> Option<Color> {
+        self.clock.as_ref().and_then(Clock::flagged)
+    }
+
+    /// A new game set up at this game's starting position, for replaying its history
+    pub fn initial_position(&self) -> GameState {
+        match self.start_fen() {
+            Some(fen) => GameState::from_fen(fen).expect("stored start FEN is valid"),
+            None => GameState::new(),
+        }
+    }
+
+    /// The piece on a square (0 = a1 … 63 = h8), if any
+    pub fn piece_at(&self, square: u8) -> Square {
+        self.board[square as usize]
+    }
+
+    /// Applies a move previously returned by `validate_move`
+    pub fn apply_move(&mut self, mv: &Move) {
+        self.move_piece(mv.from, mv.to, mv.promotion);
+    }
+
+    /// Writes a square, keeping the bitboards in step with the board vector
+    fn set_square(&mut self, idx: usize, square: Square) {
+        if let Some(old) = self.board[idx] {
+            self.bitboards.remove(idx as u8, old.color, old.piece_type);
+            self.hash ^= zobrist::piece(old.color, old.piece_type, idx as u8);
+        }
+        if let Some(new) = square {
+            self.bitboards.put(idx as u8, new.color, new.piece_type);
+            self.hash ^= zobrist::piece(new.color, new.piece_type, idx as u8);
+        }
+        self.board[idx] = square;
+    }
+
+    /// Plays a move without validation and returns the record `unmake_move` needs to take it
+    /// back. The move history is left alone, so a search can make and unmake freely.
+    pub fn make_move(&mut self, mv: &Move) -> Undo {
+        let from = mv.from;
+        let to = mv.to;
         let from_idx = from as usize;
         let to_idx = to as usize;
+        let moved = self.board[from_idx];
+        let mut undo = Undo {
+            moved,
+            captured: self.board[to_idx].map(|piece| (to, piece)),
+            castling_rights: self.castling_rights,
+            en_passant_square: self.en_passant_square,
+            halfmove_clock: self.halfmove_clock,
+            repetition_key: 0,
+        };
         // determine if halfmove clock should reset
-        let mut reset_half = false;
-        if let Some(piece) = self.board[from_idx] {
+        // side to move, castling and en passant all change; take out their old keys
+        self.hash ^= self.extras_hash();
+        let mut reset_half = undo.captured.is_some();
+        if let Some(piece) = moved {
             if piece.piece_type == PieceType::Pawn {
                 reset_half = true;
             }
-        }
-        // capture (normal or en-passant)
-        let dest_piece = self.board[to_idx];
-        if dest_piece.is_some() {
-            reset_half = true;
-        } else if let Some(piece) = self.board[from_idx] {
-            // en-passant capture
-            if piece.piece_type == PieceType::Pawn && self.en_passant_square == Some(to) {
-                reset_half = true;
-            }
-        }
-        // handle castling: king moves two squares
-        if let Some(piece) = self.board[from_idx] {
+            // handle castling: king moves two squares
             if piece.piece_type == PieceType::King {
-                // kingside
-                if to_idx == from_idx + 2 {
-                    let (rook_from, rook_to) = match piece.color {
-                        Color::White => (7, 5),
-                        Color::Black => (63, 61),
-                    };
-                    self.board[rook_to] = self.board[rook_from];
-                    self.board[rook_from] = None;
-                }
-                // queenside
-                if to_idx + 2 == from_idx {
-                    let (rook_from, rook_to) = match piece.color {
-                        Color::White => (0, 3),
-                        Color::Black => (56, 59),
-                    };
-                    self.board[rook_to] = self.board[rook_from];
-                    self.board[rook_from] = None;
+                if let Some((rook_from, rook_to)) = castle_rook_squares(piece.color, from_idx, to_idx) {
+                    self.set_square(rook_to, self.board[rook_from]);
+                    self.set_square(rook_from, None);
                 }
                 // disable both castling rights for this king
                 match piece.color {
@@ -441,48 +1138,54 @@ impl GameState {
                 }
             }
         }
+        // capturing a rook on its home square removes that castling right
+        match to_idx {
+            0 => self.castling_rights.white_queenside = false,
+            7 => self.castling_rights.white_kingside = false,
+            56 => self.castling_rights.black_queenside = false,
+            63 => self.castling_rights.black_kingside = false,
+            _ => {}
+        }
         // handle en passant target and capture
-        if let Some(piece) = self.board[from_idx] {
-            if piece.piece_type == PieceType::Pawn {
+        match moved {
+            Some(piece) if piece.piece_type == PieceType::Pawn => {
                 let row_from = from_idx as i8 / 8;
                 let row_to = to_idx as i8 / 8;
                 // double-step opens en passant
                 if (row_from - row_to).abs() == 2 {
                     // target square is the square passed over
-                    let between = ((from + to) / 2) as u8;
+                    let between = (from + to) / 2;
                     self.en_passant_square = Some(between);
                 } else {
                     // normal pawn move or capture
                     // if moving to en_passant_square, remove captured pawn
-                    if Some(to as u8) == self.en_passant_square {
+                    if Some(to) == self.en_passant_square {
                         let cap_idx = if piece.color == Color::White {
                             to_idx - 8
                         } else {
                             to_idx + 8
                         };
-                        self.board[cap_idx] = None;
+                        undo.captured = self.board[cap_idx].map(|pawn| (cap_idx as u8, pawn));
+                        self.set_square(cap_idx, None);
                     }
                     self.en_passant_square = None;
                 }
-            } else {
-                // any other piece clears en passant availability
-                self.en_passant_square = None;
             }
+            // any other piece clears en passant availability
+            Some(_) => self.en_passant_square = None,
+            None => {}
         }
         // move the piece
-        self.board[to_idx] = self.board[from_idx];
-        self.board[from_idx] = None;
+        self.set_square(to_idx, moved);
+        self.set_square(from_idx, None);
         // handle pawn promotion
-        if let Some(mut piece) = self.board[to_idx] {
-            if piece.piece_type == PieceType::Pawn {
-                let row = (to_idx / 8) as u8;
-                let last_rank = if piece.color == Color::White { 7 } else { 0 };
-                if row == last_rank {
-                    // promote to chosen piece or default Queen
-                    let new_type = promotion.unwrap_or(PieceType::Queen);
-                    self.board[to_idx] = Some(Piece { piece_type: new_type, color: piece.color });
-                }
-            }
+        if let Some(piece) = moved
+            && piece.piece_type == PieceType::Pawn
+            && to / 8 == if piece.color == Color::White { 7 } else { 0 }
+        {
+            // promote to chosen piece or default Queen
+            let new_type = mv.promotion.unwrap_or(PieceType::Queen);
+            self.set_square(to_idx, Some(Piece { piece_type: new_type, color: piece.color }));
         }
         // update halfmove clock
         if reset_half {
@@ -497,8 +1200,84 @@ impl GameState {
         // switch current player's turn
         self.turn = opposite_color(self.turn);
         // track repetition
-        self.update_repetition();
+        self.hash ^= self.extras_hash();
+        self.position_hashes.push(self.hash);
+        undo.repetition_key = self.hash;
+        undo
+    }
+
+    /// Takes back a move played with `make_move`, restoring the position exactly
+    pub fn unmake_move(&mut self, mv: &Move, undo: Undo) {
+        let from_idx = mv.from as usize;
+        let to_idx = mv.to as usize;
+        // forget the position the move reached
+        let reached = self.position_hashes.pop();
+        debug_assert_eq!(reached, Some(undo.repetition_key));
+        self.hash ^= self.extras_hash();
+        self.turn = opposite_color(self.turn);
+        if self.turn == Color::Black {
+            self.fullmove_clock -= 1;
+        }
+        // put the mover back (as a pawn if it promoted) and restore any captured piece
+        self.set_square(to_idx, None);
+        self.set_square(from_idx, undo.moved);
+        if let Some((square, piece)) = undo.captured {
+            self.set_square(square as usize, Some(piece));
+        }
+        // return the rook of a castling move
+        if let Some(piece) = undo.moved
+            && piece.piece_type == PieceType::King
+            && let Some((rook_from, rook_to)) = castle_rook_squares(piece.color, from_idx, to_idx)
+        {
+            self.set_square(rook_from, self.board[rook_to]);
+            self.set_square(rook_to, None);
+        }
+        self.castling_rights = undo.castling_rights;
+        self.en_passant_square = undo.en_passant_square;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.hash ^= self.extras_hash();
+    }
+
+    /// Moves a piece from one square to another, without validation, then switches turn.
+    /// The move is recorded in the game history so it can be taken back. A move that ends
+    /// the game by itself (see `automatic_outcome`) sets the outcome, and once the game has
+    /// an outcome no further moves are played.
+    pub fn move_piece(&mut self, from: u8, to: u8, promotion: Option<PieceType>) {
+        if self.outcome.is_some() {
+            return;
+        }
+        let mv = self.describe_move(from, to, promotion);
+        self.replay_move(&mv);
+    }
+
+    /// Plays a move recorded elsewhere, such as in a PGN file, and adds it to the history
+    /// even if the game already has an outcome: a record may go on past a position that
+    /// ends the game by itself. The first outcome stands.
+    pub fn replay_move(&mut self, mv: &Move) {
+        let undo = self.make_move(mv);
+        // an empty from-square only passes the turn, which is not a move worth recording
+        if undo.moved.is_some() {
+            self.history.push(*mv);
+            self.undo_stack.push(undo);
+        }
+        if self.outcome.is_none() {
+            self.outcome = self.automatic_outcome();
+        }
     }
+
+    /// Takes back the last move of the game, returning it, or None at the start of the game.
+    /// The outcome of a finished game stands.
+    pub fn take_back(&mut self) -> Option<Move> {
+        let mv = self.history.pop()?;
+        let undo = self.undo_stack.pop()?;
+        self.unmake_move(&mv, undo);
+        Some(mv)
+    }
+    /// Piece placement as bitboards, for fast scans such as evaluation
+    pub fn bitboards(&self) -> &Bitboards {
+        &self.bitboards
+    }
+
     /// Returns the type of the piece at a given board index, or None if empty
     pub fn piece_type_at(&self, idx: usize) -> Option<PieceType> {
         self.board.get(idx).and_then(|&sq| sq.map(|piece| piece.piece_type))
@@ -510,49 +1289,29 @@ impl GameState {
 
     /// Returns true if the current player is in check (their king is attacked)
     pub fn is_in_check(&self) -> bool {
-        // find king position for current turn
-        let king_pos = self.board.iter().position(|&sq| {
-            matches!(sq, Some(ref p) if p.piece_type == PieceType::King && p.color == self.turn)
-        });
-        if let Some(idx) = king_pos {
-            is_square_attacked(self, idx as u8, opposite_color(self.turn))
-        } else {
-            false
+        match self.bitboards.king_square(self.turn) {
+            Some(king) => is_square_attacked(self, king, opposite_color(self.turn)),
+            None => false,
         }
     }
 
+    /// true if the side to move has at least one legal move (stops at the first one found)
+    fn has_legal_move(&self) -> bool {
+        squares(self.bitboards.occupied_by(self.turn)).any(|from| {
+            let piece = self.board[from as usize].unwrap();
+            legal_moves_for_piece(self, from).into_iter().any(|to| keeps_king_safe(self, piece, from, to))
+        })
+    }
+
     /// Returns true if the current player is checkmated
     pub fn is_checkmate(&self) -> bool {
         // must be in check and have no legal moves
-        if !self.is_in_check() {
-            return false;
-        }
-        // search for any legal move for current player
-        for idx in 0..64u8 {
-            if let Some(piece) = self.board[idx as usize] {
-                if piece.color == self.turn {
-                    if !legal_moves_for_piece_strict(self, idx).is_empty() {
-                        return false;
-                    }
-                }
-            }
-        }
-        true
+        self.is_in_check() && !self.has_legal_move()
     }
 
     /// Returns true if the current player has no legal moves but is not in check (stalemate)
     pub fn is_stalemate(&self) -> bool {
-        if self.is_in_check() {
-            return false;
-        }
-        for idx in 0..64u8 {
-            if let Some(piece) = self.board[idx as usize] {
-                if piece.color == self.turn && !legal_moves_for_piece_strict(self, idx).is_empty() {
-                    return false;
-                }
-            }
-        }
-        true
+        !self.is_in_check() && !self.has_legal_move()
     }
 
     /// true if 50 moves (100 halfmoves) have occurred without pawn move or capture
@@ -560,22 +1319,156 @@ impl GameState {
         self.halfmove_clock >= 100
     }
 
+    /// true if 75 moves (150 halfmoves) have occurred without pawn move or capture
+    pub fn is_seventy_five_move_draw(&self) -> bool {
+        self.halfmove_clock >= 150
+    }
+
+    /// true if the current position has occurred five times
+    pub fn is_fivefold_repetition(&self) -> bool {
+        self.repetition_count() >= 5
+    }
+
+    /// A draw the side to move may claim: threefold repetition or the fifty-move rule
+    pub fn claimable_draw(&self) -> Option<Termination> {
+        if self.is_threefold_repetition() {
+            Some(Termination::ThreefoldRepetition)
+        } else if self.is_fifty_move_draw() {
+            Some(Termination::FiftyMoveRule)
+        } else {
+            None
+        }
+    }
+
+    /// How the game ends in this position without anyone claiming or agreeing to it.
+    /// Checkmate takes precedence over the 75-move rule.
+    pub fn automatic_outcome(&self) -> Option<Outcome> {
+        if self.is_checkmate() {
+            Some(Outcome::win(opposite_color(self.turn), Termination::Checkmate))
+        } else if self.is_stalemate() {
+            Some(Outcome::draw(Termination::Stalemate))
+        } else if self.is_insufficient_material() {
+            Some(Outcome::draw(Termination::InsufficientMaterial))
+        } else if self.is_fivefold_repetition() {
+            Some(Outcome::draw(Termination::FivefoldRepetition))
+        } else if self.is_seventy_five_move_draw() {
+            Some(Outcome::draw(Termination::SeventyFiveMoveRule))
+        } else {
+            None
+        }
+    }
+
+    /// How the game ends when `side` runs out of time: a loss, or a draw if the opponent
+    /// could not have checkmated
+    pub fn timeout_outcome(&self, side: Color) -> Outcome {
+        let opponent = opposite_color(side);
+        if self.has_mating_material(opponent) {
+            Outcome::win(opponent, Termination::Timeout)
+        } else {
+            Outcome::draw(Termination::TimeoutVsInsufficientMaterial)
+        }
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.outcome
+    }
+
+    /// Ends the game. The first outcome stands: returns false, changing nothing, if the
+    /// game had already ended.
+    pub fn set_outcome(&mut self, outcome: Outcome) -> bool {
+        if self.outcome.is_some() {
+            return false;
+        }
+        self.outcome = Some(outcome);
+        true
+    }
+
     /// Returns true if neither side has mating material (only kings, or king+single bishop/knight vs king)
     pub fn is_insufficient_material(&self) -> bool {
+        match (self.material(Color::White), self.material(Color::Black)) {
+            (Some(white_minors), Some(black_minors)) => white_minors + black_minors <= 1,
+            _ => false,
+        }
+    }
+
+    /// Whether `color` could still checkmate by some series of legal moves, however unlikely:
+    /// it has a pawn, rook or queen, or two minor pieces, or a lone minor piece against a king
+    /// with pieces of its own to hem it in. Bishops all on squares of one colour can never
+    /// mate. Decides whether running out of time against `color` loses or draws.
+    pub fn has_mating_material(&self, color: Color) -> bool {
+        if self.only_bishops_on_one_colour() {
+            return false;
+        }
+        match self.material(color) {
+            None => true,
+            Some(minors) if minors >= 2 => true,
+            Some(0) => false,
+            Some(_) => self.material(opposite_color(color)) != Some(0),
+        }
+    }
+
+    /// Whether every piece but the kings is a bishop, and they all stand on light squares
+    /// or all on dark ones
+    fn only_bishops_on_one_colour(&self) -> bool {
+        let mut shades = Vec::new();
+        for (idx, piece) in self.board.iter().enumerate() {
+            match piece.map(|p| p.piece_type) {
+                None | Some(PieceType::King) => {}
+                Some(PieceType::Bishop) => shades.push((idx % 8 + idx / 8) % 2),
+                Some(_) => return false,
+            }
+        }
+        shades.first().is_some_and(|&first| shades.iter().all(|&shade| shade == first))
+    }
+
+    /// How many bishops and knights `color` has, or None if it has a pawn, rook or queen
+    fn material(&self, color: Color) -> Option<u32> {
         let mut minor_count = 0;
-        for sq in &self.board {
-            if let Some(p) = sq {
-                match p.piece_type {
-                    PieceType::Pawn | PieceType::Rook | PieceType::Queen => return false,
-                    PieceType::Bishop | PieceType::Knight => minor_count += 1,
-                    PieceType::King => (),
-                }
+        for p in self.board.iter().flatten().filter(|p| p.color == color) {
+            match p.piece_type {
+                PieceType::Pawn | PieceType::Rook | PieceType::Queen => return None,
+                PieceType::Bishop | PieceType::Knight => minor_count += 1,
+                PieceType::King => (),
             }
         }
-        minor_count <= 1
+        Some(minor_count)
     }
 }
 
-fn main() {
-    let game = GameState::new();
+// ----------- Perft ------------
+
+impl GameState {
+    /// Counts the leaf nodes of the legal move tree `depth` plies deep. Used to check the
+    /// move generator against known node counts.
+    pub fn perft(&self, depth: u32) -> u64 {
+        self.clone().perft_inner(depth)
+    }
+
+    /// Perft split by root move, for locating which move a miscount comes from
+    pub fn divide(&self, depth: u32) -> Vec<(Move, u64)> {
+        let mut state = self.clone();
+        state.legal_moves().into_iter().map(|mv| {
+            let undo = state.make_move(&mv);
+            let nodes = if depth <= 1 { 1 } else { state.perft_inner(depth - 1) };
+            state.unmake_move(&mv, undo);
+            (mv, nodes)
+        }).collect()
+    }
+
+    fn perft_inner(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut nodes = 0;
+        for mv in &moves {
+            let undo = self.make_move(mv);
+            nodes += self.perft_inner(depth - 1);
+            self.unmake_move(mv, undo);
+        }
+        nodes
+    }
 }
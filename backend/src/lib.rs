@@ -0,0 +1,12 @@
+pub mod api;
+pub mod bitboard;
+pub mod clock;
+pub mod engine;
+pub mod external_engine;
+pub mod game;
+pub mod matchmaking;
+pub mod pgn;
+pub mod protocol;
+pub mod rating;
+pub mod storage;
+pub mod zobrist;
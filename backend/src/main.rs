@@ -1,30 +1,494 @@
 use futures_util::{StreamExt, SinkExt};
 use std::sync::Arc;
-use tokio::sync::{broadcast, Mutex as TokioMutex};
+use tokio::sync::{broadcast, mpsc, watch, Mutex as TokioMutex};
 use warp::Filter;
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use futures_util::stream::SplitSink;
 use warp::ws::{Message as WsMessage, WebSocket};
 use std::env;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Weak;
+use std::time::{Duration, Instant, SystemTime};
+use warp::Reply;
 
-mod game;
-use game::{GameState, legal_moves_for_piece_strict, Color, PieceType};
-use serde_json;
-use serde_json::json;
+use chess::api::{self, ApiError, GameDetail, GameListing, GamesPage, GamesQuery, LiveGames, PlayerQuery, PlayerRatings};
+use chess::clock::{Clock, TimeControl};
+use chess::engine::{self, Limits};
+use chess::external_engine::ExternalEngine;
+use chess::matchmaking::{MatchQueue, QueueEntry};
+use chess::game::{GameState, legal_moves_for_piece_strict, opposite_color, Color, Move, MoveError, Outcome, Termination};
+use chess::pgn;
+use chess::rating::{self, Category, Rating};
+use chess::protocol::{ClientMessage, CloseReason, ColorChoice, EngineChoice, GameSummary, MoveList, Promotion, RatingView, Role, RoomStatus, SeatKind, ServerMessage, StateUpdate, PROTOCOL_VERSION};
+use chess::storage::{GameFilter, GameRecord, GameStore, MemoryStore, PlayerRecord, SqliteStore, StorageError};
 
-type Clients = Arc<TokioMutex<HashMap<usize, Arc<TokioMutex<SplitSink<WebSocket, WsMessage>>>>>>;
 static CLIENT_ID_COUNTER: AtomicUsize = AtomicUsize::new(1);
 static GAME_ID_COUNTER: AtomicUsize = AtomicUsize::new(1);
+/// The game id of a client in no room, such as one searching for a game; real ids start at 1
+const NO_ROOM: usize = 0;
+
+type ClientSink = Arc<TokioMutex<SplitSink<WebSocket, WsMessage>>>;
+
+/// How long a player's seat is held after their connection drops, unless configured
+const DEFAULT_ABANDON_AFTER: Duration = Duration::from_secs(60);
+/// How long a room with no game in progress stays open with nothing happening, unless configured
+const DEFAULT_IDLE_AFTER: Duration = Duration::from_secs(10 * 60);
+/// How often searching players are paired, and told how their search is going
+const MATCHMAKING_TICK: Duration = Duration::from_secs(1);
+/// How many of a player's saved games count towards balancing their colors
+const RECENT_COLOR_GAMES: usize = 10;
+
+/// The timeouts that end games and close rooms, from `--abandon-after <secs>` (or
+/// CHESS_ABANDON_AFTER) and `--idle-after <secs>` (or CHESS_IDLE_AFTER)
+#[derive(Clone, Copy)]
+struct Lifecycle {
+    /// a player gone longer than this has abandoned their game, which their opponent wins
+    abandon_after: Duration,
+    /// a waiting or finished room with no activity for this long is closed
+    idle_after: Duration,
+}
+
+struct RoomClient {
+    // None for spectators
+    color: Option<Color>,
+    // players reclaim their seat with this after a dropped connection
+    resume_token: Option<String>,
+    // the connection dropped and the seat is held for a resume
+    disconnected: bool,
+}
 
 struct GameRoom {
     game_state: Arc<TokioMutex<GameState>>,
+    channel: Arc<RoomChannel>,
+    clients: HashMap<usize, RoomClient>,
+    // private rooms are only joined by game code, and left out of the live games
+    private: bool,
+    // side that asked to take back its last move, until the opponent answers
+    takeback_offer: Option<Color>,
+    // side that offered a draw, until the opponent answers or makes a move
+    draw_offer: Option<Color>,
+    // the computer's seat, for games against the computer
+    engine: Option<EngineSeat>,
+    // clock settings for every game played in the room; None for untimed games
+    time_control: Option<TimeControl>,
+}
+
+impl GameRoom {
+    fn new(store: &Arc<dyn GameStore>, private: bool, engine: Option<EngineSeat>, time_control: Option<TimeControl>) -> Self {
+        let computer = |color: Color| PlayerRecord { computer: engine.as_ref().is_some_and(|seat| seat.color == color), ..PlayerRecord::default() };
+        let record = GameRecord {
+            id: None,
+            game_code: String::new(),
+            white: computer(Color::White),
+            black: computer(Color::Black),
+            time_control,
+            private,
+            start_fen: None,
+            moves: Vec::new(),
+            outcome: None,
+            clock_ms: None,
+            started_at: unix_millis(SystemTime::now()),
+            updated_at: unix_millis(SystemTime::now()),
+            ended_at: None,
+        };
+        GameRoom::with_game(new_game(time_control), store, record, engine)
+    }
+
+    /// A room playing `gs`, saved to `store` as `record`
+    fn with_game(gs: GameState, store: &Arc<dyn GameStore>, record: GameRecord, engine: Option<EngineSeat>) -> Self {
+        let game_state = Arc::new(TokioMutex::new(gs));
+        GameRoom {
+            private: record.private,
+            time_control: record.time_control,
+            channel: RoomChannel::open(store.clone(), record, &game_state),
+            game_state,
+            clients: HashMap::new(),
+            takeback_offer: None,
+            draw_offer: None,
+            engine,
+        }
+    }
+
+    /// Whether a player (even one whose seat is held) or the computer plays `color`
+    fn seat_taken(&self, color: Color) -> bool {
+        self.clients.values().any(|client| client.color == Some(color))
+            || self.engine.as_ref().is_some_and(|seat| seat.color == color)
+    }
+
+    /// The color a newcomer would play, or None if both are taken (by players or the computer)
+    fn free_color(&self) -> Option<Color> {
+        [Color::White, Color::Black].into_iter().find(|&color| !self.seat_taken(color))
+    }
+
+    /// Where the room is in its life, given its game
+    fn status(&self, gs: &GameState) -> RoomStatus {
+        if gs.outcome().is_some() {
+            RoomStatus::Finished
+        } else if self.clients.values().any(|client| client.disconnected) {
+            RoomStatus::Abandoned
+        } else if self.free_color().is_some() {
+            RoomStatus::Waiting
+        } else {
+            RoomStatus::Playing
+        }
+    }
+
+    /// Summary of the game for the list of live games
+    async fn summary(&self) -> GameSummary {
+        let gs = self.game_state.lock().await;
+        let seat = |color: Color| {
+            if self.engine.as_ref().is_some_and(|seat| seat.color == color) {
+                SeatKind::Computer
+            } else if self.clients.values().any(|client| client.color == Some(color)) {
+                SeatKind::Player
+            } else {
+                SeatKind::Open
+            }
+        };
+        GameSummary {
+            game_code: gs.game_code().to_string(),
+            status: self.status(&gs),
+            white: seat(Color::White),
+            black: seat(Color::Black),
+            spectators: self.channel.spectators.load(Ordering::SeqCst),
+            moves: gs.history().len(),
+            turn: gs.turn(),
+        }
+    }
+}
+
+/// A room's broadcast channel and the counters every state broadcast carries. Engine
+/// tasks hold a handle to it to broadcast their own moves. Every state broadcast also
+/// saves the game, and counts as activity in the room. Tasks working for the room stop
+/// when it is closed.
+struct RoomChannel {
     tx: broadcast::Sender<String>,
-    clients: HashMap<usize, Arc<TokioMutex<SplitSink<WebSocket, WsMessage>>>>,
+    // number of spectating clients
+    spectators: AtomicUsize,
+    // sequence number of the last state broadcast
+    seq: AtomicU64,
+    // storage work for the room's writer task, which does it in order
+    store_jobs: mpsc::UnboundedSender<StoreJob>,
+    // the saved form of the current game, brought up to date on every save
+    record: std::sync::Mutex<GameRecord>,
+    // games started in the room so far, changed under `record`'s lock; tells the writer
+    // which saves are of the same game
+    games: AtomicU64,
+    // when the last state broadcast went out
+    last_activity: std::sync::Mutex<Instant>,
+    // set once the room is closed
+    closed: watch::Sender<bool>,
+    // white's and black's ratings for the game's time control, as shown with its state
+    ratings: std::sync::Mutex<[Option<Rating>; 2]>,
+    // the game's moves as broadcast, each described once
+    moves: std::sync::Mutex<MoveList>,
+}
+
+impl RoomChannel {
+    /// The channel of a room playing `game_state`, and the writer task that saves it
+    fn open(store: Arc<dyn GameStore>, record: GameRecord, game_state: &Arc<TokioMutex<GameState>>) -> Arc<Self> {
+        let (tx, _rx) = broadcast::channel::<String>(100);
+        let (store_jobs, jobs) = mpsc::unbounded_channel();
+        let channel = Arc::new(RoomChannel {
+            tx,
+            spectators: AtomicUsize::new(0),
+            seq: AtomicU64::new(0),
+            store_jobs,
+            record: std::sync::Mutex::new(record),
+            games: AtomicU64::new(0),
+            last_activity: std::sync::Mutex::new(Instant::now()),
+            closed: watch::channel(false).0,
+            ratings: std::sync::Mutex::new([None, None]),
+            moves: std::sync::Mutex::new(MoveList::default()),
+        });
+        spawn_store_writer(store, jobs, Arc::downgrade(&channel), Arc::downgrade(game_state));
+        channel.refresh_ratings();
+        channel
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.tx.subscribe()
+    }
+
+    fn send(&self, msg: &ServerMessage) {
+        let _ = self.tx.send(msg.to_text());
+    }
+
+    /// Saves the game and broadcasts its state with the next sequence number. Callers
+    /// hold the game state's lock, so updates go out in the order they are numbered.
+    fn send_state(&self, gs: &GameState) {
+        self.save(gs);
+        self.broadcast_state(gs);
+    }
+
+    /// Broadcasts the game state with the next sequence number, without saving it, as
+    /// when the players' ratings change
+    fn broadcast_state(&self, gs: &GameState) {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst) + 1;
+        let spectators = self.spectators.load(Ordering::SeqCst);
+        let moves = self.moves.lock().unwrap().update(gs).to_vec();
+        let mut update = StateUpdate::new(gs, moves, seq, spectators);
+        let [white, black] = *self.ratings.lock().unwrap();
+        update.white_rating = white.as_ref().map(RatingView::from);
+        update.black_rating = black.as_ref().map(RatingView::from);
+        self.send(&ServerMessage::State(Box::new(update)));
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    /// How long since the last state broadcast
+    fn idle_for(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+
+    /// Tells everyone in the room that it is closed, and stops the tasks working for it.
+    /// A saved game that never got a result is marked ended, so it is not restored.
+    fn close(&self, reason: CloseReason) {
+        self.send(&ServerMessage::RoomClosed { reason });
+        self.closed.send_replace(true);
+        self.end_record(&mut self.record.lock().unwrap());
+    }
+
+    /// Marks a saved game that never got a result as ended, so it is not restored
+    fn end_record(&self, record: &mut GameRecord) {
+        if record.ended_at.is_none() {
+            let now = unix_millis(SystemTime::now());
+            record.updated_at = now;
+            record.ended_at = Some(now);
+            let game = self.games.load(Ordering::SeqCst);
+            let _ = self.store_jobs.send(StoreJob::Save { game, record: record.clone(), rate: false, if_saved: true });
+        }
+    }
+
+    /// Completes when the room is closed. It does not hold the channel, so tasks holding
+    /// the room weakly can wait on it.
+    fn on_close(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut closed = self.closed.subscribe();
+        async move {
+            // an error means the channel is gone, which closes the room all the same
+            let _ = closed.wait_for(|&closed| closed).await;
+        }
+    }
+
+    /// Has the game written to storage, once it has begun, and rated when it has just
+    /// ended. The writer does both in the background; play goes on regardless.
+    fn save(&self, gs: &GameState) {
+        let mut record = self.record.lock().unwrap();
+        if record.id.is_none() && gs.history().is_empty() && gs.outcome().is_none() {
+            return;
+        }
+        let was_over = record.outcome.is_some();
+        record.update_from(gs, unix_millis(SystemTime::now()));
+        let now = Instant::now();
+        record.clock_ms = gs.clock().map(|clock| {
+            let millis = |color| clock.remaining(color, now).as_millis() as u64;
+            (millis(Color::White), millis(Color::Black))
+        });
+        let rate = !was_over && record.outcome.is_some();
+        let game = self.games.load(Ordering::SeqCst);
+        let _ = self.store_jobs.send(StoreJob::Save { game, record: record.clone(), rate, if_saved: false });
+    }
+
+    /// Has the writer look up the players' current ratings for the game's time control
+    fn refresh_ratings(&self) {
+        let record = self.record.lock().unwrap().clone();
+        let _ = self.store_jobs.send(StoreJob::RefreshRatings(record));
+    }
+
+    /// The saved form of `gs` as of now, whether or not it has been saved yet
+    fn current_record(&self, gs: &GameState) -> GameRecord {
+        let mut record = self.record.lock().unwrap().clone();
+        record.update_from(gs, unix_millis(SystemTime::now()));
+        record
+    }
+
+    /// Starts a new stored game for a rematch, with the same players. The outgoing game
+    /// is marked ended first if it has not been.
+    fn start_record(&self) {
+        let mut record = self.record.lock().unwrap();
+        self.end_record(&mut record);
+        self.games.fetch_add(1, Ordering::SeqCst);
+        record.id = None;
+        record.started_at = unix_millis(SystemTime::now());
+        record.ended_at = None;
+    }
+
+    /// Records who sits on one side of the board
+    fn set_player(&self, color: Color, player: PlayerRecord) {
+        *self.record.lock().unwrap().player_mut(color) = player;
+        self.refresh_ratings();
+    }
+}
+
+/// Storage work for a room, done by its writer task
+enum StoreJob {
+    /// save the room's `game`th game as `record`, rating it if `rate`; `if_saved` skips a
+    /// game that was never saved
+    Save { game: u64, record: GameRecord, rate: bool, if_saved: bool },
+    /// look up the ratings of the players in `record`
+    RefreshRatings(GameRecord),
+}
+
+/// Does a room's storage work in the order it was asked for, each piece on the blocking
+/// pool so SQLite never stalls the executor or a lock. Saved ids go back into the room's
+/// record, and new ratings out to the room with a fresh state. The writer finishes the
+/// work queued when the room goes away, then stops.
+fn spawn_store_writer(store: Arc<dyn GameStore>, mut jobs: mpsc::UnboundedReceiver<StoreJob>, channel: Weak<RoomChannel>, game_state: Weak<TokioMutex<GameState>>) {
+    tokio::spawn(async move {
+        // the room's latest game and its id, once saved
+        let mut saved: Option<(u64, u64)> = None;
+        while let Some(job) = jobs.recv().await {
+            let store = store.clone();
+            let ratings = match job {
+                StoreJob::Save { game, mut record, rate, if_saved } => {
+                    if record.id.is_none() {
+                        record.id = saved.filter(|&(saved_game, _)| saved_game == game).map(|(_, id)| id);
+                    }
+                    if if_saved && record.id.is_none() {
+                        continue;
+                    }
+                    let done = tokio::task::spawn_blocking(move || save_game(&*store, record, rate)).await;
+                    let Ok(Some((id, ratings))) = done else { continue };
+                    saved = Some((game, id));
+                    if let Some(channel) = channel.upgrade() {
+                        let mut record = channel.record.lock().unwrap();
+                        if channel.games.load(Ordering::SeqCst) == game {
+                            record.id = Some(id);
+                        }
+                    }
+                    let Some(ratings) = ratings else { continue };
+                    ratings
+                }
+                StoreJob::RefreshRatings(record) => {
+                    let Ok(ratings) = tokio::task::spawn_blocking(move || player_ratings(&*store, &record)).await else { continue };
+                    ratings
+                }
+            };
+            let (Some(channel), Some(game_state)) = (channel.upgrade(), game_state.upgrade()) else { continue };
+            if *channel.ratings.lock().unwrap() == ratings {
+                continue;
+            }
+            let gs = game_state.lock().await;
+            *channel.ratings.lock().unwrap() = ratings;
+            channel.broadcast_state(&gs);
+        }
+    });
+}
+
+/// Saves a game, and rates it if `rate`. Gives the game's id and, if it was rated, the
+/// players' new ratings; None if it could not be saved. Failures are logged.
+fn save_game(store: &dyn GameStore, record: GameRecord, rate: bool) -> Option<(u64, Option<[Option<Rating>; 2]>)> {
+    let id = match store.save(&record) {
+        Ok(id) => id,
+        Err(err) => {
+            eprintln!("Could not save game {}: {}", record.game_code, err);
+            return None;
+        }
+    };
+    if !rate {
+        return Some((id, None));
+    }
+    let record = GameRecord { id: Some(id), ..record };
+    match rating::rate_game(store, &record, record.updated_at) {
+        Ok(Some(_)) => Some((id, Some(player_ratings(store, &record)))),
+        Ok(None) => Some((id, None)),
+        Err(err) => {
+            eprintln!("Could not rate game {}: {}", record.game_code, err);
+            Some((id, None))
+        }
+    }
+}
+
+/// White's and black's current ratings for a game's time control
+fn player_ratings(store: &dyn GameStore, record: &GameRecord) -> [Option<Rating>; 2] {
+    let now = unix_millis(SystemTime::now());
+    let rating_of = |color| {
+        let player = record.player(color);
+        let name = player.name.as_deref().filter(|_| !player.computer)?;
+        let category = record.category()?;
+        match store.rating(name, category) {
+            Ok(rating) => Some(rating.unwrap_or_default().decayed(now)),
+            Err(err) => {
+                eprintln!("Could not look up the rating of {}: {}", name, err);
+                None
+            }
+        }
+    };
+    [rating_of(Color::White), rating_of(Color::Black)]
+}
+
+/// Milliseconds since the Unix epoch, as timestamps are stored
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Query string of the WebSocket upgrade: `/ws?code=123456` joins that game,
+/// `/ws?code=123456&spectate=true` watches it, and `/ws?resume=<token>` takes back a seat
+/// held after a dropped connection. `name=<name>` names the player; once a name is in use,
+/// `token=<token>` from its `name_registered` must come with it.
+#[derive(serde::Deserialize)]
+struct JoinQuery {
+    code: Option<String>,
+    #[serde(default)]
+    spectate: bool,
+    resume: Option<String>,
+    /// the player's name, recorded with their games and rated
+    name: Option<String>,
+    /// proves the name is the player's
+    token: Option<String>,
+}
+
+#[derive(Clone)]
+struct EngineSeat {
+    color: Color,
+    player: EnginePlayer,
+}
+
+#[derive(Clone)]
+enum EnginePlayer {
+    /// the engine module in this crate, searching on a blocking thread
+    Builtin(Limits),
+    /// a UCI executable owned by the room, given `budget` per move
+    External { engine: Arc<TokioMutex<ExternalEngine>>, budget: Duration },
 }
 
 type GameRooms = Arc<TokioMutex<HashMap<usize, GameRoom>>>;
 
+/// A client in the matchmaking queue, and what it takes to seat it once it is paired
+struct Searcher {
+    ws_tx: ClientSink,
+    name: Option<String>,
+    // tells the client's connection where it was seated
+    seated: mpsc::UnboundedSender<Seated>,
+}
+
+/// A seat the matchmaker gave a searching client
+struct Seated {
+    game_id: usize,
+    color: Color,
+    forwarder: tokio::task::JoinHandle<()>,
+}
+
+/// The matchmaking queue and the clients in it. Its lock is taken before the rooms'.
+#[derive(Default)]
+struct Lobby {
+    queue: MatchQueue,
+    searchers: HashMap<usize, Searcher>,
+}
+
+type SharedLobby = Arc<TokioMutex<Lobby>>;
+
+/// What every connection shares besides the rooms
+#[derive(Clone)]
+struct Services {
+    store: Arc<dyn GameStore>,
+    // UCI executable for play_vs_computer with "engine": "external"
+    uci_engine: Option<PathBuf>,
+    lifecycle: Lifecycle,
+    lobby: SharedLobby,
+}
+
 #[tokio::main]
 async fn main() {
     // parse command-line flags
@@ -33,15 +497,41 @@ async fn main() {
     let verbose = args.iter().any(|arg| arg == "--verbose");
     let silent_ws = silent;
     let verbose_ws = verbose;
-    // track multiple games
+    // optional UCI engine executable for play_vs_computer, from --uci-engine <path> or UCI_ENGINE
+    let uci_engine: Option<PathBuf> = setting(&args, "--uci-engine", "UCI_ENGINE").map(PathBuf::from);
+    if let Some(path) = &uci_engine {
+        println!("External UCI engine: {}", path.display());
+    }
+    // games are saved to an SQLite database, from --db <path> or CHESS_DB (chess.db by default)
+    let db_path = setting(&args, "--db", "CHESS_DB").unwrap_or_else(|| "chess.db".to_string());
+    let store: Arc<dyn GameStore> = match SqliteStore::open(&db_path) {
+        Ok(store) => Arc::new(store),
+        Err(err) => {
+            eprintln!("Could not open {}: {}; games will not be saved", db_path, err);
+            Arc::new(MemoryStore::new())
+        }
+    };
+    // when abandoned games end and idle rooms close
+    let seconds = |flag, var, default| setting(&args, flag, var).and_then(|secs| secs.parse().ok()).map_or(default, Duration::from_secs);
+    let lifecycle = Lifecycle {
+        abandon_after: seconds("--abandon-after", "CHESS_ABANDON_AFTER", DEFAULT_ABANDON_AFTER),
+        idle_after: seconds("--idle-after", "CHESS_IDLE_AFTER", DEFAULT_IDLE_AFTER),
+    };
+    // track multiple games, starting with those in progress when the server last stopped
     let game_rooms: GameRooms = Arc::new(TokioMutex::new(HashMap::new()));
+    restore_rooms(&store, &game_rooms, lifecycle, verbose || !silent).await;
+    spawn_janitor(game_rooms.clone(), lifecycle, verbose || !silent);
+    let lobby: SharedLobby = Arc::new(TokioMutex::new(Lobby::default()));
+    spawn_matchmaker(lobby.clone(), game_rooms.clone(), store.clone(), verbose || !silent);
 
     let game_rooms_ws = game_rooms.clone();
+    let services = Services { store: store.clone(), uci_engine, lifecycle, lobby };
     let ws_route = warp::path("ws")
         .and(warp::ws())
-        .and(warp::any().map(move || (game_rooms_ws.clone(), silent_ws, verbose_ws)))
-        .map(|ws: warp::ws::Ws, (game_rooms, silent, verbose)| {
-            ws.on_upgrade(move |socket| handle_connection(socket, game_rooms, silent, verbose))
+        .and(warp::query::<JoinQuery>())
+        .and(warp::any().map(move || (game_rooms_ws.clone(), silent_ws, verbose_ws, services.clone())))
+        .map(|ws: warp::ws::Ws, query: JoinQuery, (game_rooms, silent, verbose, services)| {
+            ws.on_upgrade(move |socket| handle_connection(socket, game_rooms, query, silent, verbose, services))
         });
     // Static file handler for frontend
     let static_route = warp::path::end()
@@ -49,8 +539,38 @@ async fn main() {
     // Serve frontend assets (images, JS, CSS)
     let assets_route = warp::path("frontend")
         .and(warp::fs::dir("../frontend"));
-    // Combine routes: WebSocket, index.html, and assets
-    let routes = ws_route.or(static_route).or(assets_route);
+    // PGN export of a game, looked up by game code in the rooms and then the store
+    let game_rooms_api = game_rooms.clone();
+    let with_rooms = warp::any().map(move || game_rooms_api.clone());
+    let with_store = warp::any().map(move || store.clone());
+    let pgn_route = warp::path!("games" / String / "pgn")
+        .and(warp::get())
+        .and(with_rooms.clone())
+        .and(with_store.clone())
+        .then(serve_pgn);
+    // JSON API: saved games, live games, and one game by code ("live" is not a game code)
+    let api_games_route = warp::path!("api" / "games")
+        .and(warp::get())
+        .and(warp::query::<GamesQuery>())
+        .and(with_store.clone())
+        .then(api_games);
+    let api_live_route = warp::path!("api" / "games" / "live")
+        .and(warp::get())
+        .and(with_rooms.clone())
+        .then(api_live_games);
+    let api_game_route = warp::path!("api" / "games" / String)
+        .and(warp::get())
+        .and(with_rooms)
+        .and(with_store.clone())
+        .then(api_game);
+    let api_player_route = warp::path!("api" / "players" / String)
+        .and(warp::get())
+        .and(warp::query::<PlayerQuery>())
+        .and(with_store)
+        .then(api_player);
+    // Combine routes: WebSocket, index.html, assets, PGN export and the API
+    let routes = ws_route.or(static_route).or(assets_route).or(pgn_route)
+        .or(api_games_route).or(api_live_route).or(api_game_route).or(api_player_route);
     // Determine port from env or default to 8080
     let port: u16 = std::env::var("PORT")
         .ok()
@@ -62,224 +582,1308 @@ async fn main() {
         .run(([0, 0, 0, 0], port))
         .await;
 }
+
+/// The value given on the command line after `flag`, or else the environment variable `var`
+fn setting(args: &[String], flag: &str, var: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1).cloned())
+        .or_else(|| env::var(var).ok())
+}
+
+/// Reopens the rooms of games that were in progress when the server stopped. Players'
+/// seats are held as if they had just disconnected, and a computer opponent comes back
+/// as the built-in engine.
+async fn restore_rooms(store: &Arc<dyn GameStore>, game_rooms: &GameRooms, lifecycle: Lifecycle, log: bool) {
+    let records = match store.active() {
+        Ok(records) => records,
+        Err(err) => {
+            eprintln!("Could not load saved games: {}", err);
+            return;
+        }
+    };
+    let mut rooms = game_rooms.lock().await;
+    for record in records {
+        let mut gs = match record.replay() {
+            Ok(gs) => gs,
+            Err(err) => {
+                eprintln!("Could not restore game {}: {}", record.game_code, err);
+                continue;
+            }
+        };
+        // the clock picks up where it was saved; the side to move's time runs from now
+        let running = (!gs.history().is_empty()).then(|| gs.turn());
+        gs.set_clock(record.time_control.map(|control| match record.clock_ms {
+            Some((white, black)) => Clock::restore(control, Duration::from_millis(white), Duration::from_millis(black), running, Instant::now()),
+            None => Clock::new(control),
+        }));
+        let engine = [Color::White, Color::Black].into_iter()
+            .find(|&color| record.player(color).computer)
+            .map(|color| EngineSeat { color, player: EnginePlayer::Builtin(engine_limits(None, None)) });
+        let held: Vec<(Color, String)> = [Color::White, Color::Black].into_iter()
+            .filter_map(|color| record.player(color).resume_token.clone().map(|token| (color, token)))
+            .collect();
+        let game_id = GAME_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let mut room = GameRoom::with_game(gs, store, record, engine.clone());
+        for (color, token) in held {
+            let client_id = CLIENT_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+            room.clients.insert(client_id, RoomClient { color: Some(color), resume_token: Some(token), disconnected: true });
+            release_after(lifecycle.abandon_after, game_rooms.clone(), game_id, client_id, color, log);
+        }
+        let gs = room.game_state.lock().await;
+        if log {
+            println!("Restored game {} after {} moves", gs.game_code(), gs.history().len());
+        }
+        watch_flag(&gs, &room.game_state, &room.channel, log);
+        drop(gs);
+        if let Some(seat) = engine {
+            spawn_engine_move(room.game_state.clone(), room.channel.clone(), seat, log);
+        }
+        rooms.insert(game_id, room);
+    }
+}
+
+/// Serves the PGN of the game a room is playing under the given game code, or else of the
+/// latest saved game with it, or 404 if there is neither
+async fn serve_pgn(code: String, game_rooms: GameRooms, store: Arc<dyn GameStore>) -> warp::reply::Response {
+    let live = {
+        let rooms = game_rooms.lock().await;
+        match find_room_by_code(&rooms, &code).await.and_then(|id| rooms.get(&id)) {
+            Some(room) => {
+                let gs = room.game_state.lock().await;
+                Some(pgn::write_pgn(&gs, &room.channel.current_record(&gs).pgn_headers()))
+            }
+            None => None,
+        }
+    };
+    let body = match live {
+        Some(body) => body,
+        None => {
+            let replayed = with_store(&store, move |store| store.load(&code).and_then(|record| match record {
+                Some(record) => record.replay().map(|gs| Some(pgn::write_pgn(&gs, &record.pgn_headers()))),
+                None => Ok(None),
+            })).await;
+            match replayed {
+                Ok(Some(body)) => body,
+                Ok(None) => return warp::reply::with_status("unknown game code", warp::http::StatusCode::NOT_FOUND).into_response(),
+                Err(err) => return warp::reply::with_status(err.to_string(), warp::http::StatusCode::INTERNAL_SERVER_ERROR).into_response(),
+            }
+        }
+    };
+    warp::reply::with_header(body, "content-type", "application/x-chess-pgn").into_response()
+}
+
+/// Runs storage work on the blocking pool, so SQLite never stalls the executor
+async fn with_store<T: Send + 'static>(store: &Arc<dyn GameStore>, work: impl FnOnce(&dyn GameStore) -> T + Send + 'static) -> T {
+    let store = store.clone();
+    match tokio::task::spawn_blocking(move || work(&*store)).await {
+        Ok(done) => done,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// JSON reply for a failed API request
+fn api_error(status: warp::http::StatusCode, err: ApiError) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&err), status).into_response()
+}
+
+/// `GET /api/games`: saved games, newest first, filtered and paginated by the query
+async fn api_games(query: GamesQuery, store: Arc<dyn GameStore>) -> warp::reply::Response {
+    let (filter, page, per_page) = match query.filter() {
+        Ok(filter) => filter,
+        Err(err) => return api_error(warp::http::StatusCode::BAD_REQUEST, err),
+    };
+    match with_store(&store, move |store| store.list(&filter)).await {
+        Ok(found) => {
+            let games = found.games.iter().map(GameListing::from).collect();
+            warp::reply::json(&GamesPage { games, page, per_page, total: found.total }).into_response()
+        }
+        Err(err) => api_error(warp::http::StatusCode::INTERNAL_SERVER_ERROR, ApiError::new("storage_error", err.to_string())),
+    }
+}
+
+/// `GET /api/games/live`: the public games being played now, as `list_games` gives them
+async fn api_live_games(game_rooms: GameRooms) -> warp::reply::Response {
+    let games = live_games(&*game_rooms.lock().await).await;
+    warp::reply::json(&LiveGames { games }).into_response()
+}
+
+/// `GET /api/players/{name}`: a player's ratings and their latest rating changes
+async fn api_player(name: String, query: PlayerQuery, store: Arc<dyn GameStore>) -> warp::reply::Response {
+    let Some(name) = api::decode_path_segment(&name) else {
+        return api_error(warp::http::StatusCode::BAD_REQUEST, ApiError::new("bad_request", "the name is not valid UTF-8"));
+    };
+    let category = match query.category() {
+        Ok(category) => category,
+        Err(err) => return api_error(warp::http::StatusCode::BAD_REQUEST, err),
+    };
+    let player = name.clone();
+    let found = with_store(&store, move |store| {
+        let ratings = store.ratings(&player)?;
+        Ok::<_, StorageError>((ratings, store.rating_history(&player, category, api::RATING_HISTORY_LENGTH)?))
+    }).await;
+    match found {
+        Ok((ratings, history)) => {
+            let now = unix_millis(SystemTime::now());
+            warp::reply::json(&PlayerRatings::new(name, ratings, history, now)).into_response()
+        }
+        Err(err) => api_error(warp::http::StatusCode::INTERNAL_SERVER_ERROR, ApiError::new("storage_error", err.to_string())),
+    }
+}
+
+/// `GET /api/games/{code}`: the game a room is playing under the code, or else the latest
+/// saved game with it, move by move
+async fn api_game(code: String, game_rooms: GameRooms, store: Arc<dyn GameStore>) -> warp::reply::Response {
+    let live = {
+        let rooms = game_rooms.lock().await;
+        match find_room_by_code(&rooms, &code).await.and_then(|id| rooms.get(&id)) {
+            Some(room) => Some(room.channel.current_record(&*room.game_state.lock().await)),
+            None => None,
+        }
+    };
+    let (record, is_live) = match live {
+        Some(record) => (record, true),
+        None => match with_store(&store, move |store| store.load(&code)).await {
+            Ok(Some(record)) => (record, false),
+            Ok(None) => return api_error(warp::http::StatusCode::NOT_FOUND, ApiError::new("unknown_game", "no game has that code")),
+            Err(err) => return api_error(warp::http::StatusCode::INTERNAL_SERVER_ERROR, ApiError::new("storage_error", err.to_string())),
+        },
+    };
+    match GameDetail::new(&record, is_live) {
+        Ok(detail) => warp::reply::json(&detail).into_response(),
+        Err(err) => api_error(warp::http::StatusCode::INTERNAL_SERVER_ERROR, ApiError::new("storage_error", err.to_string())),
+    }
+}
+
+/// Id of the room currently playing the game with this code
+async fn find_room_by_code(rooms: &HashMap<usize, GameRoom>, code: &str) -> Option<usize> {
+    for (&id, room) in rooms {
+        if room.game_state.lock().await.game_code() == code {
+            return Some(id);
+        }
+    }
+    None
+}
+
+/// Public games that are still being played, for spectators to pick from
+async fn live_games(rooms: &HashMap<usize, GameRoom>) -> Vec<GameSummary> {
+    let mut games = Vec::new();
+    for room in rooms.values().filter(|room| !room.private) {
+        games.push(room.summary().await);
+    }
+    games
+}
+
+/// Whether a room's game is under way: both seats are taken, or held for a player who
+/// dropped, and it has no outcome yet
+async fn in_live_game(game_rooms: &GameRooms, game_id: usize) -> bool {
+    let rooms = game_rooms.lock().await;
+    let Some(room) = rooms.get(&game_id) else { return false };
+    let status = room.status(&*room.game_state.lock().await);
+    matches!(status, RoomStatus::Playing | RoomStatus::Abandoned)
+}
+
+/// Builds a move from the `destination` (and optional `promotion`) of a request_move,
+/// using the square picked by the last get_legal_moves as the source
+fn move_from_indices(gs: &GameState, destination: Option<u8>, promotion: Option<Promotion>, from: Option<u8>) -> Result<Move, (&'static str, String)> {
+    let Some(dest) = destination else {
+        return Err(("bad_request", "a move needs uci, san or destination".to_string()));
+    };
+    let Some(from) = from else {
+        return Err(("no_source_square", "no source square selected for move".to_string()));
+    };
+    gs.validate_move(from, dest, promotion.map(Promotion::piece_type)).map_err(|err| (err.code(), err.to_string()))
+}
+
+/// Sends a message to a single client
+async fn send_message(ws_tx: &ClientSink, msg: &ServerMessage) {
+    let mut sink = ws_tx.lock().await;
+    let _ = sink.send(WsMessage::text(msg.to_text())).await;
+}
+
+/// Replies to a single client with a structured error for a rejected request
+async fn send_error(ws_tx: &ClientSink, request: &str, code: &str, message: &str) {
+    send_message(ws_tx, &ServerMessage::error(Some(request), code, message)).await;
+}
+
+/// An error reply to a request, built while locks are held and sent once they are released
+fn refusal(request: &str, code: &str, message: &str) -> Option<ServerMessage> {
+    Some(ServerMessage::error(Some(request), code, message))
+}
+
+/// Sends the reply a request came to, if any. Handlers build it while holding the lobby,
+/// rooms or game lock and send it after dropping them, so a slow socket stalls no room.
+async fn send_reply(ws_tx: &ClientSink, reply: Option<ServerMessage>) {
+    if let Some(msg) = reply {
+        send_message(ws_tx, &msg).await;
+    }
+}
+
+/// Search limits for a play_vs_computer request: a fixed `depth`, or `movetime_ms` per move
+/// (one second if neither is given). External engines always get a time budget.
+fn engine_limits(depth: Option<u32>, movetime_ms: Option<u64>) -> Limits {
+    if let Some(depth) = depth {
+        return Limits::depth(depth.clamp(1, 8));
+    }
+    let millis = movetime_ms.unwrap_or(1000);
+    Limits::time(Duration::from_millis(millis.clamp(50, 10_000)))
+}
+
+/// A fresh game, with a clock if the room is timed
+fn new_game(time_control: Option<TimeControl>) -> GameState {
+    let mut gs = GameState::new();
+    gs.set_clock(time_control.map(Clock::new));
+    gs
+}
+
+/// Whether the game has ended, so that no more moves are played
+fn game_over(gs: &GameState) -> bool {
+    gs.outcome().is_some()
+}
+
+/// Ends the game and stops the clock, unless it had already ended
+fn finish(gs: &mut GameState, outcome: Outcome) {
+    if gs.set_outcome(outcome)
+        && let Some(clock) = gs.clock_mut()
+    {
+        clock.stop(Instant::now());
+    }
+}
+
+/// Plays a validated move for the side to move, pressing the clock. If the side had run
+/// out of time the move is not played and the game is over on time instead; either way
+/// the room sees the new state.
+fn play_move(gs: &mut GameState, mv: &Move, game_state: &Arc<TokioMutex<GameState>>, channel: &Arc<RoomChannel>, log: bool) -> Result<(), Color> {
+    let now = Instant::now();
+    let mover = gs.turn();
+    if let Some(clock) = gs.clock_mut()
+        && let Err(flagged) = clock.press(mover, now)
+    {
+        if log {
+            println!("{:?} ran out of time in game {}", flagged, gs.game_code());
+        }
+        let outcome = gs.timeout_outcome(flagged);
+        finish(gs, outcome);
+        channel.send_state(gs);
+        return Err(flagged);
+    }
+    // the game records its own end by checkmate, stalemate, dead position, fivefold
+    // repetition or the 75-move rule; threefold repetition and the fifty-move rule are claimed
+    gs.apply_move(mv);
+    if game_over(gs)
+        && let Some(clock) = gs.clock_mut()
+    {
+        clock.stop(now);
+    }
+    channel.send_state(gs);
+    watch_flag(gs, game_state, channel, log);
+    Ok(())
+}
+
+/// Ends the game on time if the side to move has not moved when its clock runs out.
+/// The watch holds the room weakly and ends when the room is closed.
+fn watch_flag(gs: &GameState, game_state: &Arc<TokioMutex<GameState>>, channel: &Arc<RoomChannel>, log: bool) {
+    let Some(deadline) = gs.clock().and_then(Clock::deadline) else { return };
+    let closed = channel.on_close();
+    let (game_state, channel) = (Arc::downgrade(game_state), Arc::downgrade(channel));
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline.into()) => {}
+            _ = closed => return,
+        }
+        let (Some(game_state), Some(channel)) = (Weak::upgrade(&game_state), Weak::upgrade(&channel)) else { return };
+        let mut gs = game_state.lock().await;
+        // a later move or takeback may have moved the deadline; a later watch covers it
+        let Some(clock) = gs.clock_mut() else { return };
+        if clock.flagged().is_some() {
+            return;
+        }
+        let Some(flagged) = clock.check_flag(Instant::now()) else { return };
+        if log {
+            println!("{:?} ran out of time in game {}", flagged, gs.game_code());
+        }
+        let outcome = gs.timeout_outcome(flagged);
+        finish(&mut gs, outcome);
+        channel.send_state(&gs);
+    });
+}
+
+/// Lets the engine think in the background and plays its move, unless the position
+/// changed (takeback or rematch) while it was thinking. It gives up if the room is closed.
+fn spawn_engine_move(game_state: Arc<TokioMutex<GameState>>, channel: Arc<RoomChannel>, seat: EngineSeat, log: bool) {
+    let closed = channel.on_close();
+    let think = async move {
+        let snapshot = game_state.lock().await.clone();
+        if snapshot.turn() != seat.color || game_over(&snapshot) {
+            return;
+        }
+        let (hash, plies) = (snapshot.hash(), snapshot.history().len());
+        // on the clock, think for at most a thirtieth of the time left
+        let clock_budget = snapshot.clock().map(|clock| (clock.remaining(seat.color, Instant::now()) / 30).max(Duration::from_millis(10)));
+        let mv = match seat.player {
+            EnginePlayer::Builtin(mut limits) => {
+                if let Some(budget) = clock_budget {
+                    limits.time = Some(limits.time.map_or(budget, |time| time.min(budget)));
+                }
+                let searched = tokio::task::spawn_blocking(move || engine::search(&snapshot, &limits)).await;
+                // no move means the game is over
+                let Some(mv) = searched.ok().and_then(|result| result.best_move) else { return };
+                mv
+            }
+            EnginePlayer::External { engine, budget } => {
+                let budget = clock_budget.map_or(budget, |clock| budget.min(clock));
+                match engine.lock().await.best_move(&snapshot, budget).await {
+                    Ok(mv) => mv,
+                    Err(err) => {
+                        eprintln!("External engine failed in game {}: {}", snapshot.game_code(), err);
+                        channel.send(&ServerMessage::error(Some("engine_move"), err.code(), err.to_string()));
+                        return;
+                    }
+                }
+            }
+        };
+        let mut gs = game_state.lock().await;
+        if gs.hash() != hash || gs.history().len() != plies {
+            return;
+        }
+        if log {
+            println!("Computer ({:?}) played {}", seat.color, mv.to_san(&gs));
+        }
+        let _ = play_move(&mut gs, &mv, &game_state, &channel, log);
+    };
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = think => {}
+            _ = closed => {}
+        }
+    });
+}
+
+/// Whether the computer playing `color` agrees to a draw: only when it thinks it is
+/// behind by more than half a pawn
+fn engine_accepts_draw(gs: &GameState, color: Color) -> bool {
+    // the evaluation is from the side to move's point of view
+    let score = engine::evaluate(gs);
+    let score = if gs.turn() == color { score } else { -score };
+    score < -50
+}
+
+/// Seats a client in a room with the first free color, or as a spectator: starts
+/// forwarding the room's broadcasts after telling the client its role, and shows the
+/// current state to everyone in the room. None if the room does not exist, or has no free color for a player.
+async fn take_seat(rooms: &mut HashMap<usize, GameRoom>, game_id: usize, client_id: usize, spectate: bool, name: Option<&str>, ws_tx: &ClientSink) -> Option<(Option<Color>, tokio::task::JoinHandle<()>)> {
+    let room = rooms.get_mut(&game_id)?;
+    let color = if spectate { None } else { Some(room.free_color()?) };
+    let resume_token = color.map(|_| format!("{:032x}", rand::random::<u128>()));
+    if let Some(color) = color {
+        let name = name.map(str::to_string);
+        room.channel.set_player(color, PlayerRecord { name, computer: false, resume_token: resume_token.clone() });
+    }
+    room.clients.insert(client_id, RoomClient { color, resume_token: resume_token.clone(), disconnected: false });
+    if color.is_none() {
+        room.channel.spectators.fetch_add(1, Ordering::SeqCst);
+    }
+    let forwarder = spawn_forwarder(&ServerMessage::AssignColor { color: Role::from(color), resume_token }, room.channel.subscribe(), ws_tx.clone());
+    room.channel.send_state(&*room.game_state.lock().await);
+    Some((color, forwarder))
+}
+
+/// The room and old client id of the seat held for `token`. Fails if no seat has that
+/// token, or if its player is still connected.
+fn find_held_seat(rooms: &HashMap<usize, GameRoom>, token: &str) -> Result<(usize, usize), (&'static str, &'static str)> {
+    let (game_id, old_id, client) = rooms.iter()
+        .flat_map(|(&game_id, room)| room.clients.iter().map(move |(&client_id, client)| (game_id, client_id, client)))
+        .find(|(_, _, client)| client.resume_token.as_deref() == Some(token))
+        .ok_or(("unknown_token", "no seat is held for that token"))?;
+    if !client.disconnected {
+        return Err(("seat_in_use", "that seat's player is still connected"));
+    }
+    Ok((game_id, old_id))
+}
+
+/// Hands a held seat to a new connection: tells the client its role again, lets the room
+/// know the player is back, and shows everyone the current state
+async fn resume_seat(rooms: &mut HashMap<usize, GameRoom>, game_id: usize, old_id: usize, client_id: usize, ws_tx: &ClientSink) -> Option<(Color, tokio::task::JoinHandle<()>)> {
+    let room = rooms.get_mut(&game_id)?;
+    let mut client = room.clients.remove(&old_id)?;
+    client.disconnected = false;
+    let (color, resume_token) = (client.color?, client.resume_token.clone());
+    room.clients.insert(client_id, client);
+    let forwarder = spawn_forwarder(&ServerMessage::AssignColor { color: Role::from(color), resume_token }, room.channel.subscribe(), ws_tx.clone());
+    room.channel.send(&ServerMessage::PlayerReconnected { color: Role::from(color) });
+    room.channel.send_state(&*room.game_state.lock().await);
+    Some((color, forwarder))
+}
+
+/// Handles a dropped connection. A player's seat is held for `abandon_after` so they can
+/// resume it, and the room is told they are gone meanwhile; spectators just leave.
+async fn drop_client(game_rooms: &GameRooms, game_id: usize, client_id: usize, abandon_after: Duration, log: bool) {
+    let mut rooms = game_rooms.lock().await;
+    let held = rooms.get_mut(&game_id)
+        .and_then(|room| room.clients.get_mut(&client_id))
+        .filter(|client| client.color.is_some())
+        .and_then(|client| {
+            client.disconnected = true;
+            client.color
+        });
+    let Some(color) = held else {
+        leave_room(&mut rooms, game_id, client_id).await;
+        return;
+    };
+    let grace_ms = abandon_after.as_millis() as u64;
+    rooms[&game_id].channel.send(&ServerMessage::PlayerDisconnected { color: Role::from(color), grace_ms });
+    release_after(abandon_after, game_rooms.clone(), game_id, client_id, color, log);
+}
+
+/// Frees a held seat once `grace` has passed, unless it has been resumed. A player who
+/// does not come back has left the game.
+fn release_after(grace: Duration, game_rooms: GameRooms, game_id: usize, client_id: usize, color: Color, log: bool) {
+    tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        let mut rooms = game_rooms.lock().await;
+        // a resumed seat belongs to a new client id, so this one is gone
+        let still_held = rooms.get(&game_id)
+            .and_then(|room| room.clients.get(&client_id))
+            .is_some_and(|client| client.disconnected);
+        if still_held {
+            if log {
+                println!("{:?} did not come back to game {}; seat released", color, game_id);
+            }
+            leave_room(&mut rooms, game_id, client_id).await;
+        }
+    });
+}
+
+/// Removes a client from its room. A player leaving a game in progress abandons it, and
+/// the opponent wins if there is one. The room closes once no player is left in it.
+async fn leave_room(rooms: &mut HashMap<usize, GameRoom>, game_id: usize, client_id: usize) {
+    let Some(room) = rooms.get_mut(&game_id) else { return };
+    match room.clients.remove(&client_id) {
+        Some(RoomClient { color: None, .. }) => {
+            // let those still watching see the new spectator count
+            room.channel.spectators.fetch_sub(1, Ordering::SeqCst);
+            room.channel.send_state(&*room.game_state.lock().await);
+        }
+        Some(RoomClient { color: Some(color), .. }) => {
+            // a game nobody has moved in yet is simply open to a new player
+            let mut gs = room.game_state.lock().await;
+            if !game_over(&gs) && !gs.history().is_empty() && room.seat_taken(opposite_color(color)) {
+                finish(&mut gs, Outcome::win(opposite_color(color), Termination::Abandonment));
+                room.channel.send_state(&gs);
+            }
+        }
+        None => return,
+    }
+    if room.clients.values().all(|client| client.color.is_none()) {
+        close_room(rooms, game_id, CloseReason::PlayersLeft);
+    }
+}
+
+/// Removes a room, telling anyone still in it why, and stops its tasks and any external
+/// engine process
+fn close_room(rooms: &mut HashMap<usize, GameRoom>, game_id: usize, reason: CloseReason) {
+    let Some(room) = rooms.remove(&game_id) else { return };
+    room.channel.close(reason);
+    if let Some(EngineSeat { player: EnginePlayer::External { engine, .. }, .. }) = room.engine {
+        tokio::spawn(async move { engine.lock().await.shutdown().await });
+    }
+}
+
+/// Closes idle rooms every so often: those waiting for a player or finished, where nothing
+/// has happened for `idle_after`. A game in progress ends on the clock, by resignation or
+/// by abandonment instead.
+fn spawn_janitor(game_rooms: GameRooms, lifecycle: Lifecycle, log: bool) {
+    tokio::spawn(async move {
+        let mut sweeps = tokio::time::interval((lifecycle.idle_after / 4).max(Duration::from_secs(1)));
+        loop {
+            sweeps.tick().await;
+            let mut rooms = game_rooms.lock().await;
+            let mut idle = Vec::new();
+            for (&game_id, room) in rooms.iter() {
+                let status = room.status(&*room.game_state.lock().await);
+                if matches!(status, RoomStatus::Waiting | RoomStatus::Finished) && room.channel.idle_for() >= lifecycle.idle_after {
+                    idle.push(game_id);
+                }
+            }
+            for game_id in idle {
+                if log {
+                    println!("Closing idle game {}", rooms[&game_id].game_state.lock().await.game_code());
+                }
+                close_room(&mut rooms, game_id, CloseReason::Idle);
+            }
+        }
+    });
+}
+
+/// Undoes `requester`'s last move, and the reply to it if one was made. The clock
+/// passes to the requester with no bonus time.
+fn take_back_to(gs: &mut GameState, requester: Color) {
+    gs.take_back();
+    if gs.turn() != requester {
+        gs.take_back();
+    }
+    if let Some(clock) = gs.clock_mut() {
+        clock.hand_to(requester, Instant::now());
+    }
+}
+
+/// Sends `first` to one client's socket, then forwards a room's broadcasts to it, until
+/// the room is gone or the socket fails. Seating a client this way keeps socket writes
+/// out from under the rooms' lock.
+fn spawn_forwarder(first: &ServerMessage, mut rx: broadcast::Receiver<String>, ws_tx: ClientSink) -> tokio::task::JoinHandle<()> {
+    let first = first.to_text();
+    tokio::spawn(async move {
+        if ws_tx.lock().await.send(WsMessage::text(first)).await.is_err() {
+            return;
+        }
+        loop {
+            let msg = match rx.recv().await {
+                Ok(msg) => msg,
+                // a client that fell behind skips ahead; the next state catches it up
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if ws_tx.lock().await.send(WsMessage::text(msg)).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Games with white minus games with black among the player's latest saved games, for
+/// balancing their colors
+fn color_balance(store: &dyn GameStore, name: &str) -> i32 {
+    let filter = GameFilter { player: Some(name.to_string()), limit: RECENT_COLOR_GAMES, ..GameFilter::default() };
+    let Ok(recent) = store.list(&filter) else { return 0 };
+    recent.games.iter().map(|game| if game.white.name.as_deref() == Some(name) { 1 } else { -1 }).sum()
+}
+
+/// Checks that a name is the connecting player's, registering it to them if it is free.
+/// Players who cannot prove a name play unnamed, so nobody plays or is rated under
+/// someone else's.
+async fn claim_name(store: &Arc<dyn GameStore>, ws_tx: &ClientSink, name: String, token: Option<&str>) -> Option<String> {
+    // a player without a token is offered one, which registers the name if it is free
+    let (token, offered) = match token {
+        Some(token) => (token.to_string(), false),
+        None => (format!("{:032x}", rand::random::<u128>()), true),
+    };
+    let claimed = {
+        let (name, token) = (name.clone(), token.clone());
+        with_store(store, move |store| store.claim_name(&name, &token)).await
+    };
+    match claimed {
+        Ok(true) => {
+            if offered {
+                send_message(ws_tx, &ServerMessage::NameRegistered { name: name.clone(), token }).await;
+            }
+            Some(name)
+        }
+        Ok(false) => {
+            send_error(ws_tx, "connect", "name_taken", "that name belongs to another player; connect with its token to use it").await;
+            None
+        }
+        Err(err) => {
+            eprintln!("Could not check the name {}: {}", name, err);
+            send_error(ws_tx, "connect", "storage_error", "could not check the name; playing unnamed").await;
+            None
+        }
+    }
+}
+
+/// The rating a player searches with: their rating for the time control's category, or
+/// the default for unnamed players, untimed games and players without one
+fn search_rating(store: &dyn GameStore, name: Option<&str>, time_control: Option<TimeControl>) -> f64 {
+    let (Some(name), Some(category)) = (name, Category::of(time_control)) else { return rating::DEFAULT_RATING };
+    store.rating(name, category).ok().flatten().map_or(rating::DEFAULT_RATING, |rating| rating.rating)
+}
+
+/// A client's place in the matchmaking queue, with the rating it searches with and the
+/// balance of its recent colors, both looked up on the blocking pool
+async fn search_entry(store: &Arc<dyn GameStore>, client_id: usize, name: Option<String>, time_control: Option<TimeControl>) -> QueueEntry {
+    let (rating, color_balance) = with_store(store, move |store| {
+        let name = name.as_deref();
+        (search_rating(store, name, time_control), name.map_or(0, |name| color_balance(store, name)))
+    }).await;
+    QueueEntry { client_id, time_control, rating, color_balance, joined_at: Instant::now() }
+}
+
+/// Puts a client in the matchmaking queue, restarting its search if it was already in it,
+/// and tells it how it stands
+async fn start_search(lobby: &SharedLobby, entry: QueueEntry, searcher: Searcher) {
+    let ws_tx = searcher.ws_tx.clone();
+    let status = {
+        let mut lobby = lobby.lock().await;
+        let client_id = entry.client_id;
+        lobby.queue.join(entry);
+        lobby.searchers.insert(client_id, searcher);
+        lobby.queue.status(client_id, Instant::now())
+    };
+    send_reply(&ws_tx, status.map(ServerMessage::QueueStatus)).await;
+}
+
+/// Takes a client out of the matchmaking queue; false if it was not searching
+async fn stop_search(lobby: &SharedLobby, client_id: usize) -> bool {
+    let mut lobby = lobby.lock().await;
+    lobby.searchers.remove(&client_id);
+    lobby.queue.cancel(client_id)
+}
+
+/// Pairs searching players every `MATCHMAKING_TICK`, seating each pair in a new public
+/// room, and tells those still waiting how their search is going. A player whose
+/// opponent left before being seated goes back in the queue.
+fn spawn_matchmaker(lobby: SharedLobby, game_rooms: GameRooms, store: Arc<dyn GameStore>, log: bool) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(MATCHMAKING_TICK);
+        loop {
+            ticks.tick().await;
+            let mut lobby = lobby.lock().await;
+            let now = Instant::now();
+            let pairings = lobby.queue.pair(now);
+            if !pairings.is_empty() {
+                let mut rooms = game_rooms.lock().await;
+                for pairing in pairings {
+                    let searchers = [&pairing.white, &pairing.black].map(|entry| {
+                        lobby.searchers.remove(&entry.client_id).filter(|searcher| !searcher.seated.is_closed())
+                    });
+                    let (white, black) = match searchers {
+                        [Some(white), Some(black)] => (white, black),
+                        searchers => {
+                            for (entry, searcher) in [pairing.white, pairing.black].into_iter().zip(searchers) {
+                                if let Some(searcher) = searcher {
+                                    lobby.searchers.insert(entry.client_id, searcher);
+                                    lobby.queue.join(entry);
+                                }
+                            }
+                            continue;
+                        }
+                    };
+                    let game_id = GAME_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+                    rooms.insert(game_id, GameRoom::new(&store, false, None, pairing.white.time_control));
+                    // a fresh room seats white first
+                    for (entry, searcher) in [(&pairing.white, white), (&pairing.black, black)] {
+                        let Some((Some(color), forwarder)) = take_seat(&mut rooms, game_id, entry.client_id, false, searcher.name.as_deref(), &searcher.ws_tx).await else { continue };
+                        let _ = searcher.seated.send(Seated { game_id, color, forwarder });
+                    }
+                    if log {
+                        let (white, black) = (pairing.white.rating, pairing.black.rating);
+                        println!("Matched {:.0} and {:.0} in game {}", white, black, rooms[&game_id].game_state.lock().await.game_code());
+                    }
+                }
+            }
+            // statuses go out once the lobby is free again
+            let statuses: Vec<_> = lobby.searchers.iter()
+                .filter_map(|(&client_id, searcher)| Some((searcher.ws_tx.clone(), lobby.queue.status(client_id, now)?)))
+                .collect();
+            drop(lobby);
+            for (ws_tx, status) in statuses {
+                send_message(&ws_tx, &ServerMessage::QueueStatus(status)).await;
+            }
+        }
+    });
+}
+
 async fn handle_connection(
     ws: WebSocket,
     game_rooms: GameRooms,
+    query: JoinQuery,
     silent: bool,
     verbose: bool,
+    services: Services,
 ) {
+    let Services { store, uci_engine, lifecycle, lobby } = services;
     // split into sink & stream, then store sink for later per-client pushes
     let (ws_tx, mut ws_rx) = ws.split();
     let ws_tx = Arc::new(TokioMutex::new(ws_tx));
+    // tell the client which protocol version it is talking to
+    send_message(&ws_tx, &ServerMessage::welcome()).await;
+    // the name recorded with this player's games, if it is theirs
+    let my_name = match query.name.as_deref().map(|name| name.trim().chars().take(32).collect::<String>()) {
+        Some(name) if !name.is_empty() => claim_name(&store, &ws_tx, name, query.token.as_deref()).await,
+        _ => None,
+    };
 
     let client_id = CLIENT_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
-    // determine or create a game room and remember its ID
-    let my_game_id: usize = {
-        let mut rooms = game_rooms.lock().await;
-        // pick existing room or create new
-        let game_id = if let Some((&id, _)) = rooms.iter().find(|(_, r)| r.clients.len() < 2) {
-            id
-        } else {
-            let new_id = GAME_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
-            let new_state = Arc::new(TokioMutex::new(GameState::new()));
-            let (tx, _rx) = broadcast::channel::<String>(100);
-            rooms.insert(new_id, GameRoom { game_state: new_state.clone(), tx, clients: HashMap::new() });
-            new_id
-        };
-        // register this client
-        let room = rooms.get_mut(&game_id).unwrap();
-        room.clients.insert(client_id, ws_tx.clone());
-        // send initial state
-        let gs_arc = room.game_state.clone();
-        let init = {
-            let gs = gs_arc.lock().await;
-            if verbose || !silent { println!("Game code: {}", gs.game_code()); }
-            let mut val = serde_json::to_value(&*gs).unwrap();
-            val["in_check"] = serde_json::Value::Bool(gs.is_in_check());
-            val["is_checkmate"] = serde_json::Value::Bool(gs.is_checkmate());
-            val["is_stalemate"] = serde_json::Value::Bool(gs.is_stalemate());
-            val["is_threefold_repetition"] = serde_json::Value::Bool(gs.is_threefold_repetition());
-            val["is_fifty_move_draw"] = serde_json::Value::Bool(gs.is_fifty_move_draw());
-            val["is_insufficient_material"] = serde_json::Value::Bool(gs.is_insufficient_material());
-            serde_json::to_string(&val).unwrap()
-        };
-        // send initial state directly to this client so it renders immediately
-        {
-            let mut sink = ws_tx.lock().await;
-            let _ = sink.send(WsMessage::text(init.clone())).await;
-        }
-        // also broadcast to other subscribers (e.g., opponent)
-        let _ = room.tx.send(init);
-        game_id
-    };
-    // subscribe to this game room's broadcast channel
-    let mut rx = {
-        let rooms = game_rooms.lock().await;
-        rooms.get(&my_game_id).unwrap().tx.subscribe()
+    // seats found by the matchmaker while this client searches
+    let (seated_tx, mut seated_rx) = mpsc::unbounded_channel::<Seated>();
+    let searcher = || Searcher { ws_tx: ws_tx.clone(), name: my_name.clone(), seated: seated_tx.clone() };
+    let queue_entry = |time_control| search_entry(&store, client_id, my_name.clone(), time_control);
+    // take back a held seat, or join the game named in the URL, or else search for an
+    // untimed game
+    let mut rooms = game_rooms.lock().await;
+    let mut resume_refused = None;
+    let resumed = match &query.resume {
+        Some(token) => match find_held_seat(&rooms, token) {
+            Ok((game_id, old_id)) => {
+                let (color, forwarder) = resume_seat(&mut rooms, game_id, old_id, client_id, &ws_tx).await.unwrap();
+                if verbose || !silent { println!("{:?} resumed game {}", color, rooms[&game_id].game_state.lock().await.game_code()); }
+                Some((game_id, Some(color), Some(forwarder)))
+            }
+            Err(refused) => {
+                resume_refused = Some(refused);
+                None
+            }
+        },
+        None => None,
     };
-    // determine and send role assignment (only white for first, black for second)
-    let role_str = {
-        let rooms = game_rooms.lock().await;
-        let count = rooms.get(&my_game_id).unwrap().clients.len();
-        if count == 1 { "white" } else { "black" }
+    let joined = match resumed {
+        Some(seat) => Ok(seat),
+        None => match &query.code {
+            Some(code) => match find_room_by_code(&rooms, code).await {
+                Some(id) if query.spectate || rooms[&id].free_color().is_some() => {
+                    let (color, forwarder) = take_seat(&mut rooms, id, client_id, query.spectate, my_name.as_deref(), &ws_tx).await.unwrap();
+                    if verbose || !silent { println!("Game code: {}", rooms[&id].game_state.lock().await.game_code()); }
+                    Ok((id, color, Some(forwarder)))
+                }
+                Some(_) => Err(("game_full", "that game already has two players")),
+                None => Err(("unknown_game", "no game has that code")),
+            },
+            None => Ok((NO_ROOM, None, None)),
+        },
     };
-    let assign_msg = serde_json::to_string(&json!({
-        "instruction_type": "assign_color",
-        "color": role_str
-    })).expect("Failed to serialize assign_color");
-    {
-        let mut sink = ws_tx.lock().await;
-        let _ = sink.send(WsMessage::text(assign_msg)).await;
+    drop(rooms);
+    if let Some((code, message)) = resume_refused {
+        send_error(&ws_tx, "resume", code, message).await;
     }
-        
-    // clone sink for background game state broadcasts
-    let broadcast_tx = ws_tx.clone();
-    tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            let mut sink = broadcast_tx.lock().await;
-            let _ = sink.send(WsMessage::text(msg)).await;
+    let (mut my_game_id, color, mut forwarder) = match joined {
+        Ok(seat) => seat,
+        Err((code, message)) => {
+            send_error(&ws_tx, "join_game", code, message).await;
+            return;
         }
-    });
+    };
+    if my_game_id == NO_ROOM {
+        start_search(&lobby, queue_entry(None).await, searcher()).await;
+    }
     // state tracking for pending move
     let mut last_move_from: Option<u8> = None;
     // server-side role enforcement
-    let my_role = role_str.to_string();
-    // handle incoming messages
-    while let Some(Ok(msg)) = ws_rx.next().await {
-        if msg.is_text() {
-            if let Ok(text) = msg.to_str() {
-                if verbose { println!("Received instruction from client: {}", text); }
-                if let Ok(value) = serde_json::from_str::<serde_json::Value>(text) {
-                    // dispatch based on instruction type
-                    match value.get("instruction_type").and_then(|v| v.as_str()) {
-                        Some("get_legal_moves") => {
-                             if let Some(s) = value.get("square_clicked").and_then(|v| v.as_str()) {
-                                 if let Ok(idx) = s.parse::<u8>() {
-                                    // remember source for next move
-                                    last_move_from = Some(idx);
-                                    // computer legal move targets
-                                    let positions = {
-                                        let gs_arc = {
-                                            let rooms = game_rooms.lock().await;
-                                            rooms.get(&my_game_id).unwrap().game_state.clone()
-                                        };
-                                        let gs = gs_arc.lock().await;
-                                        legal_moves_for_piece_strict(&gs, idx)
-                                    };
-                                    let json = serde_json::to_string(&positions).unwrap();
-                                    let mut sink = ws_tx.lock().await;
-                                    let _ = sink.send(WsMessage::text(json)).await;
-                                }
-                            }
+    let mut my_role = Role::from(color);
+    let mut my_color = color;
+    // handle incoming messages, and seats found by the matchmaker
+    loop {
+        let msg = tokio::select! {
+            msg = ws_rx.next() => msg,
+            Some(seated) = seated_rx.recv() => {
+                let mut rooms = game_rooms.lock().await;
+                leave_room(&mut rooms, my_game_id, client_id).await;
+                drop(rooms);
+                if let Some(old) = forwarder.replace(seated.forwarder) {
+                    old.abort();
+                }
+                (my_game_id, my_color, my_role) = (seated.game_id, Some(seated.color), Role::from(seated.color));
+                last_move_from = None;
+                continue;
+            }
+        };
+        let Some(Ok(msg)) = msg else { break };
+        if msg.is_close() {
+            break;
+        }
+        if msg.is_ping() || msg.is_pong() {
+            continue;
+        }
+        let Ok(text) = msg.to_str() else {
+            send_message(&ws_tx, &ServerMessage::error(None, "malformed_message", "messages must be JSON text")).await;
+            continue;
+        };
+        if verbose { println!("Received instruction from client: {}", text); }
+        let instruction = match ClientMessage::parse(text) {
+            Ok(instruction) => instruction,
+            Err(err) => {
+                if verbose { eprintln!("Rejected message from client {}: {}", client_id, err); }
+                send_message(&ws_tx, &ServerMessage::error(err.request(), err.code(), err.to_string())).await;
+                continue;
+            }
+        };
+        // dispatch based on instruction type
+        match instruction {
+            ClientMessage::Hello { protocol_version } => {
+                if protocol_version != PROTOCOL_VERSION {
+                    let message = format!("server speaks protocol version {}, not {}", PROTOCOL_VERSION, protocol_version);
+                    send_error(&ws_tx, "hello", "unsupported_protocol_version", &message).await;
+                    break;
+                }
+                send_message(&ws_tx, &ServerMessage::welcome()).await;
+            }
+            ClientMessage::GetLegalMoves { square_clicked } => {
+                if square_clicked >= 64 {
+                    send_error(&ws_tx, "get_legal_moves", "bad_request", "square_clicked must be below 64").await;
+                    continue;
+                }
+                // remember source for next move
+                last_move_from = Some(square_clicked);
+                // computer legal move targets
+                let Some(gs_arc) = game_rooms.lock().await.get(&my_game_id).map(|room| room.game_state.clone()) else {
+                    send_error(&ws_tx, "get_legal_moves", "no_game", "your game has ended").await;
+                    continue;
+                };
+                let squares = legal_moves_for_piece_strict(&*gs_arc.lock().await, square_clicked);
+                send_message(&ws_tx, &ServerMessage::LegalMoves { squares }).await;
+            }
+            ClientMessage::RequestMove { uci, san, destination, promotion } => {
+                // clone game state Arc and broadcast sender, then release the rooms lock
+                let room = game_rooms.lock().await.get(&my_game_id).map(|room| (room.game_state.clone(), room.channel.clone(), room.engine.clone()));
+                // the room is gone if this is a spectator of a finished game against the computer
+                let Some((gs_arc, channel, engine_seat)) = room else {
+                    send_error(&ws_tx, "request_move", "no_game", "your game has ended").await;
+                    continue;
+                };
+                // server-side role enforcement: only the side on move may move, and only its own pieces
+                if my_color.is_none() {
+                    send_error(&ws_tx, "request_move", "not_a_player", "spectators cannot move").await;
+                    continue;
+                }
+                let reply = 'locked: {
+                    let mut gs = gs_arc.lock().await;
+                    if game_over(&gs) {
+                        break 'locked refusal("request_move", MoveError::GameOver.code(), &MoveError::GameOver.to_string());
+                    }
+                    if my_color != Some(gs.turn()) {
+                        eprintln!("Move attempted by {} out of turn", my_role);
+                        break 'locked refusal("request_move", MoveError::NotYourTurn.code(), &MoveError::NotYourTurn.to_string());
+                    }
+                    // the move may be given as UCI ("e7e8q"), SAN ("e8=Q+"), or as a destination
+                    // index for the piece last picked with get_legal_moves
+                    let parsed = if let Some(uci) = uci {
+                        Move::from_uci(&gs, &uci).map_err(|err| (err.code(), err.to_string()))
+                    } else if let Some(san) = san {
+                        Move::from_san(&gs, &san).map_err(|err| (err.code(), err.to_string()))
+                    } else {
+                        move_from_indices(&gs, destination, promotion, last_move_from)
+                    };
+                    let mv = match parsed {
+                        Ok(mv) => mv,
+                        Err((code, message)) => {
+                            eprintln!("Rejected move by {}: {}", my_role, message);
+                            break 'locked refusal("request_move", code, &message);
                         }
-                        Some("request_move") => {
-                            if let Some(dest_s) = value.get("destination").and_then(|v| v.as_str()) {
-                                if let Ok(dest) = dest_s.parse::<u8>() {
-                                    if let Some(from) = last_move_from {
-                                        // apply the move on game state
-                                        // clone game state Arc and lock
-                                        let gs_arc = {
-                                            let rooms = game_rooms.lock().await;
-                                            rooms.get(&my_game_id).unwrap().game_state.clone()
-                                        };
-                                        let mut gs = gs_arc.lock().await;
-                                        if (my_role == "white" && gs.piece_color_at(from as usize) == Some(Color::White))
-                                          || (my_role == "black" && gs.piece_color_at(from as usize) == Some(Color::Black))
-                                        {
-                                            // parse optional promotion piece
-                                            let promotion = value.get("promotion")
-                                                .and_then(|v| v.as_str())
-                                                .and_then(|s| match s {
-                                                    "queen" => Some(PieceType::Queen),
-                                                    "rook" => Some(PieceType::Rook),
-                                                    "bishop" => Some(PieceType::Bishop),
-                                                    "knight" => Some(PieceType::Knight),
-                                                    _ => None,
-                                                });
-                                            // logging context
-                                            let piece_color_enum = gs.piece_color_at(from as usize).unwrap();
-                                            let piece_type_enum = gs.piece_type_at(from as usize).unwrap();
-                                            let from_file = (b'a' + (from % 8)) as char;
-                                            let from_rank = (from / 8 + 1).to_string();
-                                            let from_coord = format!("{}{}", from_file.to_ascii_uppercase(), from_rank);
-                                            let to_file = (b'a' + (dest % 8)) as char;
-                                            let to_rank = (dest / 8 + 1).to_string();
-                                            let to_coord = format!("{}{}", to_file.to_ascii_uppercase(), to_rank);
-                                            // log events if in verbose mode or not silent
-                                            if verbose || !silent {
-                                                println!("{:?} {:?} moved from {} to {}", piece_color_enum, piece_type_enum, from_coord, to_coord);
-                                                // detect and log capture
-                                                let captured_color_opt = gs.piece_color_at(dest as usize);
-                                                let captured_type_opt = gs.piece_type_at(dest as usize);
-                                                if let (Some(captured_color), Some(captured_type)) = (captured_color_opt, captured_type_opt) {
-                                                    println!("{:?} {:?} captured by {:?} {:?}", captured_color, captured_type, piece_color_enum, piece_type_enum);
-                                                }
-                                                // check and checkmate
-                                                if gs.is_in_check() {
-                                                    println!("{:?} in Check", gs.turn());
-                                                }
-                                                if gs.is_checkmate() {
-                                                    println!("{:?} in Checkmate", gs.turn());
-                                                }
-                                            }
-                                            // apply the move
-                                            gs.move_piece(from, dest, promotion);
-                                            // broadcast updated full state
-                                            // serialize updated state with check/checkmate
-                                            let mut val = serde_json::to_value(&*gs).unwrap();
-                                            val["in_check"] = serde_json::Value::Bool(gs.is_in_check());
-                                            val["is_checkmate"] = serde_json::Value::Bool(gs.is_checkmate());
-                                            val["is_stalemate"] = serde_json::Value::Bool(gs.is_stalemate());
-                                            val["is_threefold_repetition"] = serde_json::Value::Bool(gs.is_threefold_repetition());
-                                            val["is_fifty_move_draw"] = serde_json::Value::Bool(gs.is_fifty_move_draw());
-                                            val["is_insufficient_material"] = serde_json::Value::Bool(gs.is_insufficient_material());
-                                            let full = serde_json::to_string(&val).unwrap();
-                                            let rooms = game_rooms.lock().await;
-                                            rooms.get(&my_game_id).unwrap().tx.send(full).unwrap();
-                                            last_move_from = None;
-                                        } else {
-                                            eprintln!("Illegal move by {} on {}", my_role, from);
-                                        }
-                                    } else {
-                                        eprintln!("No source square for move");
-                                    }
-                                }
-                            }
+                    };
+                    // log events if in verbose mode or not silent
+                    if verbose || !silent {
+                        println!("{:?} played {} ({})", gs.turn(), mv.to_san(&gs), mv.to_uci());
+                    }
+                    // apply the move and broadcast the updated state, unless time had run out
+                    if play_move(&mut gs, &mv, &gs_arc, &channel, verbose || !silent).is_err() {
+                        break 'locked refusal("request_move", "time_expired", "your time ran out before the move");
+                    }
+                    // check and the end of the game
+                    if verbose || !silent {
+                        if let Some(outcome) = gs.outcome() {
+                            println!("Game {} over: {:?} by {:?}", gs.game_code(), outcome.result, outcome.termination);
+                        } else if gs.is_in_check() {
+                            println!("{:?} in Check", gs.turn());
                         }
-                        Some("rematch") => {
-                            // reset game state for this room
-                            let mut rooms = game_rooms.lock().await;
-                            if let Some(room) = rooms.get_mut(&my_game_id) {
-                                // replace with new state
-                                *room.game_state.lock().await = GameState::new();
-                                // broadcast refreshed initial state
-                                let gs = room.game_state.lock().await;
-                                let mut val = serde_json::to_value(&*gs).unwrap();
-                                val["in_check"] = serde_json::Value::Bool(gs.is_in_check());
-                                val["is_checkmate"] = serde_json::Value::Bool(gs.is_checkmate());
-                                val["is_stalemate"] = serde_json::Value::Bool(gs.is_stalemate());
-                                val["is_threefold_repetition"] = serde_json::Value::Bool(gs.is_threefold_repetition());
-                                val["is_fifty_move_draw"] = serde_json::Value::Bool(gs.is_fifty_move_draw());
-                                val["is_insufficient_material"] = serde_json::Value::Bool(gs.is_insufficient_material());
-                                let full = serde_json::to_string(&val).unwrap();
-                                let _ = room.tx.send(full);
-                                // clear pending move
-                                last_move_from = None;
-                            }
+                    }
+                    None
+                };
+                if reply.is_some() {
+                    send_reply(&ws_tx, reply).await;
+                    continue;
+                }
+                // a move answers any pending takeback request, and declines a draw offered to
+                // the mover; an offer the mover made stands for the opponent to answer. The
+                // game state is released by now, keeping the lock order rooms -> state
+                if let Some(room) = game_rooms.lock().await.get_mut(&my_game_id) {
+                    room.takeback_offer = None;
+                    if room.draw_offer != my_color {
+                        room.draw_offer = None;
+                    }
+                }
+                last_move_from = None;
+                // in a game against the computer, it replies
+                if let Some(seat) = engine_seat {
+                    spawn_engine_move(gs_arc.clone(), channel, seat, verbose || !silent);
+                }
+            }
+            ClientMessage::RequestTakeback => {
+                let Some(color) = my_color else {
+                    send_error(&ws_tx, "request_takeback", "not_a_player", "spectators cannot take back moves").await;
+                    continue;
+                };
+                let reply = 'locked: {
+                    let mut rooms = game_rooms.lock().await;
+                    let Some(room) = rooms.get_mut(&my_game_id) else {
+                        break 'locked refusal("request_takeback", "no_game", "your game has ended");
+                    };
+                    let mut gs = room.game_state.lock().await;
+                    if game_over(&gs) {
+                        break 'locked refusal("request_takeback", "game_over", "the game has ended");
+                    }
+                    // there must be a move of ours on the board to take back
+                    let moves_played = gs.history().len();
+                    let has_moved = match color {
+                        Color::White => moves_played >= 1,
+                        Color::Black => moves_played >= 2,
+                    };
+                    if !has_moved {
+                        break 'locked refusal("request_takeback", "nothing_to_take_back", "you have not made a move yet");
+                    }
+                    // the computer always agrees
+                    if room.engine.is_some() {
+                        take_back_to(&mut gs, color);
+                        room.channel.send_state(&gs);
+                        watch_flag(&gs, &room.game_state, &room.channel, verbose || !silent);
+                        last_move_from = None;
+                        break 'locked None;
+                    }
+                    drop(gs);
+                    room.takeback_offer = Some(color);
+                    room.channel.send(&ServerMessage::TakebackOffer { color: my_role });
+                    None
+                };
+                send_reply(&ws_tx, reply).await;
+            }
+            instruction @ (ClientMessage::AcceptTakeback | ClientMessage::DeclineTakeback) => {
+                let accept = instruction == ClientMessage::AcceptTakeback;
+                let request = if accept { "accept_takeback" } else { "decline_takeback" };
+                let reply = 'locked: {
+                    let mut rooms = game_rooms.lock().await;
+                    let Some(room) = rooms.get_mut(&my_game_id) else {
+                        break 'locked refusal(request, "no_game", "your game has ended");
+                    };
+                    // only the opponent of the side that asked can answer
+                    let Some(requester) = room.takeback_offer.filter(|&c| my_color.is_some_and(|mine| mine != c)) else {
+                        break 'locked refusal(request, "no_takeback_offer", "there is no takeback request to answer");
+                    };
+                    room.takeback_offer = None;
+                    if !accept {
+                        room.channel.send(&ServerMessage::TakebackDeclined);
+                        break 'locked None;
+                    }
+                    let mut gs = room.game_state.lock().await;
+                    if game_over(&gs) {
+                        break 'locked refusal(request, "game_over", "the game has ended");
+                    }
+                    take_back_to(&mut gs, requester);
+                    if verbose || !silent {
+                        println!("{:?} took back a move", requester);
+                    }
+                    room.channel.send_state(&gs);
+                    watch_flag(&gs, &room.game_state, &room.channel, verbose || !silent);
+                    last_move_from = None;
+                    None
+                };
+                send_reply(&ws_tx, reply).await;
+            }
+            ClientMessage::Resign => {
+                let Some(color) = my_color else {
+                    send_error(&ws_tx, "resign", "not_a_player", "spectators cannot resign").await;
+                    continue;
+                };
+                let reply = 'locked: {
+                    let rooms = game_rooms.lock().await;
+                    let Some(room) = rooms.get(&my_game_id) else {
+                        break 'locked refusal("resign", "no_game", "your game has ended");
+                    };
+                    let mut gs = room.game_state.lock().await;
+                    if game_over(&gs) {
+                        break 'locked refusal("resign", "game_over", "the game has ended");
+                    }
+                    finish(&mut gs, Outcome::win(opposite_color(color), Termination::Resignation));
+                    if verbose || !silent {
+                        println!("{:?} resigned game {}", color, gs.game_code());
+                    }
+                    room.channel.send_state(&gs);
+                    None
+                };
+                send_reply(&ws_tx, reply).await;
+            }
+            ClientMessage::OfferDraw => {
+                let Some(color) = my_color else {
+                    send_error(&ws_tx, "offer_draw", "not_a_player", "spectators cannot offer draws").await;
+                    continue;
+                };
+                let reply = 'locked: {
+                    let mut rooms = game_rooms.lock().await;
+                    let Some(room) = rooms.get_mut(&my_game_id) else {
+                        break 'locked refusal("offer_draw", "no_game", "your game has ended");
+                    };
+                    let mut gs = room.game_state.lock().await;
+                    if game_over(&gs) {
+                        break 'locked refusal("offer_draw", "game_over", "the game has ended");
+                    }
+                    // offering a draw to a side that has offered one agrees to it; the computer
+                    // agrees when its evaluation says it is worse off
+                    let accepted = room.draw_offer == Some(opposite_color(color))
+                        || room.engine.as_ref().is_some_and(|seat| engine_accepts_draw(&gs, seat.color));
+                    if accepted {
+                        room.draw_offer = None;
+                        finish(&mut gs, Outcome::draw(Termination::Agreement));
+                        if verbose || !silent {
+                            println!("Game {} drawn by agreement", gs.game_code());
+                        }
+                        room.channel.send_state(&gs);
+                        None
+                    } else if room.engine.is_some() {
+                        Some(ServerMessage::DrawDeclined)
+                    } else {
+                        room.draw_offer = Some(color);
+                        room.channel.send(&ServerMessage::DrawOffer { color: my_role });
+                        None
+                    }
+                };
+                send_reply(&ws_tx, reply).await;
+            }
+            instruction @ (ClientMessage::AcceptDraw | ClientMessage::DeclineDraw) => {
+                let accept = instruction == ClientMessage::AcceptDraw;
+                let request = if accept { "accept_draw" } else { "decline_draw" };
+                let reply = 'locked: {
+                    let mut rooms = game_rooms.lock().await;
+                    let Some(room) = rooms.get_mut(&my_game_id) else {
+                        break 'locked refusal(request, "no_game", "your game has ended");
+                    };
+                    // only the opponent of the side that offered can answer
+                    if room.draw_offer.is_none_or(|offerer| my_color.is_none_or(|mine| mine == offerer)) {
+                        break 'locked refusal(request, "no_draw_offer", "there is no draw offer to answer");
+                    }
+                    room.draw_offer = None;
+                    if !accept {
+                        room.channel.send(&ServerMessage::DrawDeclined);
+                        break 'locked None;
+                    }
+                    let mut gs = room.game_state.lock().await;
+                    if game_over(&gs) {
+                        break 'locked refusal(request, "game_over", "the game has ended");
+                    }
+                    finish(&mut gs, Outcome::draw(Termination::Agreement));
+                    if verbose || !silent {
+                        println!("Game {} drawn by agreement", gs.game_code());
+                    }
+                    room.channel.send_state(&gs);
+                    None
+                };
+                send_reply(&ws_tx, reply).await;
+            }
+            ClientMessage::ClaimDraw => {
+                if my_color.is_none() {
+                    send_error(&ws_tx, "claim_draw", "not_a_player", "spectators cannot claim draws").await;
+                    continue;
+                }
+                let reply = 'locked: {
+                    let rooms = game_rooms.lock().await;
+                    let Some(room) = rooms.get(&my_game_id) else {
+                        break 'locked refusal("claim_draw", "no_game", "your game has ended");
+                    };
+                    let mut gs = room.game_state.lock().await;
+                    if game_over(&gs) {
+                        break 'locked refusal("claim_draw", "game_over", "the game has ended");
+                    }
+                    // the side to move claims threefold repetition or the fifty-move rule
+                    let claim = gs.claimable_draw().filter(|_| my_color == Some(gs.turn()));
+                    let Some(termination) = claim else {
+                        break 'locked refusal("claim_draw", "no_draw_to_claim", "there is no draw to claim on your move");
+                    };
+                    finish(&mut gs, Outcome::draw(termination));
+                    if verbose || !silent {
+                        println!("Draw claimed in game {} ({:?})", gs.game_code(), termination);
+                    }
+                    room.channel.send_state(&gs);
+                    None
+                };
+                send_reply(&ws_tx, reply).await;
+            }
+            ClientMessage::Rematch => {
+                if my_color.is_none() {
+                    send_error(&ws_tx, "rematch", "not_a_player", "spectators cannot start a rematch").await;
+                    continue;
+                }
+                // reset game state for this room, once its game is over: a player cannot
+                // walk away from a game, rated or not, by starting another
+                let mut rooms = game_rooms.lock().await;
+                if let Some(room) = rooms.get_mut(&my_game_id) {
+                    if !game_over(&*room.game_state.lock().await) {
+                        drop(rooms);
+                        send_error(&ws_tx, "rematch", "game_in_progress", "the game has not ended yet").await;
+                        continue;
+                    }
+                    // replace with new state
+                    *room.game_state.lock().await = new_game(room.time_control);
+                    room.channel.start_record();
+                    room.takeback_offer = None;
+                    room.draw_offer = None;
+                    // broadcast refreshed initial state
+                    let gs = room.game_state.lock().await;
+                    room.channel.send_state(&gs);
+                    // clear pending move
+                    last_move_from = None;
+                    // the computer opens if it has white
+                    if let Some(seat) = room.engine.clone() {
+                        spawn_engine_move(room.game_state.clone(), room.channel.clone(), seat, verbose || !silent);
+                    }
+                }
+            }
+            ClientMessage::PlayVsComputer { color, engine, depth, movetime_ms, time_control } => {
+                if time_control.is_some_and(|control| !control.is_valid()) {
+                    send_error(&ws_tx, "play_vs_computer", "invalid_time_control", "both sides need some time on the clock, up to a day each, an hour a move or 30 days per move").await;
+                    continue;
+                }
+                // the player's color: "white", "black", or random when missing
+                let human = match color {
+                    Some(ColorChoice::White) => Color::White,
+                    Some(ColorChoice::Black) => Color::Black,
+                    _ => if rand::random::<bool>() { Color::White } else { Color::Black },
+                };
+                // the built-in engine, or "engine": "external" for the configured UCI executable
+                let limits = engine_limits(depth, movetime_ms);
+                let player = match engine {
+                    Some(EngineChoice::External) => {
+                        let Some(path) = &uci_engine else {
+                            send_error(&ws_tx, "play_vs_computer", "engine_unavailable", "no external engine is configured").await;
+                            continue;
+                        };
+                        EnginePlayer::External {
+                            engine: Arc::new(TokioMutex::new(ExternalEngine::new(path))),
+                            budget: limits.time.unwrap_or(Duration::from_secs(1)),
                         }
-                        _ => {}
                     }
+                    _ => EnginePlayer::Builtin(limits),
+                };
+                let seat = EngineSeat {
+                    color: opposite_color(human),
+                    player,
+                };
+                // leave the current room, or the search, for a fresh private one shared with the engine
+                stop_search(&lobby, client_id).await;
+                let mut rooms = game_rooms.lock().await;
+                let new_id = GAME_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+                let room = GameRoom::new(&store, true, Some(seat.clone()), time_control);
+                let (game_state, channel) = (room.game_state.clone(), room.channel.clone());
+                rooms.insert(new_id, room);
+                let (color, new_forwarder) = take_seat(&mut rooms, new_id, client_id, false, my_name.as_deref(), &ws_tx).await.unwrap();
+                leave_room(&mut rooms, my_game_id, client_id).await;
+                drop(rooms);
+                if let Some(old) = forwarder.replace(new_forwarder) {
+                    old.abort();
+                }
+                (my_game_id, my_color, my_role) = (new_id, color, Role::from(color));
+                last_move_from = None;
+                if verbose || !silent {
+                    println!("New game against the computer, player has {:?}", human);
+                }
+                spawn_engine_move(game_state, channel, seat, verbose || !silent);
+            }
+            ClientMessage::JoinGame { code, spectate } => {
+                // asking for a particular game ends any search
+                stop_search(&lobby, client_id).await;
+                let joined = 'locked: {
+                    let mut rooms = game_rooms.lock().await;
+                    let Some(game_id) = find_room_by_code(&rooms, code.trim()).await else {
+                        break 'locked Err(("unknown_game", "no game has that code"));
+                    };
+                    if game_id == my_game_id {
+                        break 'locked Err(("already_joined", "you are already in that game"));
+                    }
+                    // players take a free color; `"spectate": true` watches instead
+                    let Some((color, new_forwarder)) = take_seat(&mut rooms, game_id, client_id, spectate, my_name.as_deref(), &ws_tx).await else {
+                        break 'locked Err(("game_full", "that game already has two players"));
+                    };
+                    leave_room(&mut rooms, my_game_id, client_id).await;
+                    Ok((game_id, color, new_forwarder))
+                };
+                let (game_id, color, new_forwarder) = match joined {
+                    Ok(joined) => joined,
+                    Err((code, message)) => {
+                        send_error(&ws_tx, "join_game", code, message).await;
+                        continue;
+                    }
+                };
+                if let Some(old) = forwarder.replace(new_forwarder) {
+                    old.abort();
+                }
+                (my_game_id, my_color, my_role) = (game_id, color, Role::from(color));
+                last_move_from = None;
+                if verbose || !silent {
+                    println!("Client {} joined game {} as {}", client_id, code.trim(), my_role);
                 }
             }
+            ClientMessage::Resume { token } => {
+                stop_search(&lobby, client_id).await;
+                let mut rooms = game_rooms.lock().await;
+                let (game_id, old_id) = match find_held_seat(&rooms, &token) {
+                    Ok(seat) => seat,
+                    Err((code, message)) => {
+                        drop(rooms);
+                        send_error(&ws_tx, "resume", code, message).await;
+                        continue;
+                    }
+                };
+                leave_room(&mut rooms, my_game_id, client_id).await;
+                let (color, new_forwarder) = resume_seat(&mut rooms, game_id, old_id, client_id, &ws_tx).await.unwrap();
+                drop(rooms);
+                if let Some(old) = forwarder.replace(new_forwarder) {
+                    old.abort();
+                }
+                (my_game_id, my_color, my_role) = (game_id, Some(color), Role::from(color));
+                last_move_from = None;
+                if verbose || !silent {
+                    println!("Client {} resumed {:?} in game {}", client_id, color, game_id);
+                }
+            }
+            ClientMessage::FindGame { time_control } => {
+                if time_control.is_some_and(|control| !control.is_valid()) {
+                    send_error(&ws_tx, "find_game", "invalid_time_control", "both sides need some time on the clock, up to a day each, an hour a move or 30 days per move").await;
+                    continue;
+                }
+                // a player finishes or resigns their game before looking for another
+                if my_color.is_some() && in_live_game(&game_rooms, my_game_id).await {
+                    send_error(&ws_tx, "find_game", "already_playing", "finish your current game first").await;
+                    continue;
+                }
+                // the client stays in its current room until it is paired
+                start_search(&lobby, queue_entry(time_control).await, searcher()).await;
+                if verbose {
+                    println!("Client {} is searching for a game ({:?})", client_id, time_control);
+                }
+            }
+            ClientMessage::CancelSearch => {
+                if stop_search(&lobby, client_id).await {
+                    send_message(&ws_tx, &ServerMessage::SearchCancelled).await;
+                } else {
+                    send_error(&ws_tx, "cancel_search", "not_searching", "you are not searching for a game").await;
+                }
+            }
+            ClientMessage::ListGames => {
+                let games = live_games(&*game_rooms.lock().await).await;
+                send_message(&ws_tx, &ServerMessage::GameList { games }).await;
+            }
+            ClientMessage::CreatePrivateGame { time_control } => {
+                if time_control.is_some_and(|control| !control.is_valid()) {
+                    send_error(&ws_tx, "create_private_game", "invalid_time_control", "both sides need some time on the clock, up to a day each, an hour a move or 30 days per move").await;
+                    continue;
+                }
+                // a fresh room that only players with its code can join
+                stop_search(&lobby, client_id).await;
+                let mut rooms = game_rooms.lock().await;
+                let new_id = GAME_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+                rooms.insert(new_id, GameRoom::new(&store, true, None, time_control));
+                let (color, new_forwarder) = take_seat(&mut rooms, new_id, client_id, false, my_name.as_deref(), &ws_tx).await.unwrap();
+                leave_room(&mut rooms, my_game_id, client_id).await;
+                if verbose || !silent {
+                    println!("Private game created: {}", rooms[&new_id].game_state.lock().await.game_code());
+                }
+                drop(rooms);
+                if let Some(old) = forwarder.replace(new_forwarder) {
+                    old.abort();
+                }
+                (my_game_id, my_color, my_role) = (new_id, color, Role::from(color));
+                last_move_from = None;
+            }
         }
     }
-    // unregister client on disconnect
-    {
+    // leave the queue, taking any seat found meanwhile, then unregister the client,
+    // holding a player's seat for a while
+    stop_search(&lobby, client_id).await;
+    while let Ok(seated) = seated_rx.try_recv() {
         let mut rooms = game_rooms.lock().await;
-        if let Some(room) = rooms.get_mut(&my_game_id) {
-            room.clients.remove(&client_id);
-        }
+        leave_room(&mut rooms, my_game_id, client_id).await;
+        drop(rooms);
+        seated.forwarder.abort();
+        my_game_id = seated.game_id;
+    }
+    if let Some(forwarder) = forwarder {
+        forwarder.abort();
     }
+    drop_client(&game_rooms, my_game_id, client_id, lifecycle.abandon_after, verbose || !silent).await;
 }
 
 // 
\ No newline at end of file
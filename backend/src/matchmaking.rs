@@ -0,0 +1,173 @@
+//! The matchmaking queue. Players searching for a game are paired with the nearest-rated
+//! opponent who wants the same time control, within a rating window that widens the longer
+//! they wait.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::clock::TimeControl;
+
+/// How far apart two ratings may be when a player starts searching
+pub const BASE_WINDOW: f64 = 100.0;
+/// How much the window grows for every second of waiting
+pub const WINDOW_GROWTH_PER_SECOND: f64 = 20.0;
+/// The widest the window gets
+pub const MAX_WINDOW: f64 = 1000.0;
+/// Waits remembered, across time controls, for estimating the next ones
+const RECENT_WAITS: usize = 50;
+
+/// A player searching for a game
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueEntry {
+    pub client_id: usize,
+    /// None for untimed games
+    pub time_control: Option<TimeControl>,
+    pub rating: f64,
+    /// games played with white minus games played with black, over the player's recent games
+    pub color_balance: i32,
+    pub joined_at: Instant,
+}
+
+impl QueueEntry {
+    /// How far from this player's rating an opponent may be, having waited until `now`
+    pub fn window(&self, now: Instant) -> f64 {
+        let waited = now.saturating_duration_since(self.joined_at).as_secs_f64();
+        (BASE_WINDOW + WINDOW_GROWTH_PER_SECOND * waited).min(MAX_WINDOW)
+    }
+
+    /// Whether the two players would accept each other as opponents at `now`
+    fn accepts(&self, other: &QueueEntry, now: Instant) -> bool {
+        let gap = (self.rating - other.rating).abs();
+        self.time_control == other.time_control && gap <= self.window(now) && gap <= other.window(now)
+    }
+}
+
+/// Two players paired for a game, with their colors
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pairing {
+    pub white: QueueEntry,
+    pub black: QueueEntry,
+}
+
+impl Pairing {
+    /// Gives white to the player who has had it less often lately; a coin decides a tie
+    fn new(first: QueueEntry, second: QueueEntry) -> Self {
+        let first_white = match first.color_balance.cmp(&second.color_balance) {
+            std::cmp::Ordering::Less => true,
+            std::cmp::Ordering::Greater => false,
+            std::cmp::Ordering::Equal => rand::random(),
+        };
+        if first_white {
+            Pairing { white: first, black: second }
+        } else {
+            Pairing { white: second, black: first }
+        }
+    }
+}
+
+/// A searching player's view of the queue, pushed to them while they wait
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueueStatus {
+    pub time_control: Option<TimeControl>,
+    /// players searching for the same time control, this one included
+    pub searching: usize,
+    pub waited_ms: u64,
+    /// the ratings an opponent may have for now
+    pub rating_min: i32,
+    pub rating_max: i32,
+    /// how much longer the search is likely to take, from recent waits for the same time
+    /// control; None until there have been some
+    pub estimated_wait_ms: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct MatchQueue {
+    // oldest first
+    entries: Vec<QueueEntry>,
+    // how long recently paired players waited, newest last
+    recent_waits: VecDeque<(Option<TimeControl>, Duration)>,
+}
+
+impl MatchQueue {
+    pub fn new() -> Self {
+        MatchQueue::default()
+    }
+
+    /// Adds a player to the queue, or restarts their search if they were in it
+    pub fn join(&mut self, entry: QueueEntry) {
+        self.cancel(entry.client_id);
+        self.entries.push(entry);
+    }
+
+    /// Takes a player out of the queue; false if they were not searching
+    pub fn cancel(&mut self, client_id: usize) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.client_id != client_id);
+        self.entries.len() != before
+    }
+
+    pub fn contains(&self, client_id: usize) -> bool {
+        self.entries.iter().any(|entry| entry.client_id == client_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Pairs whoever can be paired at `now` and takes them out of the queue. The players
+    /// who have waited longest choose first, each taking the nearest-rated opponent that
+    /// both windows allow.
+    pub fn pair(&mut self, now: Instant) -> Vec<Pairing> {
+        let mut pairings = Vec::new();
+        let mut i = 0;
+        while i < self.entries.len() {
+            let player = &self.entries[i];
+            let opponent = self.entries.iter().enumerate()
+                .filter(|&(j, other)| j != i && player.accepts(other, now))
+                .min_by(|(_, a), (_, b)| (a.rating - player.rating).abs().total_cmp(&(b.rating - player.rating).abs()))
+                .map(|(j, _)| j);
+            let Some(j) = opponent else {
+                i += 1;
+                continue;
+            };
+            // the opponent always comes later, as earlier players found nobody
+            let second = self.entries.remove(j);
+            let first = self.entries.remove(i);
+            for entry in [&first, &second] {
+                self.recent_waits.push_back((entry.time_control, now.saturating_duration_since(entry.joined_at)));
+            }
+            while self.recent_waits.len() > RECENT_WAITS {
+                self.recent_waits.pop_front();
+            }
+            pairings.push(Pairing::new(first, second));
+        }
+        pairings
+    }
+
+    /// Where a searching player stands at `now`, or None if they are not in the queue
+    pub fn status(&self, client_id: usize, now: Instant) -> Option<QueueStatus> {
+        let entry = self.entries.iter().find(|entry| entry.client_id == client_id)?;
+        let waited = now.saturating_duration_since(entry.joined_at);
+        let window = entry.window(now);
+        let waits: Vec<Duration> = self.recent_waits.iter()
+            .filter(|(control, _)| *control == entry.time_control)
+            .map(|&(_, wait)| wait)
+            .collect();
+        let estimated_wait = (!waits.is_empty())
+            .then(|| (waits.iter().sum::<Duration>() / waits.len() as u32).saturating_sub(waited));
+        Some(QueueStatus {
+            time_control: entry.time_control,
+            searching: self.entries.iter().filter(|other| other.time_control == entry.time_control).count(),
+            waited_ms: waited.as_millis() as u64,
+            rating_min: (entry.rating - window).round() as i32,
+            rating_max: (entry.rating + window).round() as i32,
+            estimated_wait_ms: estimated_wait.map(|wait| wait.as_millis() as u64),
+        })
+    }
+}
@@ -0,0 +1,415 @@
+use crate::game::{Color, FenError, GameResult, GameState, Move, SanError};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Tag values for the Seven Tag Roster. The Result tag is derived from the game itself.
+#[derive(Debug, Clone)]
+pub struct PgnHeaders {
+    pub event: String,
+    pub site: String,
+    pub date: String,
+    pub round: String,
+    pub white: String,
+    pub black: String,
+}
+
+impl Default for PgnHeaders {
+    fn default() -> Self {
+        PgnHeaders {
+            event: "Casual game".to_string(),
+            site: "Rusty Chess".to_string(),
+            date: "????.??.??".to_string(),
+            round: "-".to_string(),
+            white: "?".to_string(),
+            black: "?".to_string(),
+        }
+    }
+}
+
+/// Formats a timestamp as a PGN date ("YYYY.MM.DD", UTC)
+pub fn pgn_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) Gregorian date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// PGN result token for the game: "1-0", "0-1", "1/2-1/2" or "*" while in progress. A
+/// position that ends the game by itself counts even if no outcome was recorded.
+pub fn result_token(state: &GameState) -> &'static str {
+    match state.outcome().or_else(|| state.automatic_outcome()).map(|outcome| outcome.result) {
+        Some(GameResult::WhiteWins) => "1-0",
+        Some(GameResult::BlackWins) => "0-1",
+        Some(GameResult::Draw) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Writes the game as PGN: Seven Tag Roster, the game code, SetUp/FEN for non-standard
+/// starts, then SAN movetext wrapped at 80 columns and terminated by the result.
+pub fn write_pgn(state: &GameState, headers: &PgnHeaders) -> String {
+    let result = result_token(state);
+    let mut out = String::new();
+    let tags = [
+        ("Event", headers.event.as_str()),
+        ("Site", headers.site.as_str()),
+        ("Date", headers.date.as_str()),
+        ("Round", headers.round.as_str()),
+        ("White", headers.white.as_str()),
+        ("Black", headers.black.as_str()),
+        ("Result", result),
+        ("GameCode", state.game_code()),
+    ];
+    for (name, value) in tags {
+        out.push_str(&format!("[{} \"{}\"]\n", name, escape_tag(value)));
+    }
+    if let Some(fen) = state.start_fen() {
+        out.push_str("[SetUp \"1\"]\n");
+        out.push_str(&format!("[FEN \"{}\"]\n", escape_tag(fen)));
+    }
+    out.push('\n');
+
+    // replay from the start to get each move's SAN in its own position
+    let mut replay = state.initial_position();
+    let mut tokens = Vec::new();
+    for (ply, mv) in state.history().iter().enumerate() {
+        let number = replay.fullmove_clock();
+        if replay.turn() == Color::White {
+            tokens.push(format!("{}.", number));
+        } else if ply == 0 {
+            tokens.push(format!("{}...", number));
+        }
+        tokens.push(mv.to_san(&replay));
+        replay.replay_move(mv);
+    }
+    tokens.push(result.to_string());
+
+    let mut line_len = 0;
+    for token in tokens {
+        if line_len > 0 && line_len + 1 + token.len() > 80 {
+            out.push('\n');
+            line_len = 0;
+        } else if line_len > 0 {
+            out.push(' ');
+            line_len += 1;
+        }
+        line_len += token.len();
+        out.push_str(&token);
+    }
+    out.push('\n');
+    out
+}
+
+// ----------- Import -----------
+
+/// A game read from PGN: its tags in file order and the position after replaying the mainline
+#[derive(Debug, Clone)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub state: GameState,
+    /// the result token that terminated the movetext, if there was one
+    pub result: Option<String>,
+}
+
+impl PgnGame {
+    /// Value of the first tag with the given name
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Error from `read_pgn`, locating the problem by game (1-based) and, for moves, ply (1-based)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnError {
+    pub game: usize,
+    pub ply: Option<usize>,
+    pub kind: PgnErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnErrorKind {
+    /// the text could not be tokenised (unterminated comment, bad tag, stray ')', ...)
+    Syntax(String),
+    /// the FEN tag could not be parsed
+    Fen(FenError),
+    /// a movetext token is not valid SAN
+    MalformedMove(String),
+    /// a SAN move that no legal move matches
+    IllegalMove(String),
+    /// a SAN move that several legal moves match
+    AmbiguousMove(String),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "game {}", self.game)?;
+        if let Some(ply) = self.ply {
+            write!(f, ", ply {}", ply)?;
+        }
+        match &self.kind {
+            PgnErrorKind::Syntax(msg) => write!(f, ": {}", msg),
+            PgnErrorKind::Fen(err) => write!(f, ": bad FEN tag: {}", err),
+            PgnErrorKind::MalformedMove(san) => write!(f, ": malformed move '{}'", san),
+            PgnErrorKind::IllegalMove(san) => write!(f, ": illegal move '{}'", san),
+            PgnErrorKind::AmbiguousMove(san) => write!(f, ": ambiguous move '{}'", san),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+enum Token {
+    Tag(String, String),
+    Move(String),
+    Result(String),
+    VariationStart,
+    VariationEnd,
+}
+
+/// Splits PGN text into tags, SAN moves, results and variation brackets,
+/// dropping comments, NAGs, move numbers and escape lines
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    at_line_start: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(text: &'a str) -> Self {
+        Lexer { chars: text.chars().peekable(), line: 1, at_line_start: true }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        self.at_line_start = c == Some('\n');
+        if self.at_line_start {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, String> {
+        loop {
+            let line_start = self.at_line_start;
+            let Some(&c) = self.chars.peek() else { return Ok(None) };
+            match c {
+                c if c.is_whitespace() => {
+                    self.bump();
+                }
+                '%' if line_start => self.skip_line(),
+                ';' => self.skip_line(),
+                '{' => {
+                    let start = self.line;
+                    loop {
+                        match self.bump() {
+                            Some('}') => break,
+                            Some(_) => {}
+                            None => return Err(format!("line {}: unterminated comment", start)),
+                        }
+                    }
+                }
+                '[' => {
+                    self.bump();
+                    return self.tag().map(Some);
+                }
+                '(' => {
+                    self.bump();
+                    return Ok(Some(Token::VariationStart));
+                }
+                ')' => {
+                    self.bump();
+                    return Ok(Some(Token::VariationEnd));
+                }
+                '$' => {
+                    self.bump();
+                    while self.chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        self.bump();
+                    }
+                }
+                _ => {
+                    let mut symbol = String::new();
+                    while let Some(&c) = self.chars.peek() {
+                        if c.is_whitespace() || "{}()[];$".contains(c) {
+                            break;
+                        }
+                        symbol.push(c);
+                        self.bump();
+                    }
+                    if matches!(symbol.as_str(), "1-0" | "0-1" | "1/2-1/2" | "*") {
+                        return Ok(Some(Token::Result(symbol)));
+                    }
+                    // strip a move number such as "12." or "12..." (possibly glued to the move)
+                    let text = if symbol.starts_with(|c: char| c.is_ascii_digit()) && symbol.contains('.') {
+                        symbol.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start_matches('.')
+                    } else {
+                        symbol.as_str()
+                    };
+                    // bare dots and stand-alone annotation glyphs carry no move
+                    if text.chars().all(|c| matches!(c, '.' | '!' | '?')) {
+                        continue;
+                    }
+                    return Ok(Some(Token::Move(text.to_string())));
+                }
+            }
+        }
+    }
+
+    /// Reads the rest of a `[Name "value"]` tag pair after the opening bracket
+    fn tag(&mut self) -> Result<Token, String> {
+        let line = self.line;
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.bump();
+        }
+        let mut name = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            name.push(c);
+            self.bump();
+        }
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.bump();
+        }
+        if name.is_empty() || self.bump() != Some('"') {
+            return Err(format!("line {}: malformed tag", line));
+        }
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('\\') => match self.bump() {
+                    Some(c) => value.push(c),
+                    None => return Err(format!("line {}: unterminated tag value", line)),
+                },
+                Some('"') => break,
+                Some(c) => value.push(c),
+                None => return Err(format!("line {}: unterminated tag value", line)),
+            }
+        }
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.bump();
+        }
+        if self.bump() != Some(']') {
+            return Err(format!("line {}: tag is missing ']'", line));
+        }
+        Ok(Token::Tag(name, value))
+    }
+}
+
+/// Game being assembled while reading
+struct PendingGame {
+    tags: Vec<(String, String)>,
+    state: Option<GameState>,
+    plies: usize,
+    depth: usize,
+}
+
+impl PendingGame {
+    fn new() -> Self {
+        PendingGame { tags: Vec::new(), state: None, plies: 0, depth: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.state.is_none()
+    }
+
+    /// The position being replayed, set up from the FEN tag on first use
+    fn state(&mut self, game: usize) -> Result<&mut GameState, PgnError> {
+        if self.state.is_none() {
+            let fen = self.tags.iter().find(|(n, _)| n == "FEN").map(|(_, v)| v.as_str());
+            let state = match fen {
+                Some(fen) => GameState::from_fen(fen)
+                    .map_err(|err| PgnError { game, ply: None, kind: PgnErrorKind::Fen(err) })?,
+                None => GameState::new(),
+            };
+            self.state = Some(state);
+        }
+        Ok(self.state.as_mut().unwrap())
+    }
+
+    fn finish(mut self, game: usize, result: Option<String>) -> Result<PgnGame, PgnError> {
+        let state = self.state(game)?.clone();
+        Ok(PgnGame { tags: self.tags, state, result })
+    }
+}
+
+/// Reads every game in a PGN file, replaying mainline moves through the legal move
+/// generator. Comments, NAGs and variations are skipped.
+pub fn read_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut lexer = Lexer::new(text);
+    let mut games = Vec::new();
+    let mut current = PendingGame::new();
+    loop {
+        let game = games.len() + 1;
+        let token = lexer.next_token()
+            .map_err(|msg| PgnError { game, ply: None, kind: PgnErrorKind::Syntax(msg) })?;
+        match token {
+            None => break,
+            Some(Token::Tag(name, value)) => {
+                // a tag after movetext means the previous game had no result token
+                if current.state.is_some() {
+                    let done = std::mem::replace(&mut current, PendingGame::new());
+                    games.push(done.finish(game, None)?);
+                }
+                current.tags.push((name, value));
+            }
+            Some(Token::VariationStart) => current.depth += 1,
+            Some(Token::VariationEnd) => {
+                if current.depth == 0 {
+                    return Err(PgnError { game, ply: None, kind: PgnErrorKind::Syntax("unmatched ')'".to_string()) });
+                }
+                current.depth -= 1;
+            }
+            Some(Token::Move(_)) if current.depth > 0 => {}
+            Some(Token::Move(san)) => {
+                let ply = current.plies + 1;
+                let state = current.state(game)?;
+                let mv = Move::from_san(state, &san).map_err(|err| {
+                    let kind = match err {
+                        SanError::Syntax => PgnErrorKind::MalformedMove(san.clone()),
+                        SanError::Illegal => PgnErrorKind::IllegalMove(san.clone()),
+                        SanError::Ambiguous => PgnErrorKind::AmbiguousMove(san.clone()),
+                    };
+                    PgnError { game, ply: Some(ply), kind }
+                })?;
+                state.replay_move(&mv);
+                current.plies = ply;
+            }
+            Some(Token::Result(_)) if current.depth > 0 => {}
+            Some(Token::Result(result)) => {
+                let done = std::mem::replace(&mut current, PendingGame::new());
+                games.push(done.finish(game, Some(result))?);
+            }
+        }
+    }
+    if !current.is_empty() {
+        let game = games.len() + 1;
+        games.push(current.finish(game, None)?);
+    }
+    Ok(games)
+}
@@ -0,0 +1,452 @@
+//! Messages exchanged with clients over the `/ws` WebSocket. Every message is a JSON
+//! object whose `instruction_type` names its kind; the remaining fields depend on it.
+
+use std::fmt;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, TimeControl};
+use crate::game::{Color, GameState, Move, PieceType, Termination};
+use crate::matchmaking::QueueStatus;
+use crate::rating::Rating;
+
+/// Version of this message format. Clients may send `hello` with the version they were
+/// written against, and are told in `welcome` which version the server speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Messages sent by clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "instruction_type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Checks that the server speaks the client's protocol version
+    Hello { protocol_version: u32 },
+    /// Legal destinations of the piece on a square, which also becomes the source of the
+    /// next `request_move` given by `destination`
+    GetLegalMoves { square_clicked: u8 },
+    /// A move as UCI (`e7e8q`), SAN (`e8=Q+`), or a destination for the selected piece
+    RequestMove {
+        uci: Option<String>,
+        san: Option<String>,
+        destination: Option<u8>,
+        promotion: Option<Promotion>,
+    },
+    RequestTakeback,
+    AcceptTakeback,
+    DeclineTakeback,
+    Resign,
+    /// Offers the opponent a draw; offering back to a side that has offered agrees to it
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    /// Claims a draw by threefold repetition or the fifty-move rule, on the claimant's move
+    ClaimDraw,
+    Rematch,
+    /// Starts a new game against the built-in engine, or the configured UCI engine
+    PlayVsComputer {
+        color: Option<ColorChoice>,
+        engine: Option<EngineChoice>,
+        depth: Option<u32>,
+        movetime_ms: Option<u64>,
+        time_control: Option<TimeControl>,
+    },
+    JoinGame {
+        code: String,
+        #[serde(default)]
+        spectate: bool,
+    },
+    CreatePrivateGame { time_control: Option<TimeControl> },
+    /// Looks for an opponent through the matchmaking queue; no time control for untimed games
+    FindGame { time_control: Option<TimeControl> },
+    CancelSearch,
+    ListGames,
+    /// Takes back a seat held after a dropped connection, with the token from `assign_color`
+    Resume { token: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Promotion {
+    Queen,
+    Rook,
+    Bishop,
+    Knight,
+}
+
+impl Promotion {
+    pub fn piece_type(self) -> PieceType {
+        match self {
+            Promotion::Queen => PieceType::Queen,
+            Promotion::Rook => PieceType::Rook,
+            Promotion::Bishop => PieceType::Bishop,
+            Promotion::Knight => PieceType::Knight,
+        }
+    }
+
+    pub fn from_piece_type(piece_type: PieceType) -> Option<Self> {
+        match piece_type {
+            PieceType::Queen => Some(Promotion::Queen),
+            PieceType::Rook => Some(Promotion::Rook),
+            PieceType::Bishop => Some(Promotion::Bishop),
+            PieceType::Knight => Some(Promotion::Knight),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    White,
+    Black,
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineChoice {
+    Builtin,
+    External,
+}
+
+/// Messages sent by the server
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "instruction_type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// First message on every connection
+    Welcome { protocol_version: u32, server_version: String },
+    /// The name the client connected with was free and is now theirs. Connecting with
+    /// `token` as well proves it from then on; without it, the name is refused.
+    NameRegistered { name: String, token: String },
+    /// The client's role in its room. Players also get a token to `resume` their seat with
+    /// if the connection drops.
+    AssignColor { color: Role, resume_token: Option<String> },
+    State(Box<StateUpdate>),
+    LegalMoves { squares: Vec<u8> },
+    TakebackOffer { color: Role },
+    TakebackDeclined,
+    DrawOffer { color: Role },
+    DrawDeclined,
+    /// A player's connection dropped; their seat is held for `grace_ms`
+    PlayerDisconnected { color: Role, grace_ms: u64 },
+    PlayerReconnected { color: Role },
+    /// The room was closed; requests about its game fail from now on
+    RoomClosed { reason: CloseReason },
+    GameList { games: Vec<GameSummary> },
+    /// Sent while the client is searching for a game, until an `assign_color` seats it
+    QueueStatus(QueueStatus),
+    SearchCancelled,
+    /// A request was rejected. `request` is the instruction that failed, if it was
+    /// recognised, and `code` a stable snake_case reason.
+    Error {
+        request: Option<String>,
+        code: String,
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    pub fn welcome() -> Self {
+        ServerMessage::Welcome {
+            protocol_version: PROTOCOL_VERSION,
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    pub fn error(request: Option<&str>, code: &str, message: impl Into<String>) -> Self {
+        ServerMessage::Error {
+            request: request.map(str::to_string),
+            code: code.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// The JSON text sent over the socket
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).expect("server messages always serialize")
+    }
+}
+
+/// What a client does in its room
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    White,
+    Black,
+    Observer,
+}
+
+impl From<Option<Color>> for Role {
+    fn from(color: Option<Color>) -> Self {
+        match color {
+            Some(Color::White) => Role::White,
+            Some(Color::Black) => Role::Black,
+            None => Role::Observer,
+        }
+    }
+}
+
+impl From<Color> for Role {
+    fn from(color: Color) -> Self {
+        Role::from(Some(color))
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::White => "white",
+            Role::Black => "black",
+            Role::Observer => "observer",
+        })
+    }
+}
+
+/// One move of the game, described for display
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveRecord {
+    pub from: u8,
+    pub to: u8,
+    pub promotion: Option<Promotion>,
+    pub capture: bool,
+    pub castle: bool,
+    pub en_passant: bool,
+    pub san: String,
+    pub uci: String,
+}
+
+impl MoveRecord {
+    /// Describes `mv`, which must be legal in `state`, the position it is played from
+    pub fn new(state: &GameState, mv: &Move) -> Self {
+        MoveRecord {
+            from: mv.from(),
+            to: mv.to(),
+            promotion: mv.promotion().and_then(Promotion::from_piece_type),
+            capture: mv.is_en_passant() || state.piece_at(mv.to()).is_some(),
+            castle: mv.is_castle(),
+            en_passant: mv.is_en_passant(),
+            san: mv.to_san(state),
+            uci: mv.to_uci(),
+        }
+    }
+
+    /// Every move of the game, replayed from its starting position
+    pub fn game_moves(state: &GameState) -> Vec<MoveRecord> {
+        let mut moves = MoveList::default();
+        moves.update(state);
+        moves.records
+    }
+}
+
+/// A game's moves described for display, kept in step with the game as moves are played
+/// and taken back, so that each move is described once rather than on every broadcast
+#[derive(Debug, Clone, Default)]
+pub struct MoveList {
+    played: Vec<Move>,
+    records: Vec<MoveRecord>,
+}
+
+impl MoveList {
+    /// Brings the list up to date with `state`, describing only the moves it has not seen
+    pub fn update(&mut self, state: &GameState) -> &[MoveRecord] {
+        let history = state.history();
+        let kept = self.played.iter().zip(history).take_while(|(seen, mv)| seen == mv).count();
+        self.played.truncate(kept);
+        self.records.truncate(kept);
+        if kept < history.len() {
+            // step back to the position before the first new move, then describe forwards
+            let mut replay = state.clone();
+            for _ in kept..history.len() {
+                replay.take_back();
+            }
+            for mv in &history[kept..] {
+                self.records.push(MoveRecord::new(&replay, mv));
+                replay.replay_move(mv);
+                self.played.push(*mv);
+            }
+        }
+        &self.records
+    }
+}
+
+/// The board and game flags, broadcast to the room after every change
+#[derive(Debug, Clone, Serialize)]
+pub struct StateUpdate {
+    #[serde(flatten)]
+    pub game: GameState,
+    /// Counts the room's state broadcasts, so clients can spot missed or reordered updates
+    pub seq: u64,
+    pub last_move: Option<MoveRecord>,
+    pub moves: Vec<MoveRecord>,
+    pub spectators: usize,
+    pub clock: Option<ClockUpdate>,
+    pub in_check: bool,
+    /// a draw the side to move may claim with `claim_draw`; how the game ended is in the
+    /// game's `outcome`
+    pub claimable_draw: Option<Termination>,
+    /// the players' ratings for the game's time control; None for the computer, unnamed
+    /// players and untimed games
+    pub white_rating: Option<RatingView>,
+    pub black_rating: Option<RatingView>,
+}
+
+impl StateUpdate {
+    /// The update for `gs`, whose moves are described in `moves`
+    pub fn new(gs: &GameState, moves: Vec<MoveRecord>, seq: u64, spectators: usize) -> Self {
+        StateUpdate {
+            game: gs.clone(),
+            seq,
+            last_move: moves.last().cloned(),
+            moves,
+            spectators,
+            clock: gs.clock().map(|clock| ClockUpdate::new(clock, Instant::now())),
+            in_check: gs.is_in_check(),
+            claimable_draw: gs.claimable_draw().filter(|_| gs.outcome().is_none()),
+            white_rating: None,
+            black_rating: None,
+        }
+    }
+}
+
+/// A player's rating as shown with a game, rounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RatingView {
+    pub rating: i32,
+    pub deviation: i32,
+    /// too few games for the rating to be reliable
+    pub provisional: bool,
+}
+
+impl From<&Rating> for RatingView {
+    fn from(rating: &Rating) -> Self {
+        RatingView { rating: rating.rating.round() as i32, deviation: rating.deviation.round() as i32, provisional: rating.provisional() }
+    }
+}
+
+/// Time left on both sides of a timed game, as of the broadcast
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockUpdate {
+    pub time_control: TimeControl,
+    pub white_ms: u64,
+    pub black_ms: u64,
+    /// the side whose time is running; clients count it down until the next update
+    pub running: Option<Color>,
+    pub flagged: Option<Color>,
+}
+
+impl ClockUpdate {
+    pub fn new(clock: &Clock, now: Instant) -> Self {
+        ClockUpdate {
+            time_control: clock.control(),
+            white_ms: clock.remaining(Color::White, now).as_millis() as u64,
+            black_ms: clock.remaining(Color::Black, now).as_millis() as u64,
+            running: clock.running(),
+            flagged: clock.flagged(),
+        }
+    }
+}
+
+/// Who plays one side of a listed game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeatKind {
+    Player,
+    Computer,
+    Open,
+}
+
+/// Where a room is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomStatus {
+    /// a seat is open for a player
+    Waiting,
+    /// both seats are taken and the game goes on
+    Playing,
+    /// the game has an outcome; the players may start a rematch
+    Finished,
+    /// a player's connection dropped mid-game and their seat is held for them
+    Abandoned,
+}
+
+/// Why a room was closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// no game was in progress and nothing happened for a while
+    Idle,
+    /// every player has left
+    PlayersLeft,
+}
+
+/// One entry of `game_list`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameSummary {
+    pub game_code: String,
+    pub status: RoomStatus,
+    pub white: SeatKind,
+    pub black: SeatKind,
+    pub spectators: usize,
+    pub moves: usize,
+    pub turn: Color,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// not a JSON object with a string `instruction_type`
+    Malformed(String),
+    /// an `instruction_type` the server does not know
+    UnknownInstruction(String),
+    /// a known instruction with missing or invalid fields
+    BadRequest { instruction: String, message: String },
+}
+
+impl ProtocolError {
+    /// Machine-readable code for error replies
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProtocolError::Malformed(_) => "malformed_message",
+            ProtocolError::UnknownInstruction(_) => "unknown_instruction",
+            ProtocolError::BadRequest { .. } => "bad_request",
+        }
+    }
+
+    /// The instruction the error refers to, when there is one
+    pub fn request(&self) -> Option<&str> {
+        match self {
+            ProtocolError::Malformed(_) => None,
+            ProtocolError::UnknownInstruction(instruction) | ProtocolError::BadRequest { instruction, .. } => Some(instruction),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Malformed(reason) => write!(f, "malformed message: {}", reason),
+            ProtocolError::UnknownInstruction(instruction) => write!(f, "unknown instruction {}", instruction),
+            ProtocolError::BadRequest { message, .. } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl ClientMessage {
+    /// Parses one message from a client, telling apart bad JSON, unknown instructions and
+    /// bad fields so each can be answered with its own error code
+    pub fn parse(text: &str) -> Result<ClientMessage, ProtocolError> {
+        let value: serde_json::Value = serde_json::from_str(text).map_err(|err| ProtocolError::Malformed(err.to_string()))?;
+        let Some(instruction) = value.get("instruction_type").and_then(|v| v.as_str()).map(str::to_string) else {
+            return Err(ProtocolError::Malformed("missing instruction_type".to_string()));
+        };
+        serde_json::from_value(value).map_err(|err| {
+            // serde reports an unknown tag as an unknown variant named after it
+            if err.to_string().starts_with(&format!("unknown variant `{}`", instruction)) {
+                ProtocolError::UnknownInstruction(instruction)
+            } else {
+                ProtocolError::BadRequest { instruction, message: err.to_string() }
+            }
+        })
+    }
+}
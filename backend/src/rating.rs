@@ -0,0 +1,209 @@
+//! Glicko-2 player ratings. Every named player has one rating per category of time control,
+//! updated each time a rated game ends. A rating's deviation grows while its player is not
+//! playing, so a rating left alone becomes uncertain again.
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::TimeControl;
+use crate::game::{Color, GameResult};
+use crate::storage::{GameRecord, GameStore, StorageError};
+
+/// Rating of a player with no rated games
+pub const DEFAULT_RATING: f64 = 1500.0;
+/// Deviation of a player with no rated games, and the most any rating drifts back to
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+/// Ratings less certain than this are provisional
+pub const PROVISIONAL_DEVIATION: f64 = 110.0;
+/// The most certain a rating becomes, so that it can always move
+const MIN_DEVIATION: f64 = 45.0;
+/// Constrains how fast volatility changes
+const TAU: f64 = 0.5;
+/// Converts between the Glicko and Glicko-2 scales
+const SCALE: f64 = 173.7178;
+const CONVERGENCE: f64 = 0.000_001;
+/// A rating period: how long a player must be away for their deviation to grow by one
+/// period's volatility
+const PERIOD_MS: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+/// Kinds of time control, each with its own ratings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [Category::Bullet, Category::Blitz, Category::Rapid, Category::Classical, Category::Correspondence];
+
+    /// The category of a time control, by how long a 40-move game takes each side; None
+    /// for untimed games, which are not rated
+    pub fn of(time_control: Option<TimeControl>) -> Option<Category> {
+        let estimate_ms = match time_control? {
+            TimeControl::Fischer { base_ms, increment_ms: extra_ms } | TimeControl::Bronstein { base_ms, delay_ms: extra_ms } => extra_ms.saturating_mul(40).saturating_add(base_ms),
+            TimeControl::Correspondence { .. } => return Some(Category::Correspondence),
+        };
+        Some(match estimate_ms / 1000 {
+            0..180 => Category::Bullet,
+            180..480 => Category::Blitz,
+            480..1500 => Category::Rapid,
+            _ => Category::Classical,
+        })
+    }
+}
+
+/// A player's Glicko-2 rating in one category
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    /// rated games played in the category
+    pub games: u32,
+    /// when the last rated game ended, in milliseconds since the Unix epoch
+    pub last_played_at: Option<u64>,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating { rating: DEFAULT_RATING, deviation: DEFAULT_DEVIATION, volatility: DEFAULT_VOLATILITY, games: 0, last_played_at: None }
+    }
+}
+
+impl Rating {
+    /// Whether the rating is still too uncertain to be taken at face value
+    pub fn provisional(&self) -> bool {
+        self.deviation > PROVISIONAL_DEVIATION
+    }
+
+    /// The rating as of `now`, its deviation grown by the rating periods since the player
+    /// last played
+    pub fn decayed(&self, now: u64) -> Rating {
+        let Some(last_played_at) = self.last_played_at else { return *self };
+        if now <= last_played_at {
+            return *self;
+        }
+        let periods = (now - last_played_at) as f64 / PERIOD_MS;
+        let phi = self.deviation / SCALE;
+        let deviation = ((phi * phi + self.volatility * self.volatility * periods).sqrt() * SCALE).min(DEFAULT_DEVIATION);
+        Rating { deviation, ..*self }
+    }
+
+    /// The rating after a game against `opponent` ending at `now`, scoring 1 for a win,
+    /// 0.5 for a draw and 0 for a loss. Both ratings are taken as of `now`.
+    pub fn update(&self, opponent: &Rating, score: f64, now: u64) -> Rating {
+        let (player, opponent) = (self.decayed(now), opponent.decayed(now));
+        let mu = (player.rating - DEFAULT_RATING) / SCALE;
+        let phi = player.deviation / SCALE;
+        let mu_j = (opponent.rating - DEFAULT_RATING) / SCALE;
+        let phi_j = opponent.deviation / SCALE;
+
+        let g = 1.0 / (1.0 + 3.0 * phi_j * phi_j / (std::f64::consts::PI * std::f64::consts::PI)).sqrt();
+        let expected = 1.0 / (1.0 + (-g * (mu - mu_j)).exp());
+        let variance = 1.0 / (g * g * expected * (1.0 - expected));
+        let delta = variance * g * (score - expected);
+        let volatility = new_volatility(phi, player.volatility, variance, delta);
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / variance).sqrt();
+        let new_mu = mu + new_phi * new_phi * g * (score - expected);
+        Rating {
+            rating: new_mu * SCALE + DEFAULT_RATING,
+            deviation: (new_phi * SCALE).clamp(MIN_DEVIATION, DEFAULT_DEVIATION),
+            volatility,
+            games: player.games + 1,
+            last_played_at: Some(now),
+        }
+    }
+}
+
+/// Step 5 of Glicko-2: the new volatility, found with the Illinois algorithm
+fn new_volatility(phi: f64, sigma: f64, variance: f64, delta: f64) -> f64 {
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        let denominator = phi * phi + variance + ex;
+        ex * (delta * delta - phi * phi - variance - ex) / (2.0 * denominator * denominator) - (x - a) / (TAU * TAU)
+    };
+    let mut big_a = a;
+    let mut big_b = if delta * delta > phi * phi + variance {
+        (delta * delta - phi * phi - variance).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+    let (mut f_a, mut f_b) = (f(big_a), f(big_b));
+    while (big_b - big_a).abs() > CONVERGENCE {
+        let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+        let f_c = f(big_c);
+        if f_c * f_b <= 0.0 {
+            big_a = big_b;
+            f_a = f_b;
+        } else {
+            f_a /= 2.0;
+        }
+        big_b = big_c;
+        f_b = f_c;
+    }
+    (big_a / 2.0).exp()
+}
+
+/// How one player's rating moved in one rated game
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RatingChange {
+    pub player: String,
+    pub category: Category,
+    pub game_id: u64,
+    pub game_code: String,
+    pub color: Color,
+    pub before: Rating,
+    pub after: Rating,
+}
+
+/// Updates both players' ratings for a game that has just ended, if it is rated: a timed
+/// game between two differently named players, neither of them the computer. Returns the
+/// changes, white's first.
+pub fn rate_game(store: &dyn GameStore, game: &GameRecord, now: u64) -> Result<Option<[RatingChange; 2]>, StorageError> {
+    let (Some(category), Some(id), Some(outcome)) = (game.category(), game.id, game.outcome) else { return Ok(None) };
+    let (Some(white), Some(black)) = (rated_name(game, Color::White), rated_name(game, Color::Black)) else { return Ok(None) };
+    if white == black {
+        return Ok(None);
+    }
+    let white_score = match outcome.result {
+        GameResult::WhiteWins => 1.0,
+        GameResult::BlackWins => 0.0,
+        GameResult::Draw => 0.5,
+    };
+    // the history shows each rating as it stood when the game ended
+    let change = |player: &str, color, before: Rating, opponent: &Rating, score| RatingChange {
+        player: player.to_string(),
+        category,
+        game_id: id,
+        game_code: game.game_code.clone(),
+        color,
+        before: before.decayed(now),
+        after: before.update(opponent, score, now),
+    };
+    // read and written in one step, so games ending together for a player both count
+    let changes = store.update_ratings([white, black], category, &|[white_before, black_before]| {
+        let (white_before, black_before) = (white_before.unwrap_or_default(), black_before.unwrap_or_default());
+        [
+            change(white, Color::White, white_before, &black_before, white_score),
+            change(black, Color::Black, black_before, &white_before, 1.0 - white_score),
+        ]
+    })?;
+    Ok(Some(changes))
+}
+
+/// The name a side's rating is kept under, if it is played by a named person
+fn rated_name(game: &GameRecord, color: Color) -> Option<&str> {
+    let player = game.player(color);
+    player.name.as_deref().filter(|_| !player.computer)
+}
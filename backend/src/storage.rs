@@ -0,0 +1,685 @@
+//! Saved games and ratings. The server writes every game it hosts to a `GameStore` as it is
+//! played, so finished games can be looked up later and games in progress survive a restart.
+//! Players' ratings, and how each rated game moved them, are kept alongside.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction, TransactionBehavior};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::TimeControl;
+use crate::game::{Color, GameResult, GameState, Move, Outcome};
+use crate::pgn::{pgn_date, PgnHeaders};
+use crate::rating::{Category, Rating, RatingChange};
+
+/// One side of a stored game
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerRecord {
+    /// the name the player connected with, if they gave one
+    pub name: Option<String>,
+    /// the side is played by the computer
+    pub computer: bool,
+    /// token the player resumes the seat with, kept so a seat outlives a restart
+    #[serde(skip)]
+    pub resume_token: Option<String>,
+}
+
+/// A game as it is stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    /// storage key, None until the game is first saved. Game codes are reused over time,
+    /// so they do not identify a stored game on their own.
+    pub id: Option<u64>,
+    pub game_code: String,
+    pub white: PlayerRecord,
+    pub black: PlayerRecord,
+    pub time_control: Option<TimeControl>,
+    pub private: bool,
+    /// FEN the game started from, or None for the standard starting position
+    pub start_fen: Option<String>,
+    /// the moves in UCI notation
+    pub moves: Vec<String>,
+    pub outcome: Option<Outcome>,
+    /// time left for white and black at the last save, for timed games
+    pub clock_ms: Option<(u64, u64)>,
+    /// milliseconds since the Unix epoch
+    pub started_at: u64,
+    pub updated_at: u64,
+    /// set when the game ends; a game whose room closed before it had a result has this
+    /// without an outcome
+    pub ended_at: Option<u64>,
+}
+
+impl GameRecord {
+    /// The ratings the game counts towards, if it is rated
+    pub fn category(&self) -> Option<Category> {
+        Category::of(self.time_control)
+    }
+
+    /// Whether the game is still being played: it has no result, and its room has not
+    /// closed
+    pub fn in_progress(&self) -> bool {
+        self.outcome.is_none() && self.ended_at.is_none()
+    }
+
+    /// PGN tags for the game: the day it started and its players, with the computer named
+    /// as such
+    pub fn pgn_headers(&self) -> PgnHeaders {
+        let name = |player: &PlayerRecord| match &player.name {
+            _ if player.computer => "Computer".to_string(),
+            Some(name) => name.clone(),
+            None => "?".to_string(),
+        };
+        PgnHeaders {
+            date: pgn_date(UNIX_EPOCH + Duration::from_millis(self.started_at)),
+            white: name(&self.white),
+            black: name(&self.black),
+            ..PgnHeaders::default()
+        }
+    }
+
+    pub fn player(&self, color: Color) -> &PlayerRecord {
+        match color {
+            Color::White => &self.white,
+            Color::Black => &self.black,
+        }
+    }
+
+    pub fn player_mut(&mut self, color: Color) -> &mut PlayerRecord {
+        match color {
+            Color::White => &mut self.white,
+            Color::Black => &mut self.black,
+        }
+    }
+
+    /// Takes the moves and outcome of `gs`, stamping the update (and the end of the game,
+    /// the first time it has an outcome) with `now`
+    pub fn update_from(&mut self, gs: &GameState, now: u64) {
+        self.game_code = gs.game_code().to_string();
+        self.start_fen = gs.start_fen().map(str::to_string);
+        self.moves = gs.history().iter().map(Move::to_uci).collect();
+        self.outcome = gs.outcome();
+        self.updated_at = now;
+        if self.outcome.is_some() && self.ended_at.is_none() {
+            self.ended_at = Some(now);
+        }
+    }
+
+    /// Plays the stored moves over again from the starting position, giving the game with
+    /// its code and outcome
+    pub fn replay(&self) -> Result<GameState, StorageError> {
+        let mut gs = match &self.start_fen {
+            Some(fen) => GameState::from_fen(fen)
+                .map_err(|err| StorageError::Corrupt(format!("game {}: start FEN: {}", self.game_code, err)))?,
+            None => GameState::new(),
+        };
+        gs.set_game_code(self.game_code.clone());
+        for uci in &self.moves {
+            let mv = Move::from_uci(&gs, uci)
+                .map_err(|err| StorageError::Corrupt(format!("game {}: move {}: {}", self.game_code, uci, err)))?;
+            gs.replay_move(&mv);
+        }
+        if let Some(outcome) = self.outcome {
+            gs.set_outcome(outcome);
+        }
+        Ok(gs)
+    }
+}
+
+/// Which result a listed game must have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultFilter {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    /// not over yet
+    Ongoing,
+}
+
+impl ResultFilter {
+    fn matches(self, game: &GameRecord) -> bool {
+        let outcome = game.outcome;
+        match self {
+            ResultFilter::Ongoing => game.in_progress(),
+            ResultFilter::WhiteWins => outcome.is_some_and(|o| o.result == GameResult::WhiteWins),
+            ResultFilter::BlackWins => outcome.is_some_and(|o| o.result == GameResult::BlackWins),
+            ResultFilter::Draw => outcome.is_some_and(|o| o.result == GameResult::Draw),
+        }
+    }
+}
+
+/// Which stored games to list, newest first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameFilter {
+    /// games where either side had this name
+    pub player: Option<String>,
+    pub result: Option<ResultFilter>,
+    /// games started at or after this time, in milliseconds since the Unix epoch
+    pub started_from: Option<u64>,
+    /// games started before this time
+    pub started_before: Option<u64>,
+    /// leave out private games still being played, whose codes would let anyone join them
+    pub hide_private_ongoing: bool,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for GameFilter {
+    fn default() -> Self {
+        GameFilter { player: None, result: None, started_from: None, started_before: None, hide_private_ongoing: false, offset: 0, limit: 20 }
+    }
+}
+
+impl GameFilter {
+    fn matches(&self, game: &GameRecord) -> bool {
+        self.player.as_ref().is_none_or(|name| [&game.white, &game.black].iter().any(|side| side.name.as_ref() == Some(name)))
+            && self.result.is_none_or(|result| result.matches(game))
+            && self.started_from.is_none_or(|from| game.started_at >= from)
+            && self.started_before.is_none_or(|before| game.started_at < before)
+            && !(self.hide_private_ongoing && game.private && game.in_progress())
+    }
+}
+
+/// One page of a listing, and how many games match in all
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GamePage {
+    pub games: Vec<GameRecord>,
+    pub total: u64,
+}
+
+/// Where games are kept. Saving is cheap enough to do after every move.
+pub trait GameStore: Send + Sync {
+    /// Inserts a game that has no id yet, or updates the saved one; returns its id
+    fn save(&self, game: &GameRecord) -> Result<u64, StorageError>;
+    /// The latest game played under this code
+    fn load(&self, game_code: &str) -> Result<Option<GameRecord>, StorageError>;
+    /// Games that have not ended, oldest first, for restoring after a restart
+    fn active(&self) -> Result<Vec<GameRecord>, StorageError>;
+    /// The games that pass `filter`, newest first, from `filter.offset` on
+    fn list(&self, filter: &GameFilter) -> Result<GamePage, StorageError>;
+    /// A player's rating in a category as last stored, without decay for the time since
+    fn rating(&self, player: &str, category: Category) -> Result<Option<Rating>, StorageError>;
+    /// Every rating a player has, in the order of `Category::ALL`
+    fn ratings(&self, player: &str) -> Result<Vec<(Category, Rating)>, StorageError>;
+    /// Stores the ratings players have after a rated game, and the changes as its history
+    fn record_ratings(&self, changes: &[RatingChange]) -> Result<(), StorageError>;
+    /// Reads two players' ratings in a category and records the changes `rate` works out
+    /// from them, as one step: no other update to their ratings comes in between
+    fn update_ratings(&self, players: [&str; 2], category: Category, rate: &dyn Fn([Option<Rating>; 2]) -> [RatingChange; 2]) -> Result<[RatingChange; 2], StorageError>;
+    /// How a player's ratings moved, newest first, in one category or all of them
+    fn rating_history(&self, player: &str, category: Option<Category>, limit: usize) -> Result<Vec<RatingChange>, StorageError>;
+    /// Gives a player's name to whoever holds `token`, if nobody has it yet; true if the
+    /// name belongs to that token
+    fn claim_name(&self, name: &str, token: &str) -> Result<bool, StorageError>;
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// the database could not be opened, read or written
+    Sqlite(rusqlite::Error),
+    /// a stored game that does not make sense, such as an illegal move
+    Corrupt(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Sqlite(err) => write!(f, "database error: {}", err),
+            StorageError::Corrupt(reason) => write!(f, "corrupt game record: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        StorageError::Sqlite(err)
+    }
+}
+
+/// Keeps games in memory only, for tests and for running without a database
+#[derive(Default)]
+pub struct MemoryStore {
+    // a game's id is its index plus one
+    games: Mutex<Vec<GameRecord>>,
+    ratings: Mutex<HashMap<(String, Category), Rating>>,
+    // oldest first
+    rating_history: Mutex<Vec<RatingChange>>,
+    // the token each claimed name belongs to
+    names: Mutex<HashMap<String, String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl GameStore for MemoryStore {
+    fn save(&self, game: &GameRecord) -> Result<u64, StorageError> {
+        let mut games = self.games.lock().unwrap();
+        let id = match game.id {
+            Some(id) if (1..=games.len() as u64).contains(&id) => id,
+            Some(id) => return Err(StorageError::Corrupt(format!("no game has id {}", id))),
+            None => games.len() as u64 + 1,
+        };
+        let stored = GameRecord { id: Some(id), ..game.clone() };
+        match games.get_mut(id as usize - 1) {
+            Some(slot) => *slot = stored,
+            None => games.push(stored),
+        }
+        Ok(id)
+    }
+
+    fn load(&self, game_code: &str) -> Result<Option<GameRecord>, StorageError> {
+        let games = self.games.lock().unwrap();
+        Ok(games.iter().rev().find(|game| game.game_code == game_code).cloned())
+    }
+
+    fn active(&self) -> Result<Vec<GameRecord>, StorageError> {
+        let games = self.games.lock().unwrap();
+        Ok(games.iter().filter(|game| game.in_progress()).cloned().collect())
+    }
+
+    fn list(&self, filter: &GameFilter) -> Result<GamePage, StorageError> {
+        let games = self.games.lock().unwrap();
+        let matching: Vec<&GameRecord> = games.iter().rev().filter(|game| filter.matches(game)).collect();
+        Ok(GamePage {
+            total: matching.len() as u64,
+            games: matching.into_iter().skip(filter.offset).take(filter.limit).cloned().collect(),
+        })
+    }
+
+    fn rating(&self, player: &str, category: Category) -> Result<Option<Rating>, StorageError> {
+        Ok(self.ratings.lock().unwrap().get(&(player.to_string(), category)).copied())
+    }
+
+    fn ratings(&self, player: &str) -> Result<Vec<(Category, Rating)>, StorageError> {
+        let ratings = self.ratings.lock().unwrap();
+        Ok(Category::ALL.into_iter()
+            .filter_map(|category| ratings.get(&(player.to_string(), category)).map(|&rating| (category, rating)))
+            .collect())
+    }
+
+    fn record_ratings(&self, changes: &[RatingChange]) -> Result<(), StorageError> {
+        let mut ratings = self.ratings.lock().unwrap();
+        for change in changes {
+            ratings.insert((change.player.clone(), change.category), change.after);
+        }
+        self.rating_history.lock().unwrap().extend_from_slice(changes);
+        Ok(())
+    }
+
+    fn update_ratings(&self, players: [&str; 2], category: Category, rate: &dyn Fn([Option<Rating>; 2]) -> [RatingChange; 2]) -> Result<[RatingChange; 2], StorageError> {
+        // the ratings stay locked from the read to the write
+        let mut ratings = self.ratings.lock().unwrap();
+        let changes = rate(players.map(|player| ratings.get(&(player.to_string(), category)).copied()));
+        for change in &changes {
+            ratings.insert((change.player.clone(), change.category), change.after);
+        }
+        self.rating_history.lock().unwrap().extend_from_slice(&changes);
+        Ok(changes)
+    }
+
+    fn rating_history(&self, player: &str, category: Option<Category>, limit: usize) -> Result<Vec<RatingChange>, StorageError> {
+        let history = self.rating_history.lock().unwrap();
+        Ok(history.iter().rev()
+            .filter(|change| change.player == player && category.is_none_or(|category| change.category == category))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn claim_name(&self, name: &str, token: &str) -> Result<bool, StorageError> {
+        let mut names = self.names.lock().unwrap();
+        Ok(names.entry(name.to_string()).or_insert_with(|| token.to_string()) == token)
+    }
+}
+
+/// Keeps games in an SQLite database file
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS games (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    game_code TEXT NOT NULL,
+    white_name TEXT,
+    white_computer INTEGER NOT NULL,
+    white_token TEXT,
+    black_name TEXT,
+    black_computer INTEGER NOT NULL,
+    black_token TEXT,
+    time_control TEXT,
+    private INTEGER NOT NULL,
+    start_fen TEXT,
+    moves TEXT NOT NULL,
+    result TEXT,
+    termination TEXT,
+    white_ms INTEGER,
+    black_ms INTEGER,
+    started_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    ended_at INTEGER
+);
+CREATE INDEX IF NOT EXISTS games_by_code ON games (game_code);
+CREATE TABLE IF NOT EXISTS ratings (
+    player TEXT NOT NULL,
+    category TEXT NOT NULL,
+    rating REAL NOT NULL,
+    deviation REAL NOT NULL,
+    volatility REAL NOT NULL,
+    games INTEGER NOT NULL,
+    last_played_at INTEGER,
+    PRIMARY KEY (player, category)
+);
+CREATE TABLE IF NOT EXISTS rating_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    player TEXT NOT NULL,
+    category TEXT NOT NULL,
+    game_id INTEGER NOT NULL REFERENCES games (id),
+    game_code TEXT NOT NULL,
+    color TEXT NOT NULL,
+    rating_before TEXT NOT NULL,
+    rating_after TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS rating_history_by_player ON rating_history (player, category);
+CREATE TABLE IF NOT EXISTS names (
+    name TEXT PRIMARY KEY,
+    token TEXT NOT NULL
+);
+";
+
+/// The condition `GameRecord::in_progress` checks
+const IN_PROGRESS: &str = "result IS NULL AND ended_at IS NULL";
+
+const COLUMNS: &str = "id, game_code, white_name, white_computer, white_token, black_name, black_computer, black_token, \
+    time_control, private, moves, result, termination, white_ms, black_ms, started_at, updated_at, ended_at, start_fen";
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it and its tables if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        SqliteStore::with_connection(Connection::open(path)?)
+    }
+
+    /// A database that lives only as long as the store
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        SqliteStore::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+
+    fn query(&self, condition: &str, params: impl rusqlite::Params) -> Result<Vec<GameRecord>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM games {}", COLUMNS, condition))?;
+        let rows = stmt.query_map(params, |row| Ok(read_row(row)))?;
+        rows.map(|row| row.map_err(StorageError::from).and_then(|record| record)).collect()
+    }
+}
+
+impl GameStore for SqliteStore {
+    fn save(&self, game: &GameRecord) -> Result<u64, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let moves = game.moves.join(" ");
+        let time_control = game.time_control.map(|control| serde_json::to_string(&control).expect("time controls serialize"));
+        let (result, termination) = match game.outcome {
+            Some(outcome) => (Some(to_text(&outcome.result)), Some(to_text(&outcome.termination))),
+            None => (None, None),
+        };
+        let (white_ms, black_ms) = game.clock_ms.unzip();
+        let values = params![
+            game.game_code, game.white.name, game.white.computer, game.white.resume_token,
+            game.black.name, game.black.computer, game.black.resume_token,
+            time_control, game.private, moves, result, termination, white_ms, black_ms,
+            game.started_at, game.updated_at, game.ended_at, game.id, game.start_fen,
+        ];
+        if game.id.is_some() {
+            let updated = conn.execute(
+                "UPDATE games SET game_code = ?1, white_name = ?2, white_computer = ?3, white_token = ?4, \
+                 black_name = ?5, black_computer = ?6, black_token = ?7, time_control = ?8, private = ?9, \
+                 moves = ?10, result = ?11, termination = ?12, white_ms = ?13, black_ms = ?14, \
+                 started_at = ?15, updated_at = ?16, ended_at = ?17, start_fen = ?19 WHERE id = ?18",
+                values,
+            )?;
+            if updated == 0 {
+                return Err(StorageError::Corrupt(format!("no game has id {}", game.id.unwrap_or_default())));
+            }
+            return Ok(game.id.unwrap_or_default());
+        }
+        conn.execute(
+            &format!("INSERT INTO games ({}) VALUES (?18, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?19)", COLUMNS),
+            values,
+        )?;
+        Ok(conn.last_insert_rowid() as u64)
+    }
+
+    fn load(&self, game_code: &str) -> Result<Option<GameRecord>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let row = conn
+            .query_row(&format!("SELECT {} FROM games WHERE game_code = ?1 ORDER BY id DESC LIMIT 1", COLUMNS), [game_code], |row| Ok(read_row(row)))
+            .optional()?;
+        row.transpose()
+    }
+
+    fn active(&self) -> Result<Vec<GameRecord>, StorageError> {
+        self.query(&format!("WHERE {} ORDER BY id", IN_PROGRESS), [])
+    }
+
+    fn list(&self, filter: &GameFilter) -> Result<GamePage, StorageError> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        if let Some(name) = &filter.player {
+            values.push(name.clone().into());
+            conditions.push(format!("(white_name = ?{n} OR black_name = ?{n})", n = values.len()));
+        }
+        match filter.result {
+            Some(ResultFilter::Ongoing) => conditions.push(IN_PROGRESS.to_string()),
+            Some(result) => {
+                values.push(to_text(&result).into());
+                conditions.push(format!("result = ?{}", values.len()));
+            }
+            None => {}
+        }
+        if let Some(from) = filter.started_from {
+            values.push((from as i64).into());
+            conditions.push(format!("started_at >= ?{}", values.len()));
+        }
+        if let Some(before) = filter.started_before {
+            values.push((before as i64).into());
+            conditions.push(format!("started_at < ?{}", values.len()));
+        }
+        if filter.hide_private_ongoing {
+            conditions.push(format!("NOT (private AND {})", IN_PROGRESS));
+        }
+        let condition = if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) };
+        let total: u64 = {
+            let conn = self.conn.lock().unwrap();
+            conn.query_row(&format!("SELECT COUNT(*) FROM games {}", condition), rusqlite::params_from_iter(&values), |row| row.get(0))?
+        };
+        values.push((filter.limit as i64).into());
+        values.push((filter.offset as i64).into());
+        let page = format!("{} ORDER BY id DESC LIMIT ?{} OFFSET ?{}", condition, values.len() - 1, values.len());
+        Ok(GamePage { games: self.query(&page, rusqlite::params_from_iter(&values))?, total })
+    }
+
+    fn rating(&self, player: &str, category: Category) -> Result<Option<Rating>, StorageError> {
+        stored_rating(&self.conn.lock().unwrap(), player, category)
+    }
+
+    fn ratings(&self, player: &str) -> Result<Vec<(Category, Rating)>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT category, rating, deviation, volatility, games, last_played_at FROM ratings WHERE player = ?1")?;
+        let rows = stmt.query_map([player], |row| Ok((row.get::<_, String>("category")?, read_rating(row)?)))?;
+        let mut ratings = Vec::new();
+        for row in rows {
+            let (category, rating) = row?;
+            ratings.push((from_text::<Category>("category", &category)?, rating));
+        }
+        ratings.sort_by_key(|&(category, _)| Category::ALL.iter().position(|&c| c == category));
+        Ok(ratings)
+    }
+
+    fn record_ratings(&self, changes: &[RatingChange]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        write_rating_changes(&tx, changes)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_ratings(&self, players: [&str; 2], category: Category, rate: &dyn Fn([Option<Rating>; 2]) -> [RatingChange; 2]) -> Result<[RatingChange; 2], StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        // an immediate transaction keeps other connections to the file from writing
+        // between the read and the write
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let [white, black] = players;
+        let changes = rate([stored_rating(&tx, white, category)?, stored_rating(&tx, black, category)?]);
+        write_rating_changes(&tx, &changes)?;
+        tx.commit()?;
+        Ok(changes)
+    }
+
+    fn rating_history(&self, player: &str, category: Option<Category>, limit: usize) -> Result<Vec<RatingChange>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT player, category, game_id, game_code, color, rating_before, rating_after FROM rating_history \
+             WHERE player = ?1 AND (?2 IS NULL OR category = ?2) ORDER BY id DESC LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![player, category.map(|category| to_text(&category)), limit as i64], |row| {
+            Ok((
+                row.get::<_, String>("player")?,
+                row.get::<_, String>("category")?,
+                row.get::<_, u64>("game_id")?,
+                row.get::<_, String>("game_code")?,
+                row.get::<_, String>("color")?,
+                row.get::<_, String>("rating_before")?,
+                row.get::<_, String>("rating_after")?,
+            ))
+        })?;
+        let rating = |json: &str| serde_json::from_str(json).map_err(|_| StorageError::Corrupt(format!("bad rating {}", json)));
+        let mut history = Vec::new();
+        for row in rows {
+            let (player, category, game_id, game_code, color, before, after) = row?;
+            history.push(RatingChange {
+                player,
+                category: from_text("category", &category)?,
+                game_id,
+                game_code,
+                color: from_text("color", &color)?,
+                before: rating(&before)?,
+                after: rating(&after)?,
+            });
+        }
+        Ok(history)
+    }
+
+    fn claim_name(&self, name: &str, token: &str) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("INSERT OR IGNORE INTO names (name, token) VALUES (?1, ?2)", params![name, token])?;
+        let owner: String = conn.query_row("SELECT token FROM names WHERE name = ?1", [name], |row| row.get(0))?;
+        Ok(owner == token)
+    }
+}
+
+/// The snake_case name serde gives a unit enum variant
+fn to_text<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(text)) => text,
+        _ => unreachable!("only unit variants are stored as text"),
+    }
+}
+
+fn from_text<T: DeserializeOwned>(column: &str, text: &str) -> Result<T, StorageError> {
+    serde_json::from_value(serde_json::Value::String(text.to_string()))
+        .map_err(|_| StorageError::Corrupt(format!("bad {} {:?}", column, text)))
+}
+
+/// A player's stored rating in a category
+fn stored_rating(conn: &Connection, player: &str, category: Category) -> Result<Option<Rating>, StorageError> {
+    let rating = conn
+        .query_row(
+            "SELECT rating, deviation, volatility, games, last_played_at FROM ratings WHERE player = ?1 AND category = ?2",
+            params![player, to_text(&category)],
+            read_rating,
+        )
+        .optional()?;
+    Ok(rating)
+}
+
+/// Stores the ratings after some changes, and the changes as history, within a transaction
+fn write_rating_changes(tx: &Transaction, changes: &[RatingChange]) -> Result<(), StorageError> {
+    for change in changes {
+        let after = &change.after;
+        tx.execute(
+            "INSERT INTO ratings (player, category, rating, deviation, volatility, games, last_played_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+             ON CONFLICT (player, category) DO UPDATE SET rating = ?3, deviation = ?4, volatility = ?5, games = ?6, last_played_at = ?7",
+            params![change.player, to_text(&change.category), after.rating, after.deviation, after.volatility, after.games, after.last_played_at],
+        )?;
+        tx.execute(
+            "INSERT INTO rating_history (player, category, game_id, game_code, color, rating_before, rating_after) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                change.player, to_text(&change.category), change.game_id, change.game_code, to_text(&change.color),
+                serde_json::to_string(&change.before).expect("ratings serialize"),
+                serde_json::to_string(&change.after).expect("ratings serialize"),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn read_rating(row: &Row) -> rusqlite::Result<Rating> {
+    Ok(Rating {
+        rating: row.get("rating")?,
+        deviation: row.get("deviation")?,
+        volatility: row.get("volatility")?,
+        games: row.get("games")?,
+        last_played_at: row.get("last_played_at")?,
+    })
+}
+
+fn read_row(row: &Row) -> Result<GameRecord, StorageError> {
+    let time_control: Option<String> = row.get("time_control")?;
+    let time_control = time_control
+        .map(|json| serde_json::from_str(&json).map_err(|_| StorageError::Corrupt(format!("bad time control {}", json))))
+        .transpose()?;
+    let result: Option<String> = row.get("result")?;
+    let termination: Option<String> = row.get("termination")?;
+    let outcome = match (result, termination) {
+        (Some(result), Some(termination)) => Some(Outcome {
+            result: from_text::<GameResult>("result", &result)?,
+            termination: from_text("termination", &termination)?,
+        }),
+        _ => None,
+    };
+    let moves: String = row.get("moves")?;
+    let white_ms: Option<u64> = row.get("white_ms")?;
+    let black_ms: Option<u64> = row.get("black_ms")?;
+    Ok(GameRecord {
+        id: Some(row.get("id")?),
+        game_code: row.get("game_code")?,
+        white: PlayerRecord { name: row.get("white_name")?, computer: row.get("white_computer")?, resume_token: row.get("white_token")? },
+        black: PlayerRecord { name: row.get("black_name")?, computer: row.get("black_computer")?, resume_token: row.get("black_token")? },
+        time_control,
+        private: row.get("private")?,
+        start_fen: row.get("start_fen")?,
+        moves: moves.split_whitespace().map(str::to_string).collect(),
+        outcome,
+        clock_ms: white_ms.zip(black_ms),
+        started_at: row.get("started_at")?,
+        updated_at: row.get("updated_at")?,
+        ended_at: row.get("ended_at")?,
+    })
+}
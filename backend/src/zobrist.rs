@@ -0,0 +1,51 @@
+use crate::bitboard::{color_index, piece_index};
+use crate::game::{Color, PieceType};
+
+// Random keys for Zobrist hashing: a position's hash is the XOR of the keys for every
+// piece on its square, the side to move, each castling right still held, and the en
+// passant file when an en passant capture is possible.
+
+const PIECE_KEYS: usize = 2 * 6 * 64;
+const BLACK_TO_MOVE: usize = PIECE_KEYS;
+const CASTLING: usize = BLACK_TO_MOVE + 1;
+const EN_PASSANT: usize = CASTLING + 4;
+const KEY_COUNT: usize = EN_PASSANT + 8;
+
+/// splitmix64 from a fixed seed, so hashes are the same on every run and build
+const fn generate_keys() -> [u64; KEY_COUNT] {
+    let mut keys = [0u64; KEY_COUNT];
+    let mut state: u64 = 0x5EED_C0FF_EE15_C4E5;
+    let mut i = 0;
+    while i < KEY_COUNT {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+static KEYS: [u64; KEY_COUNT] = generate_keys();
+
+/// Key for a piece of `color` and `piece_type` standing on `sq`
+pub fn piece(color: Color, piece_type: PieceType, sq: u8) -> u64 {
+    KEYS[(color_index(color) * 6 + piece_index(piece_type)) * 64 + sq as usize]
+}
+
+/// Key toggled whenever the side to move changes
+pub fn black_to_move() -> u64 {
+    KEYS[BLACK_TO_MOVE]
+}
+
+/// Key for one castling right: 0 = white kingside, 1 = white queenside,
+/// 2 = black kingside, 3 = black queenside
+pub fn castling(right: usize) -> u64 {
+    KEYS[CASTLING + right]
+}
+
+/// Key for an en passant target on `sq`; only the file matters
+pub fn en_passant(sq: u8) -> u64 {
+    KEYS[EN_PASSANT + (sq % 8) as usize]
+}
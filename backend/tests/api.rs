@@ -0,0 +1,124 @@
+use chess::api::{decode_path_segment, parse_day, GameDetail, GamesQuery, PlayerQuery, PlayerRatings, MAX_PER_PAGE};
+use chess::game::{Color, Outcome, Termination, STARTING_FEN};
+use chess::rating::{Category, Rating};
+use chess::storage::{GameRecord, PlayerRecord, ResultFilter};
+use serde_json::Value;
+
+fn query(pairs: &[(&str, &str)]) -> GamesQuery {
+    let text: Vec<String> = pairs.iter().map(|(key, value)| format!("\"{}\":\"{}\"", key, value)).collect();
+    serde_json::from_str(&format!("{{{}}}", text.join(","))).unwrap()
+}
+
+#[test]
+fn games_query_becomes_a_filter() {
+    let (filter, page, per_page) = GamesQuery::default().filter().unwrap();
+    assert_eq!((page, per_page, filter.offset, filter.limit), (1, 20, 0, 20));
+    assert!(filter.hide_private_ongoing);
+
+    let (filter, page, _) = query(&[("player", "alice"), ("result", "ongoing"), ("from", "2025-03-01"), ("to", "2025-03-01"), ("page", "3"), ("per_page", "10")]).filter().unwrap();
+    assert_eq!(page, 3);
+    assert_eq!((filter.offset, filter.limit), (20, 10));
+    assert_eq!(filter.player.as_deref(), Some("alice"));
+    assert_eq!(filter.result, Some(ResultFilter::Ongoing));
+    // the "to" day is included in full
+    assert_eq!(filter.started_from, Some(1_740_787_200_000));
+    assert_eq!(filter.started_before, Some(1_740_787_200_000 + 86_400_000));
+
+    assert_eq!(query(&[("per_page", "1000")]).filter().unwrap().2, MAX_PER_PAGE);
+    for bad in [("result", "won"), ("from", "2025-02-30"), ("to", "yesterday"), ("to", "1000000000-01-01"), ("page", "0"), ("per_page", "-1")] {
+        assert_eq!(query(&[bad]).filter().unwrap_err().code, "bad_request", "{:?}", bad);
+    }
+}
+
+#[test]
+fn parses_days() {
+    assert_eq!(parse_day("1970-01-01"), Some(0));
+    assert_eq!(parse_day("2000-03-01"), Some(951_868_800_000));
+    assert_eq!(parse_day("2024-02-29"), Some(1_709_164_800_000));
+    assert_eq!(parse_day("2023-02-29"), None);
+    assert_eq!(parse_day("1969-12-31"), None);
+    assert_eq!(parse_day("2024-13-01"), None);
+    // too far off to count in milliseconds
+    assert_eq!(parse_day("1000000000-01-01"), None);
+    assert_eq!(parse_day("9223372036854775807-12-31"), None);
+}
+
+#[test]
+fn game_detail_has_a_fen_per_ply() {
+    let record = GameRecord {
+        id: Some(1),
+        game_code: "123456".to_string(),
+        white: PlayerRecord { name: Some("alice".to_string()), computer: false, resume_token: Some("secret".to_string()) },
+        black: PlayerRecord::default(),
+        time_control: None,
+        private: false,
+        start_fen: None,
+        moves: vec!["e2e4".to_string(), "c7c5".to_string()],
+        outcome: Some(Outcome::win(Color::Black, Termination::Resignation)),
+        clock_ms: None,
+        started_at: 0,
+        updated_at: 0,
+        ended_at: Some(0),
+    };
+    let detail = GameDetail::new(&record, false).unwrap();
+    assert_eq!(detail.initial_fen, STARTING_FEN);
+    let fens: Vec<&str> = detail.moves.iter().map(|ply| ply.fen.as_str()).collect();
+    assert_eq!(fens, [
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+        "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+    ]);
+    assert_eq!(detail.moves[1].ply, 2);
+    assert_eq!(detail.moves[1].mv.san, "c5");
+
+    // the body keeps the game's fields at the top level and never shows resume tokens
+    let json: Value = serde_json::to_value(&detail).unwrap();
+    assert_eq!(json["game_code"], "123456");
+    assert_eq!(json["outcome"]["termination"], "resignation");
+    assert_eq!(json["move_count"], 2);
+    assert_eq!(json["moves"][0]["uci"], "e2e4");
+    assert_eq!(json["white"], serde_json::json!({ "name": "alice", "computer": false }));
+}
+
+#[test]
+fn game_detail_starts_from_the_start_fen() {
+    let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+    let record = GameRecord {
+        id: Some(1),
+        game_code: "123456".to_string(),
+        white: PlayerRecord::default(),
+        black: PlayerRecord::default(),
+        time_control: None,
+        private: false,
+        start_fen: Some(fen.to_string()),
+        moves: vec!["e2e4".to_string(), "e8d7".to_string()],
+        outcome: None,
+        clock_ms: None,
+        started_at: 0,
+        updated_at: 0,
+        ended_at: None,
+    };
+    let detail = GameDetail::new(&record, true).unwrap();
+    assert_eq!(detail.initial_fen, fen);
+    assert_eq!(detail.moves[1].fen, "8/3k4/8/8/4P3/8/8/4K3 w - - 1 2");
+    assert_eq!(detail.moves[1].mv.san, "Kd7");
+}
+
+#[test]
+fn player_ratings_are_shown_as_of_now() {
+    let day = 86_400_000;
+    let settled = Rating { rating: 1720.0, deviation: 60.0, games: 50, last_played_at: Some(0), ..Rating::default() };
+    let body = serde_json::to_value(PlayerRatings::new("alice".to_string(), vec![(Category::Blitz, settled)], Vec::new(), 365 * day)).unwrap();
+    let blitz = &body["ratings"][0];
+    assert_eq!(blitz["category"], "blitz");
+    assert_eq!(blitz["rating"], 1720.0);
+    assert_eq!(blitz["games"], 50);
+    // a year away makes the rating provisional again
+    assert!(blitz["deviation"].as_f64().unwrap() > 110.0);
+    assert_eq!(blitz["provisional"], true);
+
+    assert_eq!(PlayerQuery { category: Some("rapid".to_string()) }.category().unwrap(), Some(Category::Rapid));
+    assert_eq!(PlayerQuery { category: Some("slow".to_string()) }.category().unwrap_err().code, "bad_request");
+    assert_eq!(decode_path_segment("Magnus%20C%C3%A9sar").as_deref(), Some("Magnus César"));
+    assert_eq!(decode_path_segment("100%").as_deref(), Some("100%"));
+    assert_eq!(decode_path_segment("%FF"), None);
+}
@@ -0,0 +1,130 @@
+use std::time::{Duration, Instant};
+
+use chess::clock::{Clock, TimeControl, MAX_BASE_MS, MAX_DAYS_PER_MOVE, MAX_EXTRA_MS};
+use chess::game::{Color, GameState, Outcome, Termination};
+
+const SECOND: Duration = Duration::from_secs(1);
+
+#[test]
+fn time_controls_have_limits() {
+    assert!(TimeControl::Fischer { base_ms: 180_000, increment_ms: 2000 }.is_valid());
+    assert!(TimeControl::Fischer { base_ms: MAX_BASE_MS, increment_ms: MAX_EXTRA_MS }.is_valid());
+    assert!(!TimeControl::Fischer { base_ms: 0, increment_ms: 2000 }.is_valid());
+    assert!(!TimeControl::Fischer { base_ms: MAX_BASE_MS + 1, increment_ms: 0 }.is_valid());
+    assert!(!TimeControl::Fischer { base_ms: 180_000, increment_ms: u64::MAX }.is_valid());
+    assert!(!TimeControl::Bronstein { base_ms: 180_000, delay_ms: MAX_EXTRA_MS + 1 }.is_valid());
+    assert!(TimeControl::Correspondence { days_per_move: MAX_DAYS_PER_MOVE }.is_valid());
+    assert!(!TimeControl::Correspondence { days_per_move: 0 }.is_valid());
+    assert!(!TimeControl::Correspondence { days_per_move: u32::MAX }.is_valid());
+}
+
+#[test]
+fn clock_starts_after_first_move() {
+    let start = Instant::now();
+    let mut clock = Clock::new(TimeControl::Fischer { base_ms: 60_000, increment_ms: 2000 });
+    assert_eq!(clock.running(), None);
+    assert_eq!(clock.deadline(), None);
+    // white's first move is free and starts black's time, without an increment
+    clock.press(Color::White, start + 30 * SECOND).unwrap();
+    assert_eq!(clock.remaining(Color::White, start + 30 * SECOND), 60 * SECOND);
+    assert_eq!(clock.running(), Some(Color::Black));
+    assert_eq!(clock.remaining(Color::Black, start + 40 * SECOND), 50 * SECOND);
+}
+
+#[test]
+fn fischer_adds_the_increment() {
+    let start = Instant::now();
+    let mut clock = Clock::new(TimeControl::Fischer { base_ms: 60_000, increment_ms: 2000 });
+    clock.press(Color::White, start).unwrap();
+    clock.press(Color::Black, start + 5 * SECOND).unwrap();
+    assert_eq!(clock.remaining(Color::Black, start + 5 * SECOND), 57 * SECOND);
+    assert_eq!(clock.deadline(), Some(start + 65 * SECOND));
+}
+
+#[test]
+fn bronstein_returns_time_up_to_the_delay() {
+    let start = Instant::now();
+    let mut clock = Clock::new(TimeControl::Bronstein { base_ms: 60_000, delay_ms: 3000 });
+    clock.press(Color::White, start).unwrap();
+    // a quick move costs nothing
+    clock.press(Color::Black, start + 2 * SECOND).unwrap();
+    assert_eq!(clock.remaining(Color::Black, start + 2 * SECOND), 60 * SECOND);
+    // a slow one costs all but the delay
+    clock.press(Color::White, start + 12 * SECOND).unwrap();
+    assert_eq!(clock.remaining(Color::White, start + 12 * SECOND), 53 * SECOND);
+}
+
+#[test]
+fn correspondence_resets_every_move() {
+    let day = 24 * 60 * 60 * SECOND;
+    let start = Instant::now();
+    let mut clock = Clock::new(TimeControl::Correspondence { days_per_move: 2 });
+    clock.press(Color::White, start).unwrap();
+    clock.press(Color::Black, start + day).unwrap();
+    assert_eq!(clock.remaining(Color::Black, start + day), 2 * day);
+    assert_eq!(clock.deadline(), Some(start + 3 * day));
+}
+
+#[test]
+fn late_move_flags() {
+    let start = Instant::now();
+    let mut clock = Clock::new(TimeControl::Fischer { base_ms: 10_000, increment_ms: 0 });
+    clock.press(Color::White, start).unwrap();
+    assert_eq!(clock.check_flag(start + 9 * SECOND), None);
+    assert_eq!(clock.press(Color::Black, start + 11 * SECOND), Err(Color::Black));
+    assert_eq!(clock.flagged(), Some(Color::Black));
+    assert_eq!(clock.running(), None);
+    assert_eq!(clock.remaining(Color::Black, start + 20 * SECOND), Duration::ZERO);
+}
+
+#[test]
+fn takeback_hands_over_the_clock() {
+    let start = Instant::now();
+    let mut clock = Clock::new(TimeControl::Fischer { base_ms: 60_000, increment_ms: 0 });
+    clock.press(Color::White, start).unwrap();
+    clock.press(Color::Black, start + 10 * SECOND).unwrap();
+    clock.hand_to(Color::Black, start + 15 * SECOND);
+    assert_eq!(clock.remaining(Color::White, start + 15 * SECOND), 55 * SECOND);
+    assert_eq!(clock.running(), Some(Color::Black));
+    clock.stop(start + 20 * SECOND);
+    assert_eq!(clock.running(), None);
+    assert_eq!(clock.remaining(Color::Black, start + 60 * SECOND), 45 * SECOND);
+}
+
+fn flagged_game(fen: &str, loser: Color) -> GameState {
+    let start = Instant::now();
+    let mut clock = Clock::new(TimeControl::Fischer { base_ms: 1000, increment_ms: 0 });
+    let first = if loser == Color::Black { Color::White } else { Color::Black };
+    clock.press(first, start).unwrap();
+    assert_eq!(clock.check_flag(start + 2 * SECOND), Some(loser));
+    let mut gs = GameState::from_fen(fen).unwrap();
+    gs.set_clock(Some(clock));
+    gs
+}
+
+#[test]
+fn flag_fall_draws_without_mating_material() {
+    let gs = flagged_game("4k3/8/8/8/8/8/3PPP2/3NKN2 b - - 0 1", Color::Black);
+    assert_eq!(gs.flagged(), Some(Color::Black));
+    assert_eq!(gs.timeout_outcome(Color::Black), Outcome::win(Color::White, Termination::Timeout));
+    // white has only a knight left, which cannot mate a bare king
+    let gs = flagged_game("4k3/8/8/8/8/8/8/4KN2 b - - 0 1", Color::Black);
+    assert_eq!(gs.timeout_outcome(Color::Black), Outcome::draw(Termination::TimeoutVsInsufficientMaterial));
+    assert!(!gs.has_mating_material(Color::White));
+    // but it can mate a king hemmed in by its own pawns, so that flag fall loses
+    let gs = flagged_game("4k3/3ppp2/8/8/8/8/8/4KN2 b - - 0 1", Color::Black);
+    assert_eq!(gs.timeout_outcome(Color::Black), Outcome::win(Color::White, Termination::Timeout));
+    assert!(gs.has_mating_material(Color::White));
+    // and black's pawns could still mate when white is the one who runs out
+    assert_eq!(gs.timeout_outcome(Color::White), Outcome::win(Color::Black, Termination::Timeout));
+    // a lone bishop against a bishop: only a block on the other colour allows mate
+    let gs = flagged_game("2b1k3/8/8/8/8/8/8/2B1K3 b - - 0 1", Color::Black);
+    assert_eq!(gs.timeout_outcome(Color::Black), Outcome::win(Color::White, Termination::Timeout));
+    let gs = flagged_game("4kb2/8/8/8/8/8/8/2B1K3 b - - 0 1", Color::Black);
+    assert_eq!(gs.timeout_outcome(Color::Black), Outcome::draw(Termination::TimeoutVsInsufficientMaterial));
+    // two minor pieces are enough, unless they are bishops on the same colour
+    let gs = flagged_game("4k3/8/8/8/8/8/8/2B1KN2 b - - 0 1", Color::Black);
+    assert_eq!(gs.timeout_outcome(Color::Black), Outcome::win(Color::White, Termination::Timeout));
+    let gs = flagged_game("4k3/8/8/8/8/4B3/8/2B1K3 b - - 0 1", Color::Black);
+    assert_eq!(gs.timeout_outcome(Color::Black), Outcome::draw(Termination::TimeoutVsInsufficientMaterial));
+}
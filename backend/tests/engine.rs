@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+
+use chess::engine::{evaluate, search, Limits, MATE_BOUND};
+use chess::game::{GameState, STARTING_FEN};
+
+fn best_uci(fen: &str, limits: Limits) -> Option<String> {
+    let state = GameState::from_fen(fen).unwrap();
+    search(&state, &limits).best_move.map(|mv| mv.to_uci())
+}
+
+#[test]
+fn starting_position_is_balanced() {
+    assert_eq!(evaluate(&GameState::from_fen(STARTING_FEN).unwrap()), 0);
+}
+
+#[test]
+fn finds_back_rank_mate() {
+    let state = GameState::from_fen("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1").unwrap();
+    let result = search(&state, &Limits::depth(3));
+    assert_eq!(result.best_move.unwrap().to_uci(), "d1d8");
+    assert!(result.score >= MATE_BOUND);
+}
+
+#[test]
+fn takes_hanging_queen() {
+    assert_eq!(best_uci("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1", Limits::depth(2)).as_deref(), Some("d2d5"));
+}
+
+#[test]
+fn no_move_when_checkmated() {
+    // fool's mate
+    let fen = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+    assert_eq!(best_uci(fen, Limits::depth(3)), None);
+}
+
+#[test]
+fn respects_time_budget() {
+    let state = GameState::new();
+    let started = Instant::now();
+    let result = search(&state, &Limits::time(Duration::from_millis(200)));
+    assert!(result.best_move.is_some());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
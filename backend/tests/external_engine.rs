@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use chess::external_engine::{EngineError, ExternalEngine};
+use chess::game::{GameState, Move};
+
+/// The project's own UCI binary stands in for a third-party engine
+fn engine() -> ExternalEngine {
+    ExternalEngine::new(env!("CARGO_BIN_EXE_chess-uci"))
+}
+
+#[tokio::test]
+async fn plays_move_from_game_history() {
+    let mut state = GameState::new();
+    for uci in ["f2f3", "e7e5", "g2g4"] {
+        let mv = Move::from_uci(&state, uci).unwrap();
+        state.apply_move(&mv);
+    }
+    let mut engine = engine();
+    let mv = engine.best_move(&state, Duration::from_millis(100)).await.unwrap();
+    assert_eq!(mv.to_uci(), "d8h4");
+    engine.shutdown().await;
+}
+
+#[tokio::test]
+async fn restarts_after_shutdown() {
+    let state = GameState::from_fen("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1").unwrap();
+    let mut engine = engine();
+    engine.best_move(&state, Duration::from_millis(50)).await.unwrap();
+    engine.shutdown().await;
+    let mv = engine.best_move(&state, Duration::from_millis(50)).await.unwrap();
+    assert_eq!(mv.to_uci(), "d1d8");
+    assert_eq!(engine.starts(), 2);
+}
+
+#[tokio::test]
+async fn missing_executable_is_reported() {
+    let mut engine = ExternalEngine::new("/nonexistent/uci-engine");
+    let err = engine.best_move(&GameState::new(), Duration::from_millis(50)).await.unwrap_err();
+    assert!(matches!(err, EngineError::Spawn(_)));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn crashing_engine_is_restarted_once() {
+    use std::os::unix::fs::PermissionsExt;
+    // completes the handshake, then dies as soon as it is asked to search
+    let script = std::env::temp_dir().join(format!("crashing-engine-{}.sh", std::process::id()));
+    std::fs::write(&script, "#!/bin/sh\nwhile read line; do\n  case \"$line\" in\n    uci) echo uciok ;;\n    isready) echo readyok ;;\n    go*) exit 1 ;;\n  esac\ndone\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let mut engine = ExternalEngine::new(&script);
+    let err = engine.best_move(&GameState::new(), Duration::from_millis(50)).await.unwrap_err();
+    assert!(matches!(err, EngineError::Crashed));
+    assert_eq!(engine.starts(), 2);
+    let _ = std::fs::remove_file(&script);
+}
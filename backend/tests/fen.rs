@@ -0,0 +1,42 @@
+use chess::game::{FenError, GameState, STARTING_FEN};
+
+fn error(fen: &str) -> FenError {
+    GameState::from_fen(fen).unwrap_err()
+}
+
+#[test]
+fn positions_round_trip() {
+    assert_eq!(GameState::new().to_fen(), STARTING_FEN);
+    for fen in [
+        STARTING_FEN,
+        // after 1. e4: the en passant square, and black to move
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+        "r3k2r/8/8/8/8/8/8/R3K2R w Kq - 12 40",
+        "8/3k4/8/8/4P3/8/8/4K3 w - - 1 2",
+    ] {
+        assert_eq!(GameState::from_fen(fen).unwrap().to_fen(), fen);
+    }
+    // the clocks may be left off, and default to a fresh game's
+    assert_eq!(GameState::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - -").unwrap().to_fen(), "4k3/8/8/8/8/8/4P3/4K3 b - - 0 1");
+}
+
+#[test]
+fn each_malformed_field_is_named() {
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K3 w"), FenError::FieldCount(2));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 extra"), FenError::FieldCount(7));
+
+    assert_eq!(error("4k3/8/8/4K3 w - - 0 1"), FenError::Board("expected 8 ranks, found 4".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K2 w - - 0 1"), FenError::Board("rank 1 has 7 squares".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K4 w - - 0 1"), FenError::Board("rank 1 has more than 8 squares".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4X3/4K3 w - - 0 1"), FenError::Board("unknown piece 'X' on rank 2".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/8 w - - 0 1"), FenError::Board("White must have exactly one king, found 0".to_string()));
+    assert_eq!(error("4k2P/8/8/8/8/8/8/4K3 w - - 0 1"), FenError::Board("pawn on the first or last rank".to_string()));
+
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K3 x - - 0 1"), FenError::SideToMove("expected 'w' or 'b', found 'x'".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K3 w KX - 0 1"), FenError::CastlingRights("unexpected character 'X'".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K3 w KK - 0 1"), FenError::CastlingRights("'K' given twice".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K3 w - z9 0 1"), FenError::EnPassant("'z9' is not a square".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K3 w - e3 0 1"), FenError::EnPassant("e3 is not on rank 6".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K3 w - - x 1"), FenError::HalfmoveClock("'x' is not a number".to_string()));
+    assert_eq!(error("4k3/8/8/8/8/8/4P3/4K3 w - - 0 0"), FenError::FullmoveClock("'0' is not a positive number".to_string()));
+}
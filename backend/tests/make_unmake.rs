@@ -0,0 +1,46 @@
+use chess::game::{GameState, STARTING_FEN};
+
+const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+// white to move can promote with and without capture, and black's last move allows en passant
+const PROMOTIONS: &str = "r3k2r/1P4P1/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1";
+
+/// Makes and unmakes every move to `depth` plies, checking the position is restored each time
+fn round_trip(state: &mut GameState, depth: u32) {
+    if depth == 0 {
+        return;
+    }
+    let before = serde_json::to_value(&*state).unwrap();
+    for mv in state.legal_moves() {
+        let undo = state.make_move(&mv);
+        let fresh = GameState::from_fen(&state.to_fen()).unwrap();
+        assert_eq!(state.hash(), fresh.hash(), "incremental hash after {}", mv.to_uci());
+        round_trip(state, depth - 1);
+        state.unmake_move(&mv, undo);
+        assert_eq!(serde_json::to_value(&*state).unwrap(), before, "after unmaking {}", mv.to_uci());
+    }
+}
+
+#[test]
+fn unmake_restores_position() {
+    for fen in [STARTING_FEN, KIWIPETE, PROMOTIONS] {
+        let mut state = GameState::from_fen(fen).unwrap();
+        round_trip(&mut state, 2);
+        assert_eq!(state.to_fen(), fen);
+    }
+}
+
+#[test]
+fn take_back_rewinds_history() {
+    let mut state = GameState::new();
+    assert!(state.take_back().is_none());
+    for san in ["e4", "d5", "exd5", "Qxd5"] {
+        let mv = chess::game::Move::from_san(&state, san).unwrap();
+        state.apply_move(&mv);
+    }
+    let taken = state.take_back().unwrap();
+    assert_eq!(taken.to_uci(), "d8d5");
+    assert_eq!(state.history().len(), 3);
+    assert_eq!(state.to_fen(), "rnbqkbnr/ppp1pppp/8/3P4/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2");
+    while state.take_back().is_some() {}
+    assert_eq!(state.to_fen(), STARTING_FEN);
+}
@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+use chess::clock::TimeControl;
+use chess::matchmaking::{MatchQueue, QueueEntry, BASE_WINDOW, WINDOW_GROWTH_PER_SECOND};
+
+const BLITZ: Option<TimeControl> = Some(TimeControl::Fischer { base_ms: 180_000, increment_ms: 2000 });
+
+fn entry(client_id: usize, rating: f64, joined_at: Instant) -> QueueEntry {
+    QueueEntry { client_id, time_control: BLITZ, rating, color_balance: 0, joined_at }
+}
+
+fn paired_ids(queue: &mut MatchQueue, now: Instant) -> Vec<(usize, usize)> {
+    queue.pair(now).into_iter()
+        .map(|pairing| {
+            let (white, black) = (pairing.white.client_id, pairing.black.client_id);
+            (white.min(black), white.max(black))
+        })
+        .collect()
+}
+
+#[test]
+fn pairs_the_nearest_rated_opponent() {
+    let start = Instant::now();
+    let mut queue = MatchQueue::new();
+    queue.join(entry(1, 1500.0, start));
+    queue.join(entry(2, 1580.0, start));
+    queue.join(entry(3, 1520.0, start));
+    assert_eq!(paired_ids(&mut queue, start), [(1, 3)]);
+    assert!(queue.contains(2));
+    assert_eq!(queue.len(), 1);
+}
+
+#[test]
+fn window_widens_while_waiting() {
+    let start = Instant::now();
+    let mut queue = MatchQueue::new();
+    queue.join(entry(1, 1500.0, start));
+    queue.join(entry(2, 1800.0, start));
+    assert!(paired_ids(&mut queue, start).is_empty());
+    // both windows must reach the gap
+    let reach = Duration::from_secs_f64((300.0 - BASE_WINDOW) / WINDOW_GROWTH_PER_SECOND);
+    assert!(paired_ids(&mut queue, start + reach - Duration::from_secs(1)).is_empty());
+    assert_eq!(paired_ids(&mut queue, start + reach), [(1, 2)]);
+}
+
+#[test]
+fn pairs_only_the_same_time_control() {
+    let start = Instant::now();
+    let mut queue = MatchQueue::new();
+    queue.join(entry(1, 1500.0, start));
+    queue.join(QueueEntry { time_control: None, ..entry(2, 1500.0, start) });
+    assert!(paired_ids(&mut queue, start + Duration::from_secs(600)).is_empty());
+    assert!(queue.cancel(2));
+    assert!(!queue.cancel(2));
+}
+
+#[test]
+fn balances_colors() {
+    let start = Instant::now();
+    let mut queue = MatchQueue::new();
+    // player 1 has had white three times more than black lately
+    queue.join(QueueEntry { color_balance: 3, ..entry(1, 1500.0, start) });
+    queue.join(QueueEntry { color_balance: -1, ..entry(2, 1500.0, start) });
+    let pairing = queue.pair(start).pop().unwrap();
+    assert_eq!((pairing.white.client_id, pairing.black.client_id), (2, 1));
+}
+
+#[test]
+fn status_estimates_the_wait_from_recent_pairings() {
+    let start = Instant::now();
+    let mut queue = MatchQueue::new();
+    queue.join(entry(1, 1500.0, start));
+    let status = queue.status(1, start + Duration::from_secs(2)).unwrap();
+    assert_eq!((status.searching, status.waited_ms, status.estimated_wait_ms), (1, 2000, None));
+    assert_eq!((status.rating_min, status.rating_max), (1360, 1640));
+
+    queue.join(entry(2, 1500.0, start + Duration::from_secs(6)));
+    assert_eq!(queue.pair(start + Duration::from_secs(10)).len(), 1);
+    // the two waited ten and four seconds
+    queue.join(entry(3, 2400.0, start + Duration::from_secs(10)));
+    let status = queue.status(3, start + Duration::from_secs(12)).unwrap();
+    assert_eq!(status.estimated_wait_ms, Some(5000));
+    assert_eq!(queue.status(1, start), None);
+}
@@ -0,0 +1,99 @@
+use chess::game::{GameState, STARTING_FEN};
+
+// Reference positions and node counts from https://www.chessprogramming.org/Perft_Results
+const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+const POSITION_4_MIRRORED: &str = "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+const POSITION_6: &str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+fn check(fen: &str, expected: &[u64]) {
+    let state = GameState::from_fen(fen).unwrap();
+    for (i, &nodes) in expected.iter().enumerate() {
+        let depth = i as u32 + 1;
+        assert_eq!(state.perft(depth), nodes, "perft({}) of {}", depth, fen);
+    }
+}
+
+#[test]
+fn perft_start_position() {
+    check(STARTING_FEN, &[20, 400, 8902]);
+}
+
+#[test]
+fn perft_kiwipete() {
+    check(KIWIPETE, &[48, 2039, 97862]);
+}
+
+#[test]
+fn perft_position_3() {
+    check(POSITION_3, &[14, 191, 2812, 43238]);
+}
+
+#[test]
+fn perft_position_4() {
+    check(POSITION_4, &[6, 264, 9467]);
+    check(POSITION_4_MIRRORED, &[6, 264, 9467]);
+}
+
+#[test]
+fn perft_position_5() {
+    check(POSITION_5, &[44, 1486, 62379]);
+}
+
+#[test]
+fn perft_position_6() {
+    check(POSITION_6, &[46, 2079, 89890]);
+}
+
+#[test]
+fn divide_sums_to_perft() {
+    let state = GameState::from_fen(KIWIPETE).unwrap();
+    let split = state.divide(2);
+    assert_eq!(split.len(), 48);
+    assert_eq!(split.iter().map(|(_, n)| n).sum::<u64>(), 2039);
+}
+
+#[test]
+#[ignore]
+fn perft_start_position_deep() {
+    let state = GameState::new();
+    assert_eq!(state.perft(4), 197_281);
+    assert_eq!(state.perft(5), 4_865_609);
+}
+
+#[test]
+#[ignore]
+fn perft_kiwipete_deep() {
+    let state = GameState::from_fen(KIWIPETE).unwrap();
+    assert_eq!(state.perft(4), 4_085_603);
+}
+
+#[test]
+#[ignore]
+fn perft_position_3_deep() {
+    let state = GameState::from_fen(POSITION_3).unwrap();
+    assert_eq!(state.perft(5), 674_624);
+}
+
+#[test]
+#[ignore]
+fn perft_position_4_deep() {
+    let state = GameState::from_fen(POSITION_4).unwrap();
+    assert_eq!(state.perft(4), 422_333);
+}
+
+#[test]
+#[ignore]
+fn perft_position_5_deep() {
+    let state = GameState::from_fen(POSITION_5).unwrap();
+    assert_eq!(state.perft(4), 2_103_487);
+}
+
+#[test]
+#[ignore]
+fn perft_position_6_deep() {
+    let state = GameState::from_fen(POSITION_6).unwrap();
+    assert_eq!(state.perft(4), 3_894_594);
+}
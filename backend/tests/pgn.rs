@@ -0,0 +1,150 @@
+use chess::game::{Color, FenError, GameState, Move, Outcome, Termination};
+use chess::pgn::{read_pgn, write_pgn, PgnError, PgnErrorKind, PgnHeaders};
+
+/// The UCI moves of a game read from PGN
+fn ucis(text: &str) -> Vec<String> {
+    let games = read_pgn(text).unwrap();
+    assert_eq!(games.len(), 1);
+    games[0].state.history().iter().map(Move::to_uci).collect()
+}
+
+fn error(text: &str) -> PgnError {
+    read_pgn(text).unwrap_err()
+}
+
+/// The position after the moves of a PGN game, under a known game code
+fn played(text: &str) -> GameState {
+    let mut state = read_pgn(text).unwrap().remove(0).state;
+    state.set_game_code("123456".to_string());
+    state
+}
+
+#[test]
+fn skips_comments_nags_and_variations() {
+    let text = "\
+[Event \"Test \\\"quoted\\\"\"]
+[White \"Alice\"]
+% an escape line, ignored
+1. e4 {best by test} e5 $1 2. Nf3!? ; rest of the line is a comment 2... Nf6
+2... Nc6 (2... Nf6 3. Nxe5 (3. d4 exd4 (3... Nxe4) 4. e5) d6) 3. Bb5 $14 a6 *
+";
+    let games = read_pgn(text).unwrap();
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].tag("Event"), Some("Test \"quoted\""));
+    assert_eq!(games[0].tag("White"), Some("Alice"));
+    assert_eq!(games[0].tag("Black"), None);
+    assert_eq!(games[0].result.as_deref(), Some("*"));
+    let moves: Vec<String> = games[0].state.history().iter().map(Move::to_uci).collect();
+    assert_eq!(moves, ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6"]);
+}
+
+#[test]
+fn reads_several_games() {
+    let text = "\
+[Event \"One\"]
+
+1. e4 e5 1-0
+
+[Event \"Two\"]
+[SetUp \"1\"]
+[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]
+
+1. e4 Kd7 1/2-1/2
+[Event \"Three\"]
+1. d4
+[Event \"Four\"]
+1.c4 e5 2.Nc3";
+    let games = read_pgn(text).unwrap();
+    let events: Vec<_> = games.iter().map(|game| game.tag("Event").unwrap()).collect();
+    assert_eq!(events, ["One", "Two", "Three", "Four"]);
+    let results: Vec<_> = games.iter().map(|game| game.result.as_deref()).collect();
+    assert_eq!(results, [Some("1-0"), Some("1/2-1/2"), None, None]);
+    assert_eq!(games[1].state.to_fen(), "8/3k4/8/8/4P3/8/8/4K3 w - - 1 2");
+    assert_eq!(games[3].state.history().len(), 3);
+    // move numbers glued to their moves are read like spaced ones
+    assert_eq!(ucis("1.e4 e5 2.Nf3"), ["e2e4", "e7e5", "g1f3"]);
+}
+
+#[test]
+fn replays_past_an_automatic_outcome() {
+    // the fifth repetition ends the game, but the record goes on
+    let shuffle = "Nf3 Nf6 Ng1 Ng8 ".repeat(4);
+    let games = read_pgn(&format!("{} e4 e5 *", shuffle)).unwrap();
+    assert_eq!(games[0].state.history().len(), 18);
+    assert_eq!(games[0].state.outcome(), Some(Outcome::draw(Termination::FivefoldRepetition)));
+
+    // taking the last pawn leaves too little to mate; the players play on regardless
+    let text = "[FEN \"4k3/8/8/8/8/8/5p2/4K1N1 w - - 0 1\"]\n1. Kxf2 Kd7 2. Nf3 Ke6 *";
+    let games = read_pgn(text).unwrap();
+    assert_eq!(games[0].state.history().len(), 4);
+    assert_eq!(games[0].state.outcome(), Some(Outcome::draw(Termination::InsufficientMaterial)));
+}
+
+#[test]
+fn errors_name_the_game_and_ply() {
+    let two_games = "[Event \"One\"]\n1. e4 e5 *\n\n[Event \"Two\"]\n1. d4 d5 2. Ke3 *";
+    assert_eq!(error(two_games), PgnError { game: 2, ply: Some(3), kind: PgnErrorKind::IllegalMove("Ke3".to_string()) });
+    assert_eq!(error("1. e4 e5 2. Nf3 Nc6 3. Zz9").kind, PgnErrorKind::MalformedMove("Zz9".to_string()));
+    assert_eq!(error("1. e4 e5 2. Nf3 Nc6 3. Zz9").ply, Some(5));
+    let ambiguous = "[FEN \"4k3/8/8/8/8/8/4K3/R6R w - - 0 1\"]\n1. Rd1";
+    assert_eq!(error(ambiguous), PgnError { game: 1, ply: Some(1), kind: PgnErrorKind::AmbiguousMove("Rd1".to_string()) });
+
+    assert_eq!(error("1. e4 {never closed"), PgnError { game: 1, ply: None, kind: PgnErrorKind::Syntax("line 1: unterminated comment".to_string()) });
+    assert_eq!(error("1. e4 e5 *\n1. d4 ) d5").game, 2);
+    assert!(matches!(error("1. e4 e5 *\n1. d4 ) d5").kind, PgnErrorKind::Syntax(_)));
+    assert!(matches!(error("[Event Casual]").kind, PgnErrorKind::Syntax(_)));
+    assert_eq!(error("[FEN \"8/8/8 w - - 0 1\"]\n1. e4").kind, PgnErrorKind::Fen(FenError::Board("expected 8 ranks, found 3".to_string())));
+    assert_eq!(error("1. e4 e5 *\n\n[Event \"Two\"]\n1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Bxc6 bxc6 5. Bb5").to_string(), "game 2, ply 9: illegal move 'Bb5'");
+}
+
+#[test]
+fn writes_headers_movetext_and_result() {
+    let headers = PgnHeaders {
+        date: "2024.03.09".to_string(),
+        white: "Ann \"the Rook\"".to_string(),
+        black: "Computer".to_string(),
+        ..PgnHeaders::default()
+    };
+    assert_eq!(write_pgn(&played("1. f3 e5 2. g4 Qh4#"), &headers), "\
+[Event \"Casual game\"]
+[Site \"Rusty Chess\"]
+[Date \"2024.03.09\"]
+[Round \"-\"]
+[White \"Ann \\\"the Rook\\\"\"]
+[Black \"Computer\"]
+[Result \"0-1\"]
+[GameCode \"123456\"]
+
+1. f3 e5 2. g4 Qh4# 0-1
+");
+
+    // a game set up from a position, black to move and still going
+    let from_fen = played("[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 1\"]\n1... Kd7 2. e4 *");
+    assert_eq!(write_pgn(&from_fen, &PgnHeaders::default()), "\
+[Event \"Casual game\"]
+[Site \"Rusty Chess\"]
+[Date \"????.??.??\"]
+[Round \"-\"]
+[White \"?\"]
+[Black \"?\"]
+[Result \"*\"]
+[GameCode \"123456\"]
+[SetUp \"1\"]
+[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 1\"]
+
+1... Kd7 2. e4 *
+");
+
+    // a recorded outcome gives the result, and long movetext wraps at 80 columns
+    let mut resigned = played(&"Nf3 Nf6 Ng1 Ng8 ".repeat(2));
+    resigned.set_outcome(Outcome::win(Color::White, Termination::Resignation));
+    let text = write_pgn(&resigned, &PgnHeaders::default());
+    assert!(text.contains("[Result \"1-0\"]"));
+    let movetext: Vec<&str> = text.split("\n\n").nth(1).unwrap().lines().collect();
+    assert_eq!(movetext, ["1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 1-0"]);
+    let long = write_pgn(&played(&"Nf3 Nf6 Ng1 Ng8 ".repeat(4)), &PgnHeaders::default());
+    let lines: Vec<&str> = long.split("\n\n").nth(1).unwrap().lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines.iter().all(|line| line.len() <= 80), "{:?}", lines);
+    assert!(lines[1].ends_with("1/2-1/2"));
+}
@@ -0,0 +1,125 @@
+use chess::clock::TimeControl;
+use chess::game::{GameState, Move};
+use chess::protocol::{ClientMessage, CloseReason, ColorChoice, MoveList, MoveRecord, Promotion, ProtocolError, Role, ServerMessage, StateUpdate};
+use serde_json::{json, Value};
+
+#[test]
+fn parses_client_messages() {
+    assert_eq!(ClientMessage::parse(r#"{"instruction_type":"rematch"}"#), Ok(ClientMessage::Rematch));
+    assert_eq!(ClientMessage::parse(r#"{"instruction_type":"claim_draw"}"#), Ok(ClientMessage::ClaimDraw));
+    assert_eq!(
+        ClientMessage::parse(r#"{"instruction_type":"request_move","destination":60,"promotion":"knight"}"#),
+        Ok(ClientMessage::RequestMove { uci: None, san: None, destination: Some(60), promotion: Some(Promotion::Knight) })
+    );
+    assert_eq!(
+        ClientMessage::parse(r#"{"instruction_type":"play_vs_computer","color":"black","depth":3}"#),
+        Ok(ClientMessage::PlayVsComputer { color: Some(ColorChoice::Black), engine: None, depth: Some(3), movetime_ms: None, time_control: None })
+    );
+    assert_eq!(
+        ClientMessage::parse(r#"{"instruction_type":"create_private_game","time_control":{"type":"fischer","base_ms":180000,"increment_ms":2000}}"#),
+        Ok(ClientMessage::CreatePrivateGame { time_control: Some(TimeControl::Fischer { base_ms: 180_000, increment_ms: 2000 }) })
+    );
+    assert_eq!(ClientMessage::parse(r#"{"instruction_type":"create_private_game"}"#), Ok(ClientMessage::CreatePrivateGame { time_control: None }));
+    assert_eq!(ClientMessage::parse(r#"{"instruction_type":"find_game"}"#), Ok(ClientMessage::FindGame { time_control: None }));
+    assert_eq!(ClientMessage::parse(r#"{"instruction_type":"cancel_search"}"#), Ok(ClientMessage::CancelSearch));
+    assert_eq!(
+        ClientMessage::parse(r#"{"instruction_type":"join_game","code":"123456"}"#),
+        Ok(ClientMessage::JoinGame { code: "123456".to_string(), spectate: false })
+    );
+    assert_eq!(
+        ClientMessage::parse(r#"{"instruction_type":"resume","token":"9f2c"}"#),
+        Ok(ClientMessage::Resume { token: "9f2c".to_string() })
+    );
+}
+
+#[test]
+fn rejections_carry_codes() {
+    let malformed = ClientMessage::parse("not json").unwrap_err();
+    assert_eq!(malformed.code(), "malformed_message");
+    assert_eq!(malformed.request(), None);
+    assert_eq!(ClientMessage::parse(r#"{"square_clicked":3}"#).unwrap_err().code(), "malformed_message");
+
+    let unknown = ClientMessage::parse(r#"{"instruction_type":"castle_queenside"}"#).unwrap_err();
+    assert_eq!(unknown, ProtocolError::UnknownInstruction("castle_queenside".to_string()));
+    assert_eq!(unknown.code(), "unknown_instruction");
+
+    // a bad field value is a bad request, even though serde also calls it an unknown variant
+    let bad = ClientMessage::parse(r#"{"instruction_type":"request_move","destination":60,"promotion":"king"}"#).unwrap_err();
+    assert_eq!(bad.code(), "bad_request");
+    assert_eq!(bad.request(), Some("request_move"));
+    let missing = ClientMessage::parse(r#"{"instruction_type":"join_game"}"#).unwrap_err();
+    assert_eq!(missing.code(), "bad_request");
+}
+
+#[test]
+fn server_messages_are_tagged() {
+    let assign: Value = serde_json::from_str(&ServerMessage::AssignColor { color: Role::Observer, resume_token: None }.to_text()).unwrap();
+    assert_eq!(assign, json!({ "instruction_type": "assign_color", "color": "observer", "resume_token": null }));
+
+    let error: Value = serde_json::from_str(&ServerMessage::error(Some("join_game"), "game_full", "full").to_text()).unwrap();
+    assert_eq!(error, json!({ "instruction_type": "error", "request": "join_game", "code": "game_full", "message": "full" }));
+
+    let closed: Value = serde_json::from_str(&ServerMessage::RoomClosed { reason: CloseReason::PlayersLeft }.to_text()).unwrap();
+    assert_eq!(closed, json!({ "instruction_type": "room_closed", "reason": "players_left" }));
+
+    let registered = ServerMessage::NameRegistered { name: "alice".to_string(), token: "abc".to_string() };
+    assert_eq!(serde_json::from_str::<Value>(&registered.to_text()).unwrap(), json!({ "instruction_type": "name_registered", "name": "alice", "token": "abc" }));
+
+    // the state message keeps the game's fields at the top level
+    let state: Value = serde_json::from_str(&ServerMessage::State(Box::new(StateUpdate::new(&GameState::new(), Vec::new(), 7, 2))).to_text()).unwrap();
+    assert_eq!(state["instruction_type"], "state");
+    assert_eq!(state["board"].as_array().map(Vec::len), Some(64));
+    assert_eq!(state["spectators"], 2);
+    assert_eq!(state["seq"], 7);
+    assert_eq!(state["last_move"], Value::Null);
+    assert_eq!(state["moves"], json!([]));
+    assert_eq!(state["outcome"], Value::Null);
+    assert_eq!(state["claimable_draw"], Value::Null);
+    // the moves go out once, described
+    assert!(state.get("history").is_none());
+    assert_eq!(state["white_rating"], Value::Null);
+}
+
+fn play(state: &mut GameState, moves: &[&str]) {
+    for uci in moves {
+        let mv = Move::from_uci(state, uci).unwrap();
+        state.apply_move(&mv);
+    }
+}
+
+#[test]
+fn move_records_describe_each_move() {
+    let mut state = GameState::from_fen("r3k3/1P6/8/3pP3/8/8/8/R3K2R w KQq d6 0 1").unwrap();
+    play(&mut state, &["e5d6", "a8a7", "e1g1", "a7b7", "a1a8"]);
+    let update = StateUpdate::new(&state, MoveRecord::game_moves(&state), 1, 0);
+    let sans: Vec<&str> = update.moves.iter().map(|record| record.san.as_str()).collect();
+    assert_eq!(sans, ["exd6", "Ra7", "O-O", "Rxb7", "Ra8+"]);
+    let en_passant = &update.moves[0];
+    assert!(en_passant.capture && en_passant.en_passant && !en_passant.castle);
+    assert!(update.moves[2].castle && !update.moves[2].capture);
+    assert!(update.moves[3].capture);
+    assert_eq!(update.last_move, update.moves.last().cloned());
+
+    let mut promoting = GameState::from_fen("r3k3/1P6/8/8/8/8/8/4K3 w q - 0 1").unwrap();
+    play(&mut promoting, &["b7a8n"]);
+    let records = MoveRecord::game_moves(&promoting);
+    assert_eq!(records[0].promotion, Some(Promotion::Knight));
+    assert_eq!((records[0].from, records[0].to, records[0].uci.as_str()), (49, 56, "b7a8n"));
+    assert!(records[0].capture);
+}
+
+#[test]
+fn move_lists_follow_moves_and_takebacks() {
+    let mut state = GameState::new();
+    let mut moves = MoveList::default();
+    play(&mut state, &["e2e4", "e7e5"]);
+    assert_eq!(moves.update(&state).len(), 2);
+    play(&mut state, &["g1f3"]);
+    state.take_back();
+    state.take_back();
+    play(&mut state, &["e7e6", "d2d4", "d7d5", "e4d5"]);
+    let sans: Vec<String> = moves.update(&state).iter().map(|record| record.san.clone()).collect();
+    assert_eq!(sans, ["e4", "e6", "d4", "d5", "exd5"]);
+    assert_eq!(moves.update(&state), MoveRecord::game_moves(&state));
+    assert!(moves.update(&GameState::new()).is_empty());
+}
@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use chess::clock::TimeControl;
+use chess::game::{Color, Outcome, Termination};
+use chess::rating::{rate_game, Category, Rating, DEFAULT_DEVIATION, DEFAULT_RATING};
+use chess::storage::{GameRecord, GameStore, MemoryStore, PlayerRecord, SqliteStore};
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+const NOW: u64 = 1_700_000_000_000;
+
+fn fischer(base_secs: u64, increment_secs: u64) -> Option<TimeControl> {
+    Some(TimeControl::Fischer { base_ms: base_secs * 1000, increment_ms: increment_secs * 1000 })
+}
+
+fn player(name: &str) -> PlayerRecord {
+    PlayerRecord { name: Some(name.to_string()), computer: false, resume_token: None }
+}
+
+fn finished_game(id: u64, white: PlayerRecord, black: PlayerRecord, time_control: Option<TimeControl>, outcome: Outcome) -> GameRecord {
+    GameRecord {
+        id: Some(id),
+        game_code: format!("{:06}", id),
+        white,
+        black,
+        time_control,
+        private: false,
+        start_fen: None,
+        moves: Vec::new(),
+        outcome: Some(outcome),
+        clock_ms: None,
+        started_at: NOW - 60_000,
+        updated_at: NOW,
+        ended_at: Some(NOW),
+    }
+}
+
+#[test]
+fn time_controls_fall_into_categories() {
+    assert_eq!(Category::of(None), None);
+    assert_eq!(Category::of(fischer(60, 0)), Some(Category::Bullet));
+    assert_eq!(Category::of(fischer(120, 1)), Some(Category::Bullet));
+    // 3+2 lasts 260 seconds over 40 moves
+    assert_eq!(Category::of(fischer(180, 2)), Some(Category::Blitz));
+    assert_eq!(Category::of(fischer(600, 0)), Some(Category::Rapid));
+    assert_eq!(Category::of(Some(TimeControl::Bronstein { base_ms: 900_000, delay_ms: 10_000 })), Some(Category::Rapid));
+    assert_eq!(Category::of(fischer(1800, 0)), Some(Category::Classical));
+    assert_eq!(Category::of(Some(TimeControl::Correspondence { days_per_move: 3 })), Some(Category::Correspondence));
+    // a huge increment makes a long game rather than overflowing
+    assert_eq!(Category::of(Some(TimeControl::Fischer { base_ms: u64::MAX, increment_ms: u64::MAX })), Some(Category::Classical));
+    assert_eq!(Category::of(Some(TimeControl::Bronstein { base_ms: 1000, delay_ms: u64::MAX / 8 })), Some(Category::Classical));
+}
+
+#[test]
+fn games_move_ratings_the_glicko2_way() {
+    let new = Rating::default();
+    assert!(new.provisional());
+
+    // two new players: the winner gains what the loser drops, and both grow more certain
+    let won = new.update(&new, 1.0, NOW);
+    let lost = new.update(&new, 0.0, NOW);
+    assert!((won.rating - 1662.3).abs() < 0.5, "{:?}", won);
+    assert!((won.deviation - 290.3).abs() < 0.5, "{:?}", won);
+    assert!((won.rating - DEFAULT_RATING + lost.rating - DEFAULT_RATING).abs() < 1e-6);
+    assert_eq!((won.games, won.last_played_at), (1, Some(NOW)));
+
+    // beating a far stronger player gains more than beating an equal, and a draw with an
+    // equal moves nothing
+    let strong = Rating { rating: 1900.0, deviation: 60.0, ..Rating::default() };
+    assert!(new.update(&strong, 1.0, NOW).rating > won.rating);
+    assert!((new.update(&new, 0.5, NOW).rating - DEFAULT_RATING).abs() < 1e-6);
+
+    // a rating settles after enough games
+    let mut rating = Rating::default();
+    for i in 0..30 {
+        rating = rating.update(&Rating::default(), if i % 2 == 0 { 1.0 } else { 0.0 }, NOW);
+    }
+    assert!(!rating.provisional(), "{:?}", rating);
+    assert_eq!(rating.games, 30);
+}
+
+#[test]
+fn deviation_grows_while_a_player_is_away() {
+    assert_eq!(Rating::default().decayed(NOW + 1000 * DAY_MS), Rating::default());
+    let settled = Rating { deviation: 60.0, games: 40, last_played_at: Some(NOW), ..Rating::default() };
+    assert_eq!(settled.decayed(NOW), settled);
+    let month = settled.decayed(NOW + 30 * DAY_MS);
+    assert!(month.deviation > settled.deviation && month.deviation < DEFAULT_DEVIATION, "{:?}", month);
+    assert_eq!((month.rating, month.games), (settled.rating, settled.games));
+    assert_eq!(settled.decayed(NOW + 100_000 * DAY_MS).deviation, DEFAULT_DEVIATION);
+}
+
+#[test]
+fn rated_games_update_both_players() {
+    let store = MemoryStore::new();
+    let win = Outcome::win(Color::White, Termination::Resignation);
+    let game = finished_game(1, player("alice"), player("bob"), fischer(180, 2), win);
+    let [white, black] = rate_game(&store, &game, NOW).unwrap().unwrap();
+    assert_eq!((white.player.as_str(), white.color, white.category), ("alice", Color::White, Category::Blitz));
+    assert_eq!((black.player.as_str(), black.color, black.game_code.as_str()), ("bob", Color::Black, "000001"));
+    assert!(white.after.rating > white.before.rating && black.after.rating < black.before.rating);
+    assert_eq!(store.rating("alice", Category::Blitz).unwrap(), Some(white.after));
+    assert_eq!(store.rating("alice", Category::Rapid).unwrap(), None);
+    assert_eq!(store.rating_history("bob", None, 10).unwrap(), [black]);
+
+    // the next game starts from the stored ratings
+    let draw = finished_game(2, player("bob"), player("alice"), fischer(180, 2), Outcome::draw(Termination::Agreement));
+    let [bob, alice] = rate_game(&store, &draw, NOW + DAY_MS).unwrap().unwrap();
+    assert_eq!(alice.before.rating, white.after.rating);
+    assert!(alice.before.deviation > white.after.deviation);
+    assert_eq!(bob.after.games, 2);
+    let history = store.rating_history("alice", Some(Category::Blitz), 10).unwrap();
+    assert_eq!(history.iter().map(|change| change.game_id).collect::<Vec<_>>(), [2, 1]);
+
+    // untimed games, the computer and playing oneself are not rated
+    let computer = PlayerRecord { name: Some("engine".to_string()), computer: true, resume_token: None };
+    for unrated in [
+        finished_game(3, player("alice"), player("bob"), None, win),
+        finished_game(4, player("alice"), computer, fischer(180, 2), win),
+        finished_game(5, player("alice"), PlayerRecord::default(), fischer(180, 2), win),
+        finished_game(6, player("alice"), player("alice"), fischer(180, 2), win),
+    ] {
+        assert_eq!(rate_game(&store, &unrated, NOW).unwrap(), None);
+    }
+    assert_eq!(store.rating_history("alice", None, 10).unwrap().len(), 2);
+}
+
+#[test]
+fn games_ending_together_are_all_rated() {
+    let stores: [Arc<dyn GameStore>; 2] = [Arc::new(MemoryStore::new()), Arc::new(SqliteStore::open_in_memory().unwrap())];
+    for store in stores {
+        let games: Vec<GameRecord> = (1..=8).map(|id| {
+            let win = Outcome::win(Color::White, Termination::Resignation);
+            finished_game(id, player("alice"), player(&format!("opponent{}", id)), fischer(180, 2), win)
+        }).collect();
+        for game in &games {
+            store.save(&GameRecord { id: None, ..game.clone() }).unwrap();
+        }
+        // every game's room rates it from its own writer at the same moment
+        let threads: Vec<_> = games.into_iter().map(|game| {
+            let store = store.clone();
+            std::thread::spawn(move || rate_game(&*store, &game, NOW).unwrap().unwrap())
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(store.rating("alice", Category::Blitz).unwrap().unwrap().games, 8);
+        let history = store.rating_history("alice", None, 10).unwrap();
+        assert_eq!(history.len(), 8);
+        // each game started from the rating the one before it left
+        for pair in history.windows(2) {
+            assert_eq!(pair[0].before.rating, pair[1].after.rating);
+        }
+    }
+}
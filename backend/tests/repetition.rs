@@ -0,0 +1,101 @@
+use chess::game::{Color, GameResult, GameState, Move, MoveError, Outcome, Termination};
+
+fn play(state: &mut GameState, moves: &[&str]) {
+    for san in moves {
+        let mv = Move::from_san(state, san).unwrap();
+        state.apply_move(&mv);
+    }
+}
+
+#[test]
+fn knight_shuffle_repeats_three_times() {
+    let mut state = GameState::new();
+    let shuffle = ["Nf3", "Nf6", "Ng1", "Ng8"];
+    play(&mut state, &shuffle);
+    assert_eq!(state.repetition_count(), 2);
+    assert!(!state.is_threefold_repetition());
+    play(&mut state, &shuffle);
+    assert_eq!(state.repetition_count(), 3);
+    assert!(state.is_threefold_repetition());
+    // taking a move back forgets the third occurrence
+    state.take_back();
+    assert!(!state.is_threefold_repetition());
+}
+
+#[test]
+fn irreversible_move_resets_repetitions() {
+    let mut state = GameState::new();
+    play(&mut state, &["Nf3", "Nf6", "Ng1", "Ng8", "e4", "e5"]);
+    assert_eq!(state.repetition_count(), 1);
+    play(&mut state, &["Nf3", "Nf6", "Ng1", "Ng8"]);
+    assert_eq!(state.repetition_count(), 2);
+}
+
+#[test]
+fn en_passant_counts_only_when_capturable() {
+    // after 1.e4 no black pawn can take en passant, so the target square is irrelevant
+    let mut state = GameState::new();
+    play(&mut state, &["e4"]);
+    let without_target = GameState::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+    assert_eq!(state.hash(), without_target.hash());
+
+    // here the d-pawn can take on e3, so the target is part of the position
+    let capturable = GameState::from_fen("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1").unwrap();
+    let not_capturable = GameState::from_fen("4k3/8/8/8/3pP3/8/8/4K3 b - - 0 1").unwrap();
+    assert_ne!(capturable.hash(), not_capturable.hash());
+
+    // an en passant capture that would expose the king is not possible either
+    let pinned = GameState::from_fen("8/8/8/8/k2pP2R/8/8/4K3 b - e3 0 1").unwrap();
+    let pinned_no_target = GameState::from_fen("8/8/8/8/k2pP2R/8/8/4K3 b - - 0 1").unwrap();
+    assert_eq!(pinned.hash(), pinned_no_target.hash());
+}
+
+#[test]
+fn threefold_is_claimed_but_fivefold_ends_the_game() {
+    let mut state = GameState::new();
+    let shuffle = ["Nf3", "Nf6", "Ng1", "Ng8"];
+    play(&mut state, &shuffle);
+    play(&mut state, &shuffle);
+    assert_eq!(state.claimable_draw(), Some(Termination::ThreefoldRepetition));
+    assert_eq!(state.automatic_outcome(), None);
+    play(&mut state, &shuffle);
+    assert!(!state.is_fivefold_repetition());
+    play(&mut state, &shuffle);
+    assert!(state.is_fivefold_repetition());
+    assert_eq!(state.automatic_outcome(), Some(Outcome::draw(Termination::FivefoldRepetition)));
+}
+
+#[test]
+fn fifty_moves_are_claimed_but_seventy_five_end_the_game() {
+    let fifty = GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 100 80").unwrap();
+    assert_eq!(fifty.claimable_draw(), Some(Termination::FiftyMoveRule));
+    assert_eq!(fifty.automatic_outcome(), None);
+    let seventy_five = GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 150 100").unwrap();
+    assert_eq!(seventy_five.automatic_outcome(), Some(Outcome::draw(Termination::SeventyFiveMoveRule)));
+    // mate on the last move of the 75 still wins
+    let mut mating = GameState::from_fen("4k3/R7/8/8/8/8/8/4K2R w K - 149 100").unwrap();
+    play(&mut mating, &["Rh8#"]);
+    assert_eq!(mating.automatic_outcome(), Some(Outcome::win(Color::White, Termination::Checkmate)));
+}
+
+#[test]
+fn first_outcome_stands() {
+    let mut state = GameState::new();
+    assert!(state.set_outcome(Outcome::win(Color::Black, Termination::Resignation)));
+    assert!(!state.set_outcome(Outcome::draw(Termination::Agreement)));
+    assert_eq!(state.outcome().map(|outcome| outcome.result), Some(GameResult::BlackWins));
+}
+
+#[test]
+fn no_moves_after_the_game_ends() {
+    let mut state = GameState::new();
+    play(&mut state, &["f3", "e5", "g4", "Qh4#"]);
+    assert_eq!(state.outcome(), Some(Outcome::win(Color::Black, Termination::Checkmate)));
+    assert_eq!(state.validate_move(12, 28, None), Err(MoveError::GameOver));
+    state.move_piece(12, 28, None);
+    assert_eq!(state.history().len(), 4);
+
+    let mut resigned = GameState::new();
+    resigned.set_outcome(Outcome::win(Color::White, Termination::Resignation));
+    assert_eq!(Move::from_uci(&resigned, "e7e5"), Err(MoveError::GameOver));
+}
@@ -0,0 +1,83 @@
+use chess::game::{Color, GameState, Move, MoveError, Outcome, SanError, Termination};
+
+/// SAN of a move given in SAN, as rendered back from the position
+fn san(fen: &str, input: &str) -> String {
+    let state = GameState::from_fen(fen).unwrap();
+    Move::from_san(&state, input).unwrap().to_san(&state)
+}
+
+#[test]
+fn suffix_holds_in_a_finished_game() {
+    // the game was resigned in a position where the move would still mate
+    let mut state = GameState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+    state.set_outcome(Outcome::win(Color::Black, Termination::Resignation));
+    let mate = Move::from_san(&state, "Ra8").unwrap();
+    assert_eq!(mate.to_san(&state), "Ra8#");
+    assert_eq!(san("6k1/5pp1/8/8/8/8/8/R5K1 w - - 0 1", "Ra8"), "Ra8+");
+}
+
+#[test]
+fn disambiguates_by_file_rank_or_both() {
+    // knights on b1 and f1 both reach d2
+    assert_eq!(san("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "Nbd2"), "Nbd2");
+    // rooks on a1 and a5 both reach a3
+    assert_eq!(san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "R1a3"), "R1a3");
+    assert_eq!(san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "R5a3"), "R5a3");
+    // queens on a1, a3 and c1 all reach b2; the one on a1 shares a file and a rank
+    assert_eq!(san("4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1", "Qa1b2"), "Qa1b2");
+    assert_eq!(san("4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1", "Qcb2"), "Qcb2");
+    // a piece with no rival needs no disambiguation, even when it is given
+    assert_eq!(san("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1", "Nbd2"), "Nd2");
+}
+
+#[test]
+fn renders_promotions_castling_and_checks() {
+    assert_eq!(san("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a8=Q"), "a8=Q+");
+    assert_eq!(san("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a8=N"), "a8=N");
+    assert_eq!(san("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "axb8=R"), "axb8=R+");
+
+    let castles = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+    assert_eq!(san(castles, "O-O"), "O-O");
+    assert_eq!(san(castles, "0-0-0"), "O-O-O");
+    assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "O-O-O"), "O-O-O");
+
+    assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "Ra8"), "Ra8#");
+    assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "Ra8+"), "Ra8#");
+    assert_eq!(san("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "Nf3"), "Nf3");
+}
+
+#[test]
+fn rejects_ambiguous_illegal_and_malformed_san() {
+    let knights = GameState::from_fen("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1").unwrap();
+    assert_eq!(Move::from_san(&knights, "Nd2"), Err(SanError::Ambiguous));
+    assert_eq!(Move::from_san(&knights, "Nd3"), Err(SanError::Illegal));
+    assert_eq!(Move::from_san(&knights, "O-O"), Err(SanError::Illegal));
+    assert_eq!(Move::from_san(&GameState::new(), "e5"), Err(SanError::Illegal));
+    // a pawn reaching the last rank must say what it becomes
+    assert!(Move::from_san(&GameState::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap(), "a8").is_err());
+    for malformed in ["", "Zz9", "e9", "Nd"] {
+        assert_eq!(Move::from_san(&knights, malformed), Err(SanError::Syntax), "{:?}", malformed);
+    }
+}
+
+#[test]
+fn uci_round_trips() {
+    let start = GameState::new();
+    for uci in ["e2e4", "g1f3", "b1a3"] {
+        assert_eq!(Move::from_uci(&start, uci).unwrap().to_uci(), uci);
+    }
+    let promoting = GameState::from_fen("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    for uci in ["a7a8q", "a7a8n", "a7b8r", "a7b8b"] {
+        let mv = Move::from_uci(&promoting, uci).unwrap();
+        assert_eq!(mv.to_uci(), uci);
+        // the same move in SAN comes back to the same UCI
+        assert_eq!(Move::from_san(&promoting, &mv.to_san(&promoting)).unwrap().to_uci(), uci);
+    }
+    assert_eq!(Move::from_uci(&GameState::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap(), "e1g1").unwrap().to_uci(), "e1g1");
+
+    assert_eq!(Move::from_uci(&start, "e2e9"), Err(MoveError::BadNotation));
+    assert_eq!(Move::from_uci(&start, "e2e4x"), Err(MoveError::BadNotation));
+    assert_eq!(Move::from_uci(&start, "e2"), Err(MoveError::BadNotation));
+    assert_eq!(Move::from_uci(&start, "e2e5"), Err(MoveError::IllegalMove));
+    assert_eq!(Move::from_uci(&start, "e2e4q"), Err(MoveError::InvalidPromotion));
+}
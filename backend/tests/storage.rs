@@ -0,0 +1,203 @@
+use chess::clock::TimeControl;
+use chess::game::{Color, GameState, Move, Outcome, Termination};
+use chess::rating::{Category, Rating, RatingChange};
+use chess::storage::{GameFilter, GameRecord, GameStore, MemoryStore, PlayerRecord, ResultFilter, SqliteStore};
+
+fn record(game_code: &str, moves: &[&str]) -> GameRecord {
+    GameRecord {
+        id: None,
+        game_code: game_code.to_string(),
+        white: PlayerRecord { name: Some("alice".to_string()), computer: false, resume_token: Some("a1b2".to_string()) },
+        black: PlayerRecord { name: None, computer: true, resume_token: None },
+        time_control: Some(TimeControl::Fischer { base_ms: 180_000, increment_ms: 2000 }),
+        private: true,
+        start_fen: None,
+        moves: moves.iter().map(|uci| uci.to_string()).collect(),
+        outcome: None,
+        clock_ms: Some((175_000, 179_500)),
+        started_at: 1_700_000_000_000,
+        updated_at: 1_700_000_060_000,
+        ended_at: None,
+    }
+}
+
+/// The same checks run against every backend
+fn saves_and_loads(store: &dyn GameStore) {
+    let game = record("123456", &["e2e4", "e7e5"]);
+    let id = store.save(&game).unwrap();
+    let loaded = store.load("123456").unwrap().unwrap();
+    assert_eq!(loaded, GameRecord { id: Some(id), ..game.clone() });
+    assert_eq!(store.load("654321").unwrap(), None);
+
+    // saving with the id updates the game in place
+    let mut finished = loaded.clone();
+    finished.moves.push("g1f3".to_string());
+    finished.outcome = Some(Outcome::win(Color::White, Termination::Resignation));
+    finished.ended_at = Some(1_700_000_090_000);
+    assert_eq!(store.save(&finished).unwrap(), id);
+    assert_eq!(store.load("123456").unwrap(), Some(finished.clone()));
+    assert!(store.active().unwrap().is_empty());
+
+    // a game set up from a FEN keeps it
+    let from_fen = GameRecord { id: Some(id), start_fen: Some("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1".to_string()), ..finished };
+    store.save(&from_fen).unwrap();
+    assert_eq!(store.load("123456").unwrap(), Some(from_fen));
+
+    // a later game under the same code is a new game, and the one found by code
+    let rematch = record("123456", &[]);
+    let rematch_id = store.save(&rematch).unwrap();
+    assert_ne!(rematch_id, id);
+    assert_eq!(store.load("123456").unwrap().and_then(|game| game.id), Some(rematch_id));
+    let active = store.active().unwrap();
+    assert_eq!(active.iter().map(|game| game.id).collect::<Vec<_>>(), [Some(rematch_id)]);
+
+    // a game whose room closed before it had a result is over, and not restored
+    let closed = GameRecord { id: Some(rematch_id), ended_at: Some(1_700_000_120_000), ..rematch };
+    store.save(&closed).unwrap();
+    assert!(store.active().unwrap().is_empty());
+    assert_eq!(store.load("123456").unwrap().map(|game| (game.outcome, game.ended_at)), Some((None, closed.ended_at)));
+    let ongoing = GameFilter { result: Some(ResultFilter::Ongoing), ..GameFilter::default() };
+    assert_eq!(store.list(&ongoing).unwrap().total, 0);
+}
+
+/// Lists the codes of the games passing `filter`
+fn codes(store: &dyn GameStore, filter: GameFilter) -> (Vec<String>, u64) {
+    let page = store.list(&filter).unwrap();
+    (page.games.into_iter().map(|game| game.game_code).collect(), page.total)
+}
+
+fn lists_with_filters(store: &dyn GameStore) {
+    let day = 24 * 60 * 60 * 1000;
+    for (i, (code, name, outcome, private)) in [
+        ("000001", "alice", Some(Outcome::win(Color::White, Termination::Checkmate)), false),
+        ("000002", "bob", Some(Outcome::draw(Termination::Agreement)), false),
+        ("000003", "alice", None, true),
+        ("000004", "carol", None, false),
+    ].into_iter().enumerate() {
+        let mut game = record(code, &[]);
+        game.white.name = Some(name.to_string());
+        game.black.name = Some("dave".to_string());
+        game.outcome = outcome;
+        game.private = private;
+        game.started_at = i as u64 * day;
+        store.save(&game).unwrap();
+    }
+    assert_eq!(codes(store, GameFilter::default()), (vec!["000004".into(), "000003".into(), "000002".into(), "000001".into()], 4));
+    let alice = GameFilter { player: Some("alice".to_string()), ..GameFilter::default() };
+    assert_eq!(codes(store, alice.clone()).1, 2);
+    assert_eq!(codes(store, GameFilter { hide_private_ongoing: true, ..alice }), (vec!["000001".into()], 1));
+    assert_eq!(codes(store, GameFilter { player: Some("dave".to_string()), ..GameFilter::default() }).1, 4);
+    assert_eq!(codes(store, GameFilter { result: Some(ResultFilter::Draw), ..GameFilter::default() }).0, ["000002"]);
+    assert_eq!(codes(store, GameFilter { result: Some(ResultFilter::Ongoing), ..GameFilter::default() }).1, 2);
+    let days = GameFilter { started_from: Some(day), started_before: Some(3 * day), ..GameFilter::default() };
+    assert_eq!(codes(store, days).0, ["000003", "000002"]);
+    // pages count from the newest game; the total covers every page
+    assert_eq!(codes(store, GameFilter { offset: 1, limit: 2, ..GameFilter::default() }), (vec!["000003".into(), "000002".into()], 4));
+}
+
+fn keeps_ratings(store: &dyn GameStore) {
+    let change = |player: &str, category, game_id: u64, rating: f64| RatingChange {
+        player: player.to_string(),
+        category,
+        game_id,
+        game_code: format!("{:06}", game_id),
+        color: if game_id.is_multiple_of(2) { Color::White } else { Color::Black },
+        before: Rating::default(),
+        after: Rating { rating, deviation: 290.5, volatility: 0.059_99, games: 1, last_played_at: Some(1_700_000_000_000 + game_id) },
+    };
+    // the history refers to saved games
+    for game_id in 1..=3 {
+        assert_eq!(store.save(&record(&format!("{:06}", game_id), &[])).unwrap(), game_id);
+    }
+    assert_eq!(store.rating("alice", Category::Blitz).unwrap(), None);
+    assert!(store.ratings("alice").unwrap().is_empty());
+    store.record_ratings(&[change("alice", Category::Rapid, 1, 1662.3), change("bob", Category::Rapid, 1, 1337.7)]).unwrap();
+    store.record_ratings(&[change("alice", Category::Blitz, 2, 1550.0), change("carol", Category::Blitz, 2, 1450.0)]).unwrap();
+    store.record_ratings(&[change("alice", Category::Rapid, 3, 1700.0), change("bob", Category::Rapid, 3, 1300.0)]).unwrap();
+
+    // the latest change is the rating; ratings come in category order
+    assert_eq!(store.rating("alice", Category::Rapid).unwrap(), Some(change("alice", Category::Rapid, 3, 1700.0).after));
+    let categories: Vec<Category> = store.ratings("alice").unwrap().into_iter().map(|(category, _)| category).collect();
+    assert_eq!(categories, [Category::Blitz, Category::Rapid]);
+    assert_eq!(store.rating_history("alice", None, 10).unwrap(), [
+        change("alice", Category::Rapid, 3, 1700.0),
+        change("alice", Category::Blitz, 2, 1550.0),
+        change("alice", Category::Rapid, 1, 1662.3),
+    ]);
+    let rapid = store.rating_history("alice", Some(Category::Rapid), 1).unwrap();
+    assert_eq!(rapid, [change("alice", Category::Rapid, 3, 1700.0)]);
+    assert!(store.rating_history("dave", None, 10).unwrap().is_empty());
+}
+
+fn claims_names(store: &dyn GameStore) {
+    assert!(store.claim_name("alice", "secret").unwrap());
+    assert!(store.claim_name("alice", "secret").unwrap());
+    assert!(!store.claim_name("alice", "guess").unwrap());
+    assert!(store.claim_name("bob", "guess").unwrap());
+}
+
+#[test]
+fn memory_store_saves_and_loads() {
+    saves_and_loads(&MemoryStore::new());
+    lists_with_filters(&MemoryStore::new());
+    keeps_ratings(&MemoryStore::new());
+    claims_names(&MemoryStore::new());
+}
+
+#[test]
+fn sqlite_store_saves_and_loads() {
+    saves_and_loads(&SqliteStore::open_in_memory().unwrap());
+    lists_with_filters(&SqliteStore::open_in_memory().unwrap());
+    keeps_ratings(&SqliteStore::open_in_memory().unwrap());
+    claims_names(&SqliteStore::open_in_memory().unwrap());
+}
+
+#[test]
+fn sqlite_store_keeps_games_across_reopening() {
+    let path = std::env::temp_dir().join(format!("chess-storage-test-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let id = SqliteStore::open(&path).unwrap().save(&record("777777", &["d2d4"])).unwrap();
+    let reopened = SqliteStore::open(&path).unwrap();
+    assert_eq!(reopened.active().unwrap().first().and_then(|game| game.id), Some(id));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn records_replay_into_games() {
+    let mut gs = GameState::new();
+    for uci in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+        let mv = Move::from_uci(&gs, uci).unwrap();
+        gs.apply_move(&mv);
+    }
+    let mut saved = record("", &[]);
+    saved.update_from(&gs, 1_700_000_100_000);
+    assert_eq!(saved.moves, ["f2f3", "e7e5", "g2g4", "d8h4"]);
+    assert_eq!(saved.ended_at, Some(1_700_000_100_000));
+
+    let replayed = saved.replay().unwrap();
+    assert_eq!(replayed.game_code(), gs.game_code());
+    assert_eq!(replayed.hash(), gs.hash());
+    assert_eq!(replayed.outcome(), Some(Outcome::win(Color::Black, Termination::Checkmate)));
+
+    // an outcome that is not in the moves, such as a resignation, is restored as saved
+    let resigned = GameRecord { outcome: Some(Outcome::win(Color::White, Termination::Resignation)), ..record("1", &["e2e4"]) };
+    assert_eq!(resigned.replay().unwrap().outcome(), resigned.outcome);
+    assert!(record("1", &["e2e5"]).replay().is_err());
+
+    // a game set up from a FEN replays from it
+    let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+    let from_fen = GameRecord { start_fen: Some(fen.to_string()), ..record("1", &["e1d2", "e8d7"]) };
+    let replayed = from_fen.replay().unwrap();
+    assert_eq!(replayed.start_fen(), Some(fen));
+    assert_eq!(replayed.to_fen(), "8/3k4/8/8/8/8/3KP3/8 w - - 2 2");
+    assert!(GameRecord { start_fen: Some("8/8 w".to_string()), ..record("1", &[]) }.replay().is_err());
+}
+
+#[test]
+fn records_name_the_players_in_pgn() {
+    let headers = record("1", &[]).pgn_headers();
+    assert_eq!((headers.white.as_str(), headers.black.as_str()), ("alice", "Computer"));
+    assert_eq!(headers.date, "2023.11.14");
+    let unnamed = GameRecord { white: PlayerRecord::default(), ..record("1", &[]) };
+    assert_eq!(unnamed.pgn_headers().white, "?");
+}
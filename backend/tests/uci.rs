@@ -0,0 +1,53 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+/// Runs the UCI binary over `input` and returns everything it printed
+fn run(input: &str) -> Vec<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chess-uci"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let lines = BufReader::new(child.stdout.take().unwrap()).lines().map(Result::unwrap).collect();
+    child.wait().unwrap();
+    lines
+}
+
+#[test]
+fn handshake() {
+    let out = run("uci\nisready\nquit\n");
+    assert!(out.iter().any(|line| line.starts_with("id name")));
+    assert!(out.contains(&"uciok".to_string()));
+    assert!(out.contains(&"readyok".to_string()));
+}
+
+#[test]
+fn searches_position_with_moves() {
+    // after 1.f3 e5 2.g4 black mates with Qh4
+    let out = run("position startpos moves f2f3 e7e5 g2g4\ngo depth 2\nisready\nquit\n");
+    assert!(out.iter().any(|line| line.starts_with("info depth 1 ")));
+    assert!(out.contains(&"bestmove d8h4".to_string()), "{:?}", out);
+}
+
+#[test]
+fn plays_on_past_fivefold_repetition() {
+    // the knights shuffle back to the start four times, then 1.f3 e5 2.g4 as before
+    let shuffle = "g1f3 g8f6 f3g1 f6g8 ".repeat(4);
+    let out = run(&format!("position startpos moves {}f2f3 e7e5 g2g4\ngo depth 2\nisready\nquit\n", shuffle));
+    assert!(!out.iter().any(|line| line.contains("bad position")), "{:?}", out);
+    assert!(out.contains(&"bestmove d8h4".to_string()), "{:?}", out);
+}
+
+#[test]
+fn searches_fen_position() {
+    let out = run("position fen 6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1\ngo movetime 100\nquit\n");
+    assert!(out.iter().any(|line| line.contains("score mate 1")), "{:?}", out);
+    assert!(out.contains(&"bestmove d1d8".to_string()));
+}
+
+#[test]
+fn stop_ends_infinite_search() {
+    let out = run("position startpos\ngo infinite\nstop\nquit\n");
+    assert_eq!(out.iter().filter(|line| line.starts_with("bestmove ")).count(), 1);
+}